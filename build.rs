@@ -0,0 +1,39 @@
+//! 起動時に実行するユーザープログラムのビルド
+//!
+//! ユーザープログラムはカーネルとは別に、フリースタンディングな静的リンクの
+//! ELF実行ファイルとしてコンパイルしてOUT_DIRに置く。カーネルはそれを
+//! `include_bytes!`で埋め込み、ELFローダーで起動する。
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// ユーザープログラムのターゲット（`rustup target add x86_64-unknown-none`が必要）
+const USER_TARGET: &str = "x86_64-unknown-none";
+/// ユーザープログラムのロード先（カーネルイメージより上）
+const USER_IMAGE_BASE: u64 = 0x400000;
+
+/// (ソースファイル, OUT_DIRに出力するファイル名)
+const USER_PROGRAMS: &[(&str, &str)] = &[
+    ("src/tests/syscall_test.rs", "syscall_test"),
+];
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"));
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+
+    for (source, name) in USER_PROGRAMS {
+        println!("cargo:rerun-if-changed={}", source);
+        let status = Command::new(&rustc)
+            .args(["--edition", "2024", "--crate-type", "bin", "--target", USER_TARGET])
+            .args(["-C", "panic=abort", "-C", "opt-level=s", "-C", "strip=debuginfo"])
+            // ET_EXECとして固定アドレスにリンクする（ローダーは再配置に対応しない）
+            .args(["-C", "relocation-model=static"])
+            .arg("-C").arg(format!("link-arg=--image-base={:#x}", USER_IMAGE_BASE))
+            .arg("-o").arg(out_dir.join(name))
+            .arg(source)
+            .status()
+            .expect("failed to run rustc for a user program");
+        assert!(status.success(), "failed to build user program {}", source);
+    }
+}
@@ -13,7 +13,7 @@ static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAl
 
 // ヒープ領域の開始アドレスとサイズ
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB のヒープ領域を確保する。まあ、これで十分だろう。
+pub const HEAP_SIZE: usize = 1024 * 1024; // 1 MiB のヒープ領域。プロセスごとのカーネルスタックもここから確保する。
 
 // トレイト実装を許してもらうための、spin::Mutexをラップする型
 pub struct Locked<A> {
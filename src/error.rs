@@ -77,6 +77,8 @@ pub enum ProcessError {
     StackAllocationFailed,
     /// Context switch failed
     ContextSwitchFailed,
+    /// Executable image is malformed or unsupported
+    InvalidExecutable,
 }
 
 impl fmt::Display for ProcessError {
@@ -88,6 +90,7 @@ impl fmt::Display for ProcessError {
             ProcessError::InvalidState => write!(f, "Invalid process state"),
             ProcessError::StackAllocationFailed => write!(f, "Stack allocation failed"),
             ProcessError::ContextSwitchFailed => write!(f, "Context switch failed"),
+            ProcessError::InvalidExecutable => write!(f, "Invalid executable format"),
         }
     }
 }
@@ -107,6 +110,8 @@ pub enum SyscallError {
     NotSupported,
     /// Buffer too small
     BufferTooSmall,
+    /// System call number already registered
+    AlreadyRegistered,
 }
 
 impl fmt::Display for SyscallError {
@@ -118,6 +123,7 @@ impl fmt::Display for SyscallError {
             SyscallError::ResourceUnavailable => write!(f, "Resource unavailable"),
             SyscallError::NotSupported => write!(f, "Operation not supported"),
             SyscallError::BufferTooSmall => write!(f, "Buffer too small"),
+            SyscallError::AlreadyRegistered => write!(f, "System call already registered"),
         }
     }
 }
@@ -159,6 +165,14 @@ pub enum IpcError {
     InvalidProcess,
     /// Circular access attempt detected
     CircularTransfer,
+    /// 端点のケイパビリティのスロットが足りない
+    TooManyCapabilities,
+    /// 名前サービスにその名前のサービスがない
+    ServiceNotFound,
+    /// その名前のサービスはすでに登録されている
+    ServiceExists,
+    /// メッセージにケイパビリティが載っているが、受け取り先が指定されていない
+    CapabilityNotAccepted,
 }
 
 impl fmt::Display for IpcError {
@@ -181,6 +195,10 @@ impl fmt::Display for IpcError {
             IpcError::InvalidAddress => write!(f, "Invalid address"),
             IpcError::InvalidProcess => write!(f, "Invalid process"),
             IpcError::CircularTransfer => write!(f, "Circular transfer detected"),
+            IpcError::TooManyCapabilities => write!(f, "Too many capabilities"),
+            IpcError::ServiceNotFound => write!(f, "Service not found"),
+            IpcError::ServiceExists => write!(f, "Service already registered"),
+            IpcError::CapabilityNotAccepted => write!(f, "Message carries a capability but no slot pointer was given"),
         }
     }
 }
@@ -236,6 +254,187 @@ impl fmt::Display for GeneralError {
     }
 }
 
+/// Linux-compatible error numbers returned from system calls
+///
+/// System calls return `-(errno)` in RAX on failure, matching the Linux ABI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Errno {
+    /// Operation not permitted
+    EPERM = 1,
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// Interrupted system call
+    EINTR = 4,
+    /// I/O error
+    EIO = 5,
+    /// Argument list too long
+    E2BIG = 7,
+    /// Exec format error
+    ENOEXEC = 8,
+    /// Bad file descriptor
+    EBADF = 9,
+    /// No child processes
+    ECHILD = 10,
+    /// Try again
+    EAGAIN = 11,
+    /// Out of memory
+    ENOMEM = 12,
+    /// Permission denied
+    EACCES = 13,
+    /// Bad address
+    EFAULT = 14,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// File exists
+    EEXIST = 17,
+    /// No such device
+    ENODEV = 19,
+    /// Invalid argument
+    EINVAL = 22,
+    /// Too many open files
+    EMFILE = 24,
+    /// Result out of range
+    ERANGE = 34,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// Function not implemented
+    ENOSYS = 38,
+    /// Message too long
+    EMSGSIZE = 90,
+    /// Operation not supported
+    EOPNOTSUPP = 95,
+    /// Connection timed out
+    ETIMEDOUT = 110,
+    /// Connection refused
+    ECONNREFUSED = 111,
+    /// Kernel-internal: the syscall blocked and must be restarted when woken.
+    /// Never returned to user space.
+    ERESTARTSYS = 512,
+    /// Kernel-internal: the handler replaced the caller's registers (rt_sigreturn),
+    /// so neither RAX nor RIP may be touched on return. Never returned to user space.
+    EJUSTRETURN = 513,
+}
+
+impl Errno {
+    /// Value placed in RAX when a system call fails with this error
+    pub const fn as_syscall_return(self) -> i64 {
+        -(self as i64)
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} ({})", self, *self as i64)
+    }
+}
+
+impl From<AllocError> for Errno {
+    fn from(err: AllocError) -> Self {
+        match err {
+            AllocError::OutOfMemory => Errno::ENOMEM,
+            AllocError::BadAlignment => Errno::EINVAL,
+            AllocError::AlreadyInUse => Errno::EEXIST,
+            AllocError::InvalidAddress => Errno::EFAULT,
+            AllocError::PermissionDenied => Errno::EACCES,
+        }
+    }
+}
+
+impl From<ProcessError> for Errno {
+    fn from(err: ProcessError) -> Self {
+        match err {
+            ProcessError::InvalidPid => Errno::EINVAL,
+            ProcessError::NotFound => Errno::ESRCH,
+            ProcessError::AlreadyExists => Errno::EEXIST,
+            ProcessError::InvalidState => Errno::EINVAL,
+            ProcessError::StackAllocationFailed => Errno::ENOMEM,
+            ProcessError::ContextSwitchFailed => Errno::EAGAIN,
+            ProcessError::InvalidExecutable => Errno::ENOEXEC,
+        }
+    }
+}
+
+impl From<SyscallError> for Errno {
+    fn from(err: SyscallError) -> Self {
+        match err {
+            SyscallError::InvalidNumber => Errno::ENOSYS,
+            SyscallError::InvalidArgs => Errno::EINVAL,
+            SyscallError::PermissionDenied => Errno::EPERM,
+            SyscallError::ResourceUnavailable => Errno::EAGAIN,
+            SyscallError::NotSupported => Errno::EOPNOTSUPP,
+            SyscallError::BufferTooSmall => Errno::ERANGE,
+            SyscallError::AlreadyRegistered => Errno::EEXIST,
+        }
+    }
+}
+
+impl From<IpcError> for Errno {
+    fn from(err: IpcError) -> Self {
+        match err {
+            IpcError::ChannelNotFound => Errno::EBADF,
+            IpcError::ChannelExists => Errno::EEXIST,
+            IpcError::MessageTooLarge => Errno::EMSGSIZE,
+            IpcError::NoMessage => Errno::EAGAIN,
+            IpcError::InvalidChannelId => Errno::EBADF,
+            IpcError::ConnectionRefused => Errno::ECONNREFUSED,
+            IpcError::InvalidSender => Errno::EPERM,
+            IpcError::ChannelFull => Errno::EAGAIN,
+            IpcError::HandleNotFound => Errno::EBADF,
+            IpcError::InvalidRange => Errno::EINVAL,
+            IpcError::AccessDenied => Errno::EACCES,
+            IpcError::TransferFailed => Errno::EIO,
+            IpcError::MappingFailed => Errno::ENOMEM,
+            IpcError::UnmappingFailed => Errno::EINVAL,
+            IpcError::InvalidAddress => Errno::EFAULT,
+            IpcError::InvalidProcess => Errno::ESRCH,
+            IpcError::CircularTransfer => Errno::EINVAL,
+            IpcError::TooManyCapabilities => Errno::EMFILE,
+            IpcError::ServiceNotFound => Errno::ENOENT,
+            IpcError::ServiceExists => Errno::EEXIST,
+            IpcError::CapabilityNotAccepted => Errno::EINVAL,
+        }
+    }
+}
+
+impl From<HardwareError> for Errno {
+    fn from(err: HardwareError) -> Self {
+        match err {
+            HardwareError::DeviceNotFound => Errno::ENODEV,
+            HardwareError::DeviceBusy => Errno::EBUSY,
+            HardwareError::IoFailed => Errno::EIO,
+            HardwareError::InvalidPort => Errno::EINVAL,
+            HardwareError::Timeout => Errno::ETIMEDOUT,
+        }
+    }
+}
+
+impl From<GeneralError> for Errno {
+    fn from(err: GeneralError) -> Self {
+        match err {
+            GeneralError::InvalidOperation => Errno::EINVAL,
+            GeneralError::NotImplemented => Errno::ENOSYS,
+            GeneralError::Internal => Errno::EIO,
+            GeneralError::InvalidState => Errno::EINVAL,
+        }
+    }
+}
+
+impl From<KernelError> for Errno {
+    fn from(err: KernelError) -> Self {
+        match err {
+            KernelError::Memory(e) => e.into(),
+            KernelError::Process(e) => e.into(),
+            KernelError::Syscall(e) => e.into(),
+            KernelError::Ipc(e) => e.into(),
+            KernelError::Hardware(e) => e.into(),
+            KernelError::General(e) => e.into(),
+        }
+    }
+}
+
 /// Result type alias for kernel operations
 pub type KernelResult<T> = Result<T, KernelError>;
 
@@ -0,0 +1,236 @@
+//! FPU / SSE / AVX の拡張レジスタ状態
+//!
+//! `ProcessContext`には汎用レジスタしかないので、x87・XMM・YMMレジスタは
+//! スレッドごとの`ExtendedState`にXSAVE（なければFXSAVE）で保存する。
+//! 切り替えは遅延して行う。スレッドを切り替えるときはCR0.TSを立てるだけにし、
+//! 新しいスレッドが最初にFPU/SSE/AVX命令を使ったときのデバイス使用不可例外（#NM）で、
+//! 前の持ち主の状態を保存してから自分の状態を復元する。FPUを使わないスレッド
+//! ばかりなら保存・復元は一度も起こらない。
+//!
+//! カーネル自身はソフトフロートでビルドしているので、これらのレジスタを使わない。
+
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+/// FXSAVEの保存領域のサイズ
+pub const FXSAVE_AREA_SIZE: usize = 512;
+
+// CPUID.01H:ECX の機能ビット
+const CPUID_XSAVE: u32 = 1 << 26;
+const CPUID_AVX: u32 = 1 << 28;
+
+// 保存領域の初期値（FNINIT直後と同じ）
+const DEFAULT_FCW: u16 = 0x037f;
+const DEFAULT_MXCSR: u32 = 0x1f80;
+// FXSAVE形式の領域内のオフセット
+const FCW_OFFSET: usize = 0;
+const MXCSR_OFFSET: usize = 24;
+
+/// XSAVEを使うか（CPUが対応していればinitで有効にする）
+static XSAVE_ENABLED: AtomicBool = AtomicBool::new(false);
+/// スレッドごとの保存領域のサイズ
+static AREA_SIZE: AtomicUsize = AtomicUsize::new(FXSAVE_AREA_SIZE);
+/// レジスタに状態が載っているスレッドのTID（0なら誰もいない）
+static OWNER: AtomicU64 = AtomicU64::new(0);
+
+/// FPU/SSE（とあればAVX）を有効にする
+///
+/// CPUIDでXSAVEとAVXの対応を調べ、XCR0に保存する状態を設定する。
+/// プロセスを作成する前に呼ぶこと（保存領域のサイズが決まる）。
+pub fn init() {
+    let features = __cpuid(1);
+    let has_xsave = features.ecx & CPUID_XSAVE != 0;
+    let has_avx = features.ecx & CPUID_AVX != 0;
+
+    unsafe {
+        // EMをクリアしてFPU命令を実行できるようにし、MPでTSによる#NMを有効にする
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+
+        if has_xsave {
+            Cr4::update(|flags| flags.insert(Cr4Flags::OSXSAVE));
+            let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+            if has_avx {
+                xcr0 |= XCr0Flags::AVX;
+            }
+            XCr0::write(xcr0);
+        }
+    }
+
+    if has_xsave {
+        // XCR0で有効にした状態をすべて保存するのに必要なサイズ
+        let size = __cpuid_count(0xd, 0).ebx as usize;
+        AREA_SIZE.store(size.max(FXSAVE_AREA_SIZE), Ordering::Relaxed);
+        XSAVE_ENABLED.store(true, Ordering::Relaxed);
+    }
+    OWNER.store(0, Ordering::Relaxed);
+    set_task_switched();
+
+    crate::println!("FPU: {} ({} bytes per thread{})",
+        if has_xsave { "XSAVE" } else { "FXSAVE" },
+        area_size(),
+        if has_xsave && has_avx { ", AVX" } else { "" });
+}
+
+/// スレッドごとの保存領域のサイズ（バイト）
+pub fn area_size() -> usize {
+    AREA_SIZE.load(Ordering::Relaxed)
+}
+
+// XSAVE/FXSAVEの領域は64バイト境界に置く必要がある
+#[derive(Clone, Copy)]
+#[repr(C, align(64))]
+struct AreaChunk([u8; 64]);
+
+/// スレッドごとのFPU/SSE/AVXレジスタの保存領域
+#[derive(Clone)]
+pub struct ExtendedState {
+    area: Box<[AreaChunk]>,
+}
+
+impl ExtendedState {
+    /// 初期状態（FNINIT直後、XMM/YMMはすべて0）の保存領域
+    pub fn new() -> Self {
+        let chunks = area_size().div_ceil(64);
+        let mut state = ExtendedState {
+            area: alloc::vec![AreaChunk([0; 64]); chunks].into_boxed_slice(),
+        };
+        // XSAVEヘッダ（XSTATE_BV）が0なら、復元時に各状態は初期値になる。
+        // FCWとMXCSRはFXRSTORでもXRSTORでも領域から読まれるので設定しておく
+        let bytes = state.bytes_mut();
+        bytes[FCW_OFFSET..FCW_OFFSET + 2].copy_from_slice(&DEFAULT_FCW.to_le_bytes());
+        bytes[MXCSR_OFFSET..MXCSR_OFFSET + 4].copy_from_slice(&DEFAULT_MXCSR.to_le_bytes());
+        state
+    }
+
+    /// `tid`のスレッドの今の状態を複製する（fork・cloneで子に引き継ぐ）
+    ///
+    /// そのスレッドがレジスタを持っていれば、保存領域よりレジスタの方が新しい。
+    pub fn duplicate(&self, tid: u64) -> Self {
+        let mut copy = self.clone();
+        if OWNER.load(Ordering::Relaxed) == tid {
+            clear_task_switched();
+            copy.save();
+        }
+        copy
+    }
+
+    /// 保存されているx87の制御ワード（FCW）
+    pub fn control_word(&self) -> u16 {
+        u16::from_le_bytes([self.bytes()[FCW_OFFSET], self.bytes()[FCW_OFFSET + 1]])
+    }
+
+    /// 保存されているSSEの制御・状態レジスタ（MXCSR）
+    pub fn mxcsr(&self) -> u32 {
+        let mut value = [0u8; 4];
+        value.copy_from_slice(&self.bytes()[MXCSR_OFFSET..MXCSR_OFFSET + 4]);
+        u32::from_le_bytes(value)
+    }
+
+    /// MXCSRを書き換える（次に復元したときにレジスタに反映される）
+    pub fn set_mxcsr(&mut self, value: u32) {
+        self.bytes_mut()[MXCSR_OFFSET..MXCSR_OFFSET + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&self) -> &[u8] {
+        let len = self.area.len() * 64;
+        unsafe { core::slice::from_raw_parts(self.area.as_ptr() as *const u8, len) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        let len = self.area.len() * 64;
+        unsafe { core::slice::from_raw_parts_mut(self.area.as_mut_ptr() as *mut u8, len) }
+    }
+
+    // レジスタの状態を保存領域に書き出す（CR0.TSがクリアされていること）
+    fn save(&mut self) {
+        let ptr = self.area.as_mut_ptr();
+        unsafe {
+            if XSAVE_ENABLED.load(Ordering::Relaxed) {
+                asm!("xsave64 [{}]", in(reg) ptr, in("eax") u32::MAX, in("edx") u32::MAX, options(nostack));
+            } else {
+                asm!("fxsave64 [{}]", in(reg) ptr, options(nostack));
+            }
+        }
+    }
+
+    // 保存領域からレジスタに読み込む（CR0.TSがクリアされていること）
+    fn restore(&self) {
+        let ptr = self.area.as_ptr();
+        unsafe {
+            if XSAVE_ENABLED.load(Ordering::Relaxed) {
+                asm!("xrstor64 [{}]", in(reg) ptr, in("eax") u32::MAX, in("edx") u32::MAX, options(nostack, readonly));
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) ptr, options(nostack, readonly));
+            }
+        }
+    }
+}
+
+impl Default for ExtendedState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn set_task_switched() {
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED)) };
+}
+
+fn clear_task_switched() {
+    unsafe { asm!("clts", options(nomem, nostack)) };
+}
+
+/// スレッドを切り替えるときに呼ぶ
+///
+/// 切り替え先がレジスタを持っていなければCR0.TSを立て、最初に使ったときに
+/// #NMで状態を入れ替える。
+pub fn switch_to(tid: u64) {
+    if OWNER.load(Ordering::Relaxed) == tid {
+        clear_task_switched();
+    } else {
+        set_task_switched();
+    }
+}
+
+/// スレッドの状態を捨てる（execveで初期状態からやり直すとき）
+///
+/// レジスタに残っている状態は保存せず、次に使ったときに保存領域から復元させる。
+pub fn release(tid: u64) {
+    if OWNER.compare_exchange(tid, 0, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+        set_task_switched();
+    }
+}
+
+/// デバイス使用不可例外（#NM）の処理
+///
+/// 前の持ち主の状態を保存し、現在のスレッドの状態を復元する。持ち主が
+/// すでに終了していれば保存は省く。
+pub fn handle_device_not_available() {
+    use crate::process::scheduler::SCHEDULER;
+
+    clear_task_switched();
+    let current = crate::syscall::get_current_process_id();
+    let owner = OWNER.load(Ordering::Relaxed);
+    if owner == current {
+        return;
+    }
+
+    let mut sched = SCHEDULER.lock();
+    if owner != 0
+        && let Some(process) = sched.processes.iter_mut().find(|p| p.id == owner)
+    {
+        process.fpu.save();
+    }
+    if let Some(process) = sched.current_process_mut() {
+        process.fpu.restore();
+        OWNER.store(current, Ordering::Relaxed);
+    }
+}
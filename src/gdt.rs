@@ -7,28 +7,30 @@ use lazy_static::lazy_static;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        // Ring 3 -> Ring0 遷移スタック
-        tss.privilege_stack_table[0] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            stack_start + STACK_SIZE
-        };
-
-        // スタックオーバーフローやダブルフォルトなどの例外処理用にスタックを設定
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
+// TSSはコンテキストスイッチのたびにRSP0を書き換えるため、可変のstaticとして持つ
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+// TSSのスタックを設定して参照を返す（GDTの初期化時に一度だけ呼ばれる）
+fn init_tss() -> &'static TaskStateSegment {
+    let tss = unsafe { &mut *core::ptr::addr_of_mut!(TSS) };
+    // Ring 3 -> Ring0 遷移スタック（最初のプロセスに切り替わるまで使われる）
+    tss.privilege_stack_table[0] = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        let stack_start = VirtAddr::from_ptr(&raw const STACK);
+        stack_start + STACK_SIZE
     };
+
+    // スタックオーバーフローやダブルフォルトなどの例外処理用にスタックを設定
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(&raw const STACK);
+        let stack_end = stack_start + STACK_SIZE;
+        stack_end
+    };
+    unsafe { &*core::ptr::addr_of!(TSS) }
 }
 
 lazy_static! {
@@ -44,7 +46,7 @@ lazy_static! {
         let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
 
         // TSS
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(init_tss()));
         (gdt, Selectors {
             code_selector,
             data_selector,
@@ -65,8 +67,22 @@ pub struct Selectors {
 
 // カーネル特権スタックの最上部アドレスを返す。
 pub fn kernel_stack_top() -> VirtAddr {
+    // TSSの初期化はGDTと同時に行われる
+    lazy_static::initialize(&GDT);
     // TSS.privilege_stack_table[0] を返す
-    TSS.privilege_stack_table[0]
+    unsafe { (*core::ptr::addr_of!(TSS)).privilege_stack_table[0] }
+}
+
+/// 割り込み・システムコールで使うカーネルスタックを切り替える
+///
+/// TSSのRSP0（割り込み用）とCPU_DATAのカーネルスタック（SYSCALL用）を
+/// 同時に更新する。スケジューラがプロセスを切り替えるときに呼ぶ。
+pub fn set_kernel_stack(stack_top: VirtAddr) {
+    lazy_static::initialize(&GDT);
+    unsafe {
+        (*core::ptr::addr_of_mut!(TSS)).privilege_stack_table[0] = stack_top;
+        crate::syscall::CPU_DATA.kernel_stack_top = stack_top.as_u64();
+    }
 }
 
 pub fn init() {
@@ -203,11 +219,6 @@ pub unsafe fn jump_to_user_mode(code_addr: VirtAddr, stack_addr: VirtAddr) -> !
     x86_64::instructions::interrupts::disable();
     crate::println!("Interrupts disabled for user mode jump");
     
-    // ユーザーモード開始をタイマーに通知
-    // 現在のプロセスIDを取得して渡す
-    let current_pid = unsafe { crate::syscall::CPU_DATA.current_process_id };
-    crate::timer::start_user_mode(current_pid);
-
     unsafe {
         // セキュアなRFLAGSを設定
         let secure_rflags = setup_secure_rflags();
@@ -21,9 +21,11 @@ lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         let timer_addr = VirtAddr::new(timer_interrupt_handler as *const () as u64);
+        let page_fault_addr = VirtAddr::new(page_fault_handler as *const () as u64);
         idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.device_not_available.set_handler_fn(device_not_available_handler);
         unsafe {
+            idt.page_fault.set_handler_addr(page_fault_addr);
             idt.double_fault.set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
             idt[InterruptIndex::Timer.as_usize()].set_handler_addr(timer_addr);
@@ -52,6 +54,19 @@ extern "x86-interrupt" fn breakpoint_handler(
     hlt_loop();
 }
 
+// デバイス使用不可例外（#NM）ハンドラ
+// CR0.TSが立っているときにFPU/SSE/AVX命令を使うと発生する。レジスタの
+// 持ち主を現在のスレッドに入れ替えて、同じ命令から再開する
+extern "x86-interrupt" fn device_not_available_handler(
+    stack_frame: InterruptStackFrame)
+{
+    // カーネルはソフトフロートなので、ユーザーモード以外からは起こらないはず
+    if stack_frame.code_segment & 3 != 3 {
+        panic!("EXCEPTION: DEVICE NOT AVAILABLE in kernel\n{:#?}", stack_frame);
+    }
+    crate::fpu::handle_device_not_available();
+}
+
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame, _error_code: u64)
     -> !
@@ -59,16 +74,90 @@ extern "x86-interrupt" fn double_fault_handler(
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
+// ページフォルト例外ハンドラ
+//
+// 不正なアクセスをしたプロセスを終了させて別のプロセスに切り替えられるよう、
+// タイマーと同じくレジスタをProcessContextの並びで保存する。CPUが積んだ
+// エラーコードはちょうどRAXの位置にあるので、RAXと入れ替えて取り出す。
+#[unsafe(naked)]
+unsafe extern "C" fn page_fault_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    naked_asm!(
+        "xchg rax, [rsp]",     // RAXを保存し、エラーコードを取り出す
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+
+        "mov rdi, rsp",        // 第1引数に保存したコンテキスト
+        "mov rsi, rax",        // 第2引数にエラーコード
+        "call {fault_handler}",
+        "mov rsp, rax",
+
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+
+        "iretq",
+        fault_handler = sym handle_page_fault,
+    );
+}
+
+// ページフォルトの本体。復帰先のコンテキストを返す
+extern "C" fn handle_page_fault(context_ptr: u64, error_code: u64) -> u64 {
     use x86_64::registers::control::Cr2;
+    use crate::memory::vma::{self, Access};
+
+    let addr = Cr2::read();
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+    let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        Access::Execute
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        Access::Write
+    } else {
+        Access::Read
+    };
+
+    // VMAの範囲内なら、ページを割り当てる（デマンドページング・COW）
+    if vma::handle_user_fault(addr, access) {
+        return context_ptr;
+    }
+
+    // ユーザープロセスの不正なアクセスはそのプロセスにSIGSEGVを送る
+    if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        println!("Segmentation fault: PID {} accessed {:?} ({:?})",
+            crate::syscall::get_current_process_id(), addr, error_code);
+        return crate::process::scheduler::force_signal_current(context_ptr, crate::process::signal::SIGSEGV);
+    }
 
+    let context = unsafe { &*(context_ptr as *const crate::process::ProcessContext) };
     println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
+    println!("Accessed Address: {:?}", addr);
     println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
+    println!("RIP: {:#x}, RSP: {:#x}", context.instruction_pointer(), context.stack_pointer());
     hlt_loop();
 }
 
@@ -95,14 +184,13 @@ pub unsafe extern "C" fn timer_interrupt_handler(
         "push r14",
         "push r15",
 
+        // ティックを進める（RAXを壊すので切り替えより先に）
+        "call {tick_handler}",
+
         "sub rsp, 8",         // アライメント調整（16バイト境界にする）
         "mov rdi, rsp",
         "add rdi, 8",         // 引数には「元のContextの先頭」を渡す
         "call {switch_handler}",
-        "add rsp, 8",         // 調整を戻す
-        
-        // タイムアウトチェックを呼び出す
-        "call {timeout_handler}",
 
         "mov rsp, rax",
 
@@ -125,7 +213,7 @@ pub unsafe extern "C" fn timer_interrupt_handler(
 
         "iretq",
         switch_handler = sym crate::process::handle_switch,
-        timeout_handler = sym crate::timer::increment_tick,
+        tick_handler = sym crate::timer::increment_tick,
     );
 }
 
@@ -134,7 +222,8 @@ pub unsafe extern "C" fn syscall_interrupt_handler(
     _stack_frame: InterruptStackFrame)
 {
     naked_asm!(
-        // 汎用レジスタを保存
+        // CPUが積んだIRETQ用フレームの下に汎用レジスタを積み、
+        // SYSCALL命令の入口と同じSyscallFrameにする
         "push rax",
         "push rcx", 
         "push rdx",
@@ -151,13 +240,10 @@ pub unsafe extern "C" fn syscall_interrupt_handler(
         "push r14",
         "push r15",
 
-        "sub rsp, 8",         // アライメント調整
-        "mov rdi, rsp",         // 第1引数に現在のスタックポインタ
-        "add rdi, 8",         // 引数には「元のContextの先頭」を渡す
+        "mov rdi, rsp",         // 第1引数にSyscallFrame
         "call {syscall_handler}",
-        "add rsp, 8",         // 調整を戻す
-
-        // システムコールの結果はRAXにあるのでそのまま
+        // 復帰先のコンテキスト（結果はそのRAXに書き込まれている）
+        "mov rsp, rax",
 
         // レジスタを復元
         "pop r15",
@@ -174,7 +260,7 @@ pub unsafe extern "C" fn syscall_interrupt_handler(
         "pop rsi",
         "pop rdx",
         "pop rcx",
-        // RAXは結果が入っているので最後に復元しない
+        "pop rax",
 
         "iretq",
         syscall_handler = sym crate::syscall::rust_syscall_handler,
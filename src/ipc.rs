@@ -3,19 +3,46 @@
 //! このモジュールは、従来のメッセージパッシングとRuix独自の
 //! メモリハンドルIPCを提供します。データをコピーする代わりに
 //! メモリアクセス権限を転送する仕組みを提供します。
+//! チャンネルはPIDではなく、プロセスごとに持つ端点のケイパビリティ
+//! （スロット番号）で指し、ケイパビリティはメッセージに載せて移せます。
+//! サーバーは名前サービスに端点を登録し、クライアントは名前で検索して
+//! その端点のケイパビリティを受け取ります。
+//! 同期IPC（ipc_call / ipc_reply_wait）は小さなメッセージをレジスタのまま渡し、
+//! 呼び出し元から待っているサーバーへスケジューラを通さずに切り替えます。
 //!
 
-use alloc::collections::VecDeque;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
 use alloc::vec::Vec;
 use spin::Mutex;
 use lazy_static::lazy_static;
-use x86_64::{VirtAddr, PhysAddr, structures::paging::{PhysFrame, PageTableFlags}};
+use x86_64::{VirtAddr, PhysAddr, structures::paging::{Page, PhysFrame, PageTableFlags}};
 use core::sync::atomic::{AtomicU64, Ordering};
 use crate::error::{KernelResult, IpcError};
-use crate::syscall::{get_current_process_id, set_current_process_id};
+use crate::kerror;
+use crate::memory::{mmap, paging};
+use crate::memory::vma::Access;
+use crate::process::{ProcessContext, ProcessState, WaitReason};
+use crate::process::scheduler::Scheduler;
+use crate::syscall::{get_current_thread_group_id, set_current_process_id};
+use crate::syscall::table::MAX_CAPABILITIES;
 
 /// Maximum number of messages per IPC channel to prevent DoS attacks
-const MAX_QUEUE_SIZE: usize = 1000;
+pub const MAX_QUEUE_SIZE: usize = 1000;
+
+/// send_message / receive_message のフラグ：待たずにEAGAINで戻る
+pub const IPC_NONBLOCK: u64 = 1;
+/// send_message のフラグ：R9のスロットのケイパビリティをメッセージに載せて移す
+pub const IPC_TRANSFER_CAPABILITY: u64 = 2;
+/// receive_message：メッセージにケイパビリティが載っていなかった
+pub const NO_CAPABILITY: u64 = u64::MAX;
+
+/// 名前サービスに登録できる名前の最大長（バイト）
+pub const MAX_SERVICE_NAME: usize = 64;
+// register_service の policy
+pub const SERVICE_PUBLIC: u64 = 0;
+pub const SERVICE_SESSION: u64 = 1;
+pub const SERVICE_PRIVILEGED: u64 = 2;
 
 /// ハンドルのメモリアクセス権限
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -65,6 +92,12 @@ impl PageRange {
         addr >= self.start_addr && addr < end_addr
     }
 
+    /// 範囲内の各ページの先頭アドレス
+    pub fn pages(self) -> impl Iterator<Item = VirtAddr> {
+        let start = self.start_addr;
+        (0..self.size / 4096).map(move |i| start + (i * 4096) as u64)
+    }
+
     /// 範囲が適切にページ境界に揃っているか検証
     pub fn is_valid(&self) -> bool {
         self.start_addr.as_u64() % 4096 == 0 && self.size % 4096 == 0 && self.size > 0
@@ -103,6 +136,10 @@ pub struct MemoryHandle {
     pub is_mapped: bool,
     /// Holder's virtual address where memory is mapped (if mapped)
     pub holder_virt_addr: Option<VirtAddr>,
+    /// 転送時に送信者のページテーブルから取り出したフレーム
+    ///
+    /// 送信者がマッピングを外してもフレームが残るよう、ハンドルが参照を1つずつ持つ。
+    pub frames: Vec<PhysFrame>,
 }
 
 impl MemoryHandle {
@@ -117,6 +154,7 @@ impl MemoryHandle {
             active: true,
             is_mapped: false,
             holder_virt_addr: None,
+            frames: Vec::new(),
         }
     }
 
@@ -131,9 +169,10 @@ impl MemoryHandle {
         match self.rights {
             AccessRights::ReadOnly => {
                 // リードオンリー
+                flags |= PageTableFlags::NO_EXECUTE;
             },
             AccessRights::ReadWrite => {
-                flags |= PageTableFlags::WRITABLE;
+                flags |= PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
             },
             AccessRights::Execute => {
                 // NXビット処理 - Clear the NO_EXECUTE flag to allow execution
@@ -150,6 +189,14 @@ impl MemoryHandle {
     pub fn revoke(&mut self) {
         self.active = false;
         self.rights = AccessRights::None;
+        self.release_frames();
+    }
+
+    // ハンドルが持つフレームの参照を手放す
+    fn release_frames(&mut self) {
+        for frame in self.frames.drain(..) {
+            crate::memory::release_frame(frame);
+        }
     }
 
     /// ハンドルを検証
@@ -261,6 +308,93 @@ impl HandleRegistry {
         }
     }
 
+    /// ハンドルのメモリを`to_pid`に転送する
+    ///
+    /// 送信者のページテーブルから実際のフレームを取り出してハンドルに持たせる。
+    /// Ownership・Exclusiveでは送信者のマッピングを外す。受信者へのマップは
+    /// `accept_handle`で行う。
+    pub fn transfer_handle(&mut self, ops: &mut impl IpcPageTableOps, handle_id: u64, from_pid: u64, to_pid: u64) -> Result<(), IpcError> {
+        if self.detect_circular_transfer(from_pid, to_pid) {
+            return Err(IpcError::CircularTransfer);
+        }
+        let handle = self.get_handle_mut(handle_id).ok_or(IpcError::HandleNotFound)?;
+        if handle.owner_pid != from_pid {
+            return Err(IpcError::AccessDenied);
+        }
+        if !handle.validate() {
+            return Err(IpcError::InvalidRange);
+        }
+        // 転送済みのハンドルをもう一度転送することはできない
+        if !handle.frames.is_empty() {
+            return Err(IpcError::TransferFailed);
+        }
+
+        let flags = handle.access_to_flags();
+        let mut frames = Vec::with_capacity(handle.range.page_count()?);
+        for addr in handle.range.pages() {
+            let phys = ops.verify_ownership(from_pid, addr, flags).map_err(|_| IpcError::InvalidAddress)?;
+            frames.push(PhysFrame::containing_address(phys));
+        }
+        for &frame in &frames {
+            crate::memory::share_frame(frame);
+        }
+        handle.frames = frames;
+
+        if matches!(handle.mode, TransferMode::Ownership | TransferMode::Exclusive) {
+            if ops.unmap_memory(from_pid, handle.range.start_addr, handle.frames.len()).is_err() {
+                handle.release_frames();
+                return Err(IpcError::UnmappingFailed);
+            }
+            for addr in handle.range.pages() {
+                ops.flush_tlb_entry(addr);
+            }
+        }
+        handle.holder_pid = to_pid;
+        Ok(())
+    }
+
+    /// 転送されたハンドルのメモリを保持者のアドレス空間にマップする
+    ///
+    /// 送信者と同じ仮想アドレスに`MemoryHandle::access_to_flags`の権限でマップし、
+    /// その範囲を返す。すでにマップ済みならそのまま返す。
+    pub fn accept_handle(&mut self, ops: &mut impl IpcPageTableOps, handle_id: u64, pid: u64) -> Result<PageRange, IpcError> {
+        let handle = self.get_handle_mut(handle_id).ok_or(IpcError::HandleNotFound)?;
+        if handle.holder_pid != pid || handle.frames.is_empty() {
+            return Err(IpcError::AccessDenied);
+        }
+        if !handle.validate() {
+            return Err(IpcError::InvalidRange);
+        }
+        if !handle.is_mapped {
+            ops.map_memory(pid, handle.range.start_addr, &handle.frames, handle.access_to_flags())
+                .map_err(|_| IpcError::MappingFailed)?;
+            handle.mark_mapped(handle.range.start_addr);
+        }
+        Ok(handle.range)
+    }
+
+    /// ハンドルを無効化し、保持者のアドレス空間からマッピングを外す（所有者のみ）
+    ///
+    /// 無効化する前の保持者のPIDを返す。
+    pub fn revoke_and_unmap(&mut self, ops: &mut impl IpcPageTableOps, handle_id: u64, pid: u64) -> Result<u64, IpcError> {
+        let handle = self.get_handle_mut(handle_id).ok_or(IpcError::HandleNotFound)?;
+        if handle.owner_pid != pid {
+            return Err(IpcError::AccessDenied);
+        }
+        if handle.is_mapped
+            && let Some(virt_addr) = handle.holder_virt_addr
+        {
+            // 保持者がすでに終了していれば、マッピングはアドレス空間ごと消えている
+            let _ = ops.unmap_memory(handle.holder_pid, virt_addr, handle.frames.len());
+            for addr in PageRange::new(virt_addr, handle.range.size).pages() {
+                ops.flush_tlb_entry(addr);
+            }
+            handle.mark_unmapped();
+        }
+        handle.revoke();
+        Ok(handle.holder_pid)
+    }
+
     /// 循環転送が存在しないことを確認
     fn detect_circular_transfer(&self, from_pid: u64, to_pid: u64) -> bool {
         // とりあえず。同じプロセスの転送を防ぐ
@@ -279,6 +413,8 @@ pub struct Message {
     pub data: [u8; 256],
     /// 実際のデータ長
     pub data_len: usize,
+    /// 一緒に送る端点のケイパビリティ（受信したプロセスに移る）
+    pub capability: Option<Endpoint>,
 }
 
 impl Message {
@@ -293,6 +429,7 @@ impl Message {
             msg_type,
             data: msg_data,
             data_len: len,
+            capability: None,
         }
     }
 
@@ -302,67 +439,217 @@ impl Message {
     }
 }
 
-/// 2つのプロセス間の双方向通信用IPCチャンネル
+/// 同期IPC（ipc_call / ipc_reply_wait）でレジスタに載せて渡すメッセージの語数
+pub const CALL_MESSAGE_WORDS: usize = 4;
+
+/// 同期IPCのメッセージ
+///
+/// キューを通さず、レジスタのまま相手に渡す。送る側はRSIにラベル、
+/// RDX, R10, R8, R9に本文を入れてシステムコールを呼び、受け取る側には
+/// 同じレジスタに載って返る。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CallMessage {
+    /// アプリケーションが決めるメッセージの種類
+    pub label: u64,
+    /// 本文
+    pub words: [u64; CALL_MESSAGE_WORDS],
+}
+
+impl CallMessage {
+    /// システムコールを呼んだときのレジスタからメッセージを取り出す
+    pub fn from_context(context: &ProcessContext) -> Self {
+        let [_, label, w0, w1, w2, w3] = context.syscall_args();
+        CallMessage { label, words: [w0, w1, w2, w3] }
+    }
+
+    /// メッセージをレジスタに載せる（RDIはそのまま）
+    pub fn write_to(&self, context: &mut ProcessContext) {
+        let [rdi, ..] = context.syscall_args();
+        let [w0, w1, w2, w3] = self.words;
+        context.set_syscall_args([rdi, self.label, w0, w1, w2, w3]);
+    }
+}
+
+/// チャンネルの片側の端点
+///
+/// ユーザー空間はこれを直接扱わず、`CapabilityTable`のスロット番号で指す。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Endpoint {
+    /// チャンネルID
+    pub channel_id: u64,
+    /// どちら側か（0または1）
+    pub side: usize,
+}
+
+impl Endpoint {
+    /// 同じチャンネルの反対側の端点
+    pub fn peer(self) -> Endpoint {
+        Endpoint { channel_id: self.channel_id, side: self.side ^ 1 }
+    }
+
+    /// 待ち状態（`WaitReason::IpcReceive`など）で端点を区別するためのキー
+    pub fn key(self) -> u64 {
+        (self.channel_id << 1) | self.side as u64
+    }
+}
+
+/// 2つの端点を結ぶ双方向通信用IPCチャンネル
+///
+/// 端点を持つプロセスは`ChannelRegistry`のケイパビリティで管理し、
+/// チャンネル自体はPIDを覚えない。
 #[derive(Debug)]
 pub struct Channel {
     /// チャンネルID
     pub id: u64,
-    /// 最初のエンドポイントプロセスID
-    pub endpoint1: u64,
-    /// 2番目のエンドポイントプロセスID
-    pub endpoint2: u64,
-    /// endpoint1 -> endpoint2 用のメッセージキュー
-    pub queue1_to_2: VecDeque<Message>,
-    /// endpoint2 -> endpoint1 用のメッセージキュー
-    pub queue2_to_1: VecDeque<Message>,
+    /// 端点ごとの受信キュー（`queues[side]`はその端点宛てのメッセージ）
+    pub queues: [VecDeque<Message>; 2],
+    /// 端点を指しているケイパビリティの数（プロセスが持っているものと、
+    /// メッセージに載って移動中のもの）。0になった端点は閉じている
+    pub refs: [usize; 2],
 }
 
 impl Channel {
-    pub fn new(id: u64, pid1: u64, pid2: u64) -> Self {
+    pub fn new(id: u64) -> Self {
         Channel {
             id,
-            endpoint1: pid1,
-            endpoint2: pid2,
-            queue1_to_2: VecDeque::new(),
-            queue2_to_1: VecDeque::new(),
+            queues: [VecDeque::new(), VecDeque::new()],
+            refs: [1, 1],
         }
     }
 
-    /// 送信者から受信者へメッセージを送信
-    pub fn send(&mut self, sender_pid: u64, message: Message) -> Result<(), IpcError> {
-        if sender_pid == self.endpoint1 {
-            // Check queue size limit to prevent DoS attacks
-            if self.queue1_to_2.len() >= MAX_QUEUE_SIZE {
-                return Err(IpcError::ChannelFull);
-            }
-            self.queue1_to_2.push_back(message);
-            Ok(())
-        } else if sender_pid == self.endpoint2 {
-            // Check queue size limit to prevent DoS attacks
-            if self.queue2_to_1.len() >= MAX_QUEUE_SIZE {
-                return Err(IpcError::ChannelFull);
-            }
-            self.queue2_to_1.push_back(message);
-            Ok(())
-        } else {
-            Err(IpcError::InvalidSender)
+    /// 端点が閉じていないか
+    pub fn is_open(&self, side: usize) -> bool {
+        self.refs[side] > 0
+    }
+
+    /// `side`の端点から反対側へメッセージを送信
+    pub fn send(&mut self, side: usize, message: Message) -> Result<(), IpcError> {
+        let peer = side ^ 1;
+        if !self.is_open(peer) {
+            return Err(IpcError::ConnectionRefused);
+        }
+        // Check queue size limit to prevent DoS attacks
+        if self.queues[peer].len() >= MAX_QUEUE_SIZE {
+            return Err(IpcError::ChannelFull);
         }
+        self.queues[peer].push_back(message);
+        Ok(())
     }
 
-    /// 指定されたプロセスのメッセージを受信
-    pub fn receive(&mut self, receiver_pid: u64) -> Option<Message> {
-        if receiver_pid == self.endpoint1 {
-            self.queue2_to_1.pop_front()
-        } else if receiver_pid == self.endpoint2 {
-            self.queue1_to_2.pop_front()
-        } else {
-            None
+    /// `side`の端点宛てのメッセージを受信
+    ///
+    /// キューが空で相手が閉じていれば、もう届くことはないのでエラーにする。
+    pub fn receive(&mut self, side: usize) -> Result<Option<Message>, IpcError> {
+        match self.queues[side].pop_front() {
+            None if !self.is_open(side ^ 1) => Err(IpcError::ConnectionRefused),
+            message => Ok(message),
+        }
+    }
+}
+
+/// プロセスが持つ端点のケイパビリティ
+///
+/// スロット番号がユーザー空間から見たチャンネルの識別子になる。持っている
+/// プロセスだけがその端点で送受信でき、メッセージに載せて別のプロセスに移せる。
+/// forkした子は親のケイパビリティを引き継ぐ（ファイルディスクリプタと同じ）。
+#[derive(Debug, Clone, Default)]
+pub struct CapabilityTable {
+    slots: Vec<Option<Endpoint>>,
+}
+
+impl CapabilityTable {
+    /// 空いているスロットのうち最小の番号に端点を入れる
+    pub fn insert(&mut self, endpoint: Endpoint) -> Result<u64, IpcError> {
+        if let Some(slot) = self.slots.iter().position(Option::is_none) {
+            self.slots[slot] = Some(endpoint);
+            return Ok(slot as u64);
+        }
+        if self.slots.len() as u64 >= MAX_CAPABILITIES {
+            return Err(IpcError::TooManyCapabilities);
+        }
+        self.slots.push(Some(endpoint));
+        Ok(self.slots.len() as u64 - 1)
+    }
+
+    /// スロットの端点
+    pub fn get(&self, slot: u64) -> Option<Endpoint> {
+        self.slots.get(slot as usize).copied().flatten()
+    }
+
+    /// スロットから端点を取り出す（スロットは空く）
+    pub fn remove(&mut self, slot: u64) -> Option<Endpoint> {
+        self.slots.get_mut(slot as usize)?.take()
+    }
+
+    /// 端点を持っているか
+    pub fn holds(&self, endpoint: Endpoint) -> bool {
+        self.slots.contains(&Some(endpoint))
+    }
+
+    /// 持っているすべての端点
+    pub fn endpoints(&self) -> impl Iterator<Item = Endpoint> + '_ {
+        self.slots.iter().flatten().copied()
+    }
+}
+
+/// 最後のケイパビリティがなくなって閉じた端点
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClosedEndpoint {
+    pub endpoint: Endpoint,
+    /// 相手側の端点を持っているプロセス（待っているスレッドを起こす）
+    pub peer_holders: Vec<u64>,
+}
+
+/// 名前サービスのサービスを検索できるプロセス
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServicePolicy {
+    /// 誰でも
+    Public,
+    /// 登録したプロセスと同じセッションのプロセスと、特権を持つプロセス
+    Session,
+    /// 特権を持つプロセスだけ
+    Privileged,
+}
+
+impl ServicePolicy {
+    /// register_service の policy 引数から変換
+    pub fn from_raw(policy: u64) -> Option<Self> {
+        match policy {
+            SERVICE_PUBLIC => Some(ServicePolicy::Public),
+            SERVICE_SESSION => Some(ServicePolicy::Session),
+            SERVICE_PRIVILEGED => Some(ServicePolicy::Privileged),
+            _ => None,
         }
     }
+}
+
+/// 検索するプロセスの資格（`ServicePolicy`と照らし合わせる）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    pub session_id: u64,
+    pub privileged: bool,
+}
+
+/// 名前サービスに登録されたサービス
+#[derive(Debug, Clone)]
+pub struct Service {
+    /// 登録したプロセス。終了すると（親が回収するのを待たずに）登録も消える
+    pub owner: u64,
+    /// クライアントに渡す端点（名前サービス自身もケイパビリティを1つ持つ）
+    pub endpoint: Endpoint,
+    pub policy: ServicePolicy,
+    /// 登録したプロセスのセッション（`ServicePolicy::Session`で使う）
+    pub session_id: u64,
+}
 
-    /// プロセスがこのチャンネルのエンドポイントかチェック
-    pub fn has_endpoint(&self, pid: u64) -> bool {
-        pid == self.endpoint1 || pid == self.endpoint2
+impl Service {
+    /// `credentials`のプロセスがこのサービスを検索できるか
+    pub fn allows(&self, credentials: Credentials) -> bool {
+        match self.policy {
+            ServicePolicy::Public => true,
+            ServicePolicy::Session => credentials.privileged || credentials.session_id == self.session_id,
+            ServicePolicy::Privileged => credentials.privileged,
+        }
     }
 }
 
@@ -372,6 +659,10 @@ pub struct ChannelRegistry {
     channels: Vec<Channel>,
     /// 次に割り当てるチャンネルID
     next_id: u64,
+    /// プロセス（スレッドグループ）ごとのケイパビリティ
+    capabilities: BTreeMap<u64, CapabilityTable>,
+    /// 名前サービス（サービス名 -> 登録）
+    services: BTreeMap<String, Service>,
 }
 
 impl ChannelRegistry {
@@ -380,18 +671,28 @@ impl ChannelRegistry {
         ChannelRegistry {
             channels: Vec::new(),
             next_id: 1,
+            capabilities: BTreeMap::new(),
+            services: BTreeMap::new(),
         }
     }
 
-    /// Create a new channel between two processes
-    pub fn create_channel(&mut self, pid1: u64, pid2: u64) -> Result<u64, IpcError> {
+    /// チャンネルを作成し、両方の端点のケイパビリティを`pid`に渡す
+    ///
+    /// 2つのスロット番号を返す。片方を別のプロセスに送れば通信できる。
+    pub fn create_channel(&mut self, pid: u64) -> Result<(u64, u64), IpcError> {
         let channel_id = self.next_id;
+        let table = self.capabilities.entry(pid).or_default();
+        let first = table.insert(Endpoint { channel_id, side: 0 })?;
+        let second = match table.insert(Endpoint { channel_id, side: 1 }) {
+            Ok(slot) => slot,
+            Err(e) => {
+                table.remove(first);
+                return Err(e);
+            }
+        };
         self.next_id += 1;
-
-        let channel = Channel::new(channel_id, pid1, pid2);
-        self.channels.push(channel);
-
-        Ok(channel_id)
+        self.channels.push(Channel::new(channel_id));
+        Ok((first, second))
     }
 
     /// IDでチャンネルへの可変参照を取得
@@ -404,23 +705,180 @@ impl ChannelRegistry {
         self.channels.iter().find(|c| c.id == channel_id)
     }
 
-    /// プロセスのチャンネルを検索
-    pub fn get_channels_for_process(&self, pid: u64) -> Vec<&Channel> {
-        self.channels.iter().filter(|c| c.has_endpoint(pid)).collect()
+    /// `pid`のスロットが指す端点（持っていなければエラー）
+    pub fn resolve(&self, pid: u64, slot: u64) -> Result<Endpoint, IpcError> {
+        self.capabilities.get(&pid)
+            .and_then(|table| table.get(slot))
+            .ok_or(IpcError::InvalidChannelId)
+    }
+
+    /// 端点のケイパビリティを持っているプロセス
+    ///
+    /// メッセージに載って移動中の端点や閉じた端点は誰も持っていない。
+    pub fn holders_of(&self, endpoint: Endpoint) -> Vec<u64> {
+        self.capabilities.iter()
+            .filter(|(_, table)| table.holds(endpoint))
+            .map(|(&pid, _)| pid)
+            .collect()
     }
 
-    /// Clean up all channels for a specific process
-    pub fn cleanup_process_channels(&mut self, pid: u64) {
-        let channels_to_remove: Vec<u64> = self.channels
-            .iter()
-            .filter(|c| c.has_endpoint(pid))
-            .map(|c| c.id)
-            .collect();
-            
-        for channel_id in channels_to_remove {
-            self.channels.retain(|c| c.id != channel_id);
-            crate::println!("IPC: Cleaned up channel {} for PID {}", channel_id, pid);
+    /// `pid`のスロットの端点からメッセージを送る
+    ///
+    /// `transfer`を指定すると、そのスロットのケイパビリティを`pid`から取り除いて
+    /// メッセージに載せる（受信したプロセスに移る）。送信できなかったときは
+    /// ケイパビリティは`pid`に残る。
+    pub fn send(&mut self, pid: u64, slot: u64, mut message: Message, transfer: Option<u64>) -> Result<(), IpcError> {
+        let endpoint = self.resolve(pid, slot)?;
+        if let Some(transfer) = transfer {
+            let capability = self.resolve(pid, transfer)?;
+            // 自分のチャンネルの端点を載せると、誰も受け取れないまま閉じられなくなる
+            if capability.channel_id == endpoint.channel_id {
+                return Err(IpcError::CircularTransfer);
+            }
+            message.capability = Some(capability);
         }
+
+        let channel = self.get_channel_mut(endpoint.channel_id).ok_or(IpcError::ChannelNotFound)?;
+        channel.send(endpoint.side, message)?;
+        if let Some(transfer) = transfer
+            && let Some(table) = self.capabilities.get_mut(&pid)
+        {
+            table.remove(transfer);
+        }
+        Ok(())
+    }
+
+    /// `pid`のスロットの端点宛てのメッセージを受け取る
+    ///
+    /// メッセージにケイパビリティが載っていれば`pid`のスロットに入れ、その番号も返す。
+    /// `accept_capability`が偽なら、ケイパビリティが載ったメッセージは取り出さずに
+    /// `CapabilityNotAccepted`を返す（スロット番号を知らせる先がないと、端点が
+    /// 誰にも使えないまま残ってしまう）。
+    pub fn receive(&mut self, pid: u64, slot: u64, accept_capability: bool) -> Result<Option<(Message, Option<u64>)>, IpcError> {
+        let endpoint = self.resolve(pid, slot)?;
+        let channel = self.get_channel_mut(endpoint.channel_id).ok_or(IpcError::ChannelNotFound)?;
+        if !accept_capability && channel.queues[endpoint.side].front().is_some_and(|m| m.capability.is_some()) {
+            return Err(IpcError::CapabilityNotAccepted);
+        }
+        let Some(message) = channel.receive(endpoint.side)? else {
+            return Ok(None);
+        };
+        let Some(capability) = message.capability else {
+            return Ok(Some((message, None)));
+        };
+        match self.capabilities.entry(pid).or_default().insert(capability) {
+            Ok(new_slot) => Ok(Some((message, Some(new_slot)))),
+            Err(e) => {
+                // スロットが足りなければメッセージを戻して、あとで受け取れるようにする
+                if let Some(channel) = self.get_channel_mut(endpoint.channel_id) {
+                    channel.queues[endpoint.side].push_front(message);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// `pid`のスロットのケイパビリティを閉じる
+    ///
+    /// ほかに端点を指しているケイパビリティがなければ端点も閉じ、それを返す。
+    pub fn close(&mut self, pid: u64, slot: u64) -> Result<Vec<ClosedEndpoint>, IpcError> {
+        let endpoint = self.capabilities.get_mut(&pid)
+            .and_then(|table| table.remove(slot))
+            .ok_or(IpcError::InvalidChannelId)?;
+        Ok(self.release(alloc::vec![endpoint]))
+    }
+
+    /// forkした子に親のケイパビリティをすべて引き継ぐ
+    pub fn inherit(&mut self, parent: u64, child: u64) {
+        let Some(table) = self.capabilities.get(&parent).cloned() else {
+            return;
+        };
+        for endpoint in table.endpoints() {
+            if let Some(channel) = self.get_channel_mut(endpoint.channel_id) {
+                channel.refs[endpoint.side] += 1;
+            }
+        }
+        self.capabilities.insert(child, table);
+    }
+
+    /// プロセスが終了したときに、持っているケイパビリティをすべて閉じる
+    ///
+    /// そのプロセスが名前サービスに登録したサービスも削除する。
+    pub fn release_process(&mut self, pid: u64) -> Vec<ClosedEndpoint> {
+        let mut endpoints: Vec<Endpoint> = self.capabilities.remove(&pid)
+            .map(|table| table.endpoints().collect())
+            .unwrap_or_default();
+        self.services.retain(|name, service| {
+            if service.owner != pid {
+                return true;
+            }
+            crate::println!("IPC: Service \"{}\" unregistered", name);
+            endpoints.push(service.endpoint);
+            false
+        });
+        self.release(endpoints)
+    }
+
+    /// `pid`のスロットの端点を名前サービスに`name`で登録する
+    ///
+    /// 名前サービスは端点のケイパビリティを1つ持ち、検索したプロセスに複製して渡す。
+    /// 登録したプロセスはケイパビリティを持ったままで、ふつうは反対側の端点で
+    /// 呼び出しを待つ。検索したプロセスは名前を信頼して接続するので、ほかの
+    /// サービスになりすませないよう、登録できるのは特権を持つプロセスだけ
+    /// （持たなければ`AccessDenied`）。
+    pub fn register_service(&mut self, pid: u64, name: &str, slot: u64, policy: ServicePolicy, credentials: Credentials) -> Result<(), IpcError> {
+        if !credentials.privileged {
+            return Err(IpcError::AccessDenied);
+        }
+        let endpoint = self.resolve(pid, slot)?;
+        if self.services.contains_key(name) {
+            return Err(IpcError::ServiceExists);
+        }
+        let channel = self.get_channel_mut(endpoint.channel_id).ok_or(IpcError::ChannelNotFound)?;
+        channel.refs[endpoint.side] += 1;
+        self.services.insert(String::from(name), Service { owner: pid, endpoint, policy, session_id: credentials.session_id });
+        Ok(())
+    }
+
+    /// 名前サービスで`name`を検索し、その端点のケイパビリティを`pid`に渡す
+    ///
+    /// 渡したスロット番号を返す。`credentials`がサービスのポリシーを満たさなければ
+    /// `AccessDenied`、登録がなければ`ServiceNotFound`。
+    pub fn lookup_service(&mut self, pid: u64, name: &str, credentials: Credentials) -> Result<u64, IpcError> {
+        let service = self.services.get(name).ok_or(IpcError::ServiceNotFound)?;
+        if !service.allows(credentials) {
+            return Err(IpcError::AccessDenied);
+        }
+        let endpoint = service.endpoint;
+        let slot = self.capabilities.entry(pid).or_default().insert(endpoint)?;
+        if let Some(channel) = self.get_channel_mut(endpoint.channel_id) {
+            channel.refs[endpoint.side] += 1;
+        }
+        Ok(slot)
+    }
+
+    // ケイパビリティを手放す。最後の1つなら端点を閉じ、宛てのメッセージを捨てる。
+    // 捨てたメッセージに載っていた端点も手放し、両側とも閉じたチャンネルは削除する
+    fn release(&mut self, mut pending: Vec<Endpoint>) -> Vec<ClosedEndpoint> {
+        let mut closed = Vec::new();
+        while let Some(endpoint) = pending.pop() {
+            let Some(pos) = self.channels.iter().position(|c| c.id == endpoint.channel_id) else {
+                continue;
+            };
+            let channel = &mut self.channels[pos];
+            channel.refs[endpoint.side] = channel.refs[endpoint.side].saturating_sub(1);
+            if channel.is_open(endpoint.side) {
+                continue;
+            }
+            let dropped = core::mem::take(&mut channel.queues[endpoint.side]);
+            pending.extend(dropped.iter().filter_map(|m| m.capability));
+            if !channel.is_open(endpoint.side ^ 1) {
+                self.channels.remove(pos);
+                crate::println!("IPC: Channel {} closed", endpoint.channel_id);
+            }
+            closed.push(ClosedEndpoint { endpoint, peer_holders: self.holders_of(endpoint.peer()) });
+        }
+        closed
     }
 }
 
@@ -474,81 +932,605 @@ pub trait IpcPageTableOps {
     fn flush_tlb_entry(&mut self, virt_addr: VirtAddr);
 
     /// Verify that a process owns a physical page
+    ///
+    /// The page must be mapped with at least the permissions in `flags`.
+    /// Returns the physical address of the page.
     fn verify_ownership(
         &self,
         pid: u64,
         virt_addr: VirtAddr,
+        flags: PageTableFlags,
     ) -> KernelResult<PhysAddr>;
 }
 
+/// スケジューラが持つプロセスのアドレス空間に対するページテーブル操作
+///
+/// PIDはスレッドグループのID（アドレス空間はスレッド間で共有している）。
+/// 送信者のページは、コピーオンライトを解決してから送信者専用のフレームを渡す。
+impl IpcPageTableOps for Scheduler {
+    fn map_memory(
+        &mut self,
+        target_pid: u64,
+        virt_addr: VirtAddr,
+        phys_frames: &[PhysFrame],
+        flags: PageTableFlags,
+    ) -> KernelResult<()> {
+        let process = match self.processes.iter_mut().find(|p| p.id == target_pid && p.state != ProcessState::Zombie) {
+            Some(process) => process,
+            None => return kerror!(IpcError::InvalidProcess),
+        };
+        if process.address_space.map_frames(virt_addr.as_u64(), phys_frames, mmap::flags_to_prot(flags)).is_err() {
+            return kerror!(IpcError::MappingFailed);
+        }
+        process.stats.memory_used = process.address_space.total_size();
+        Ok(())
+    }
+
+    fn unmap_memory(
+        &mut self,
+        target_pid: u64,
+        virt_addr: VirtAddr,
+        page_count: usize,
+    ) -> KernelResult<()> {
+        let process = match self.processes.iter_mut().find(|p| p.id == target_pid && p.state != ProcessState::Zombie) {
+            Some(process) => process,
+            None => return kerror!(IpcError::InvalidProcess),
+        };
+        process.address_space.unmap(virt_addr.as_u64(), page_count as u64 * mmap::PAGE_SIZE);
+        process.stats.memory_used = process.address_space.total_size();
+        Ok(())
+    }
+
+    fn flush_tlb_entry(&mut self, virt_addr: VirtAddr) {
+        paging::shootdown_tlb_entry(virt_addr);
+    }
+
+    fn verify_ownership(
+        &self,
+        pid: u64,
+        virt_addr: VirtAddr,
+        flags: PageTableFlags,
+    ) -> KernelResult<PhysAddr> {
+        let process = match self.processes.iter().find(|p| p.id == pid && p.state != ProcessState::Zombie) {
+            Some(process) => process,
+            None => return kerror!(IpcError::InvalidProcess),
+        };
+        let space = &process.address_space;
+        let vma = match space.find_vma(virt_addr.as_u64()) {
+            Some(vma) => vma,
+            None => return kerror!(IpcError::InvalidAddress),
+        };
+        let required = mmap::flags_to_prot(flags);
+        if required == mmap::PROT_NONE || vma.prot & required != required {
+            return kerror!(IpcError::AccessDenied);
+        }
+
+        // 未割り当てのページは割り当て、書き込めるページのCOWは解決しておく。
+        // そうしないと送信者が後で書き込んだときに別のフレームに移ってしまう
+        let access = if vma.allows(Access::Write) { Access::Write } else { Access::Read };
+        if !space.handle_fault(virt_addr, access) {
+            return kerror!(IpcError::MappingFailed);
+        }
+        match paging::user_page_frame(space.page_table_frame(), Page::containing_address(virt_addr)) {
+            Some(frame) => Ok(frame.start_address()),
+            None => kerror!(IpcError::InvalidAddress),
+        }
+    }
+}
+
 /// IPCシステムコールハンドラ
 /// 
 /// These are the primary IPC system calls exposed to user processes.
 /// All operations are mediated through this module to ensure security.
 pub mod syscalls {
     use super::*;
+    use crate::syscall::{self, get_current_process_id, SyscallArgs, SyscallFrame};
+    use crate::memory::uaccess;
+    use crate::memory::vma::{self, Access, AddressSpace};
+    use crate::syscall::table::{ArgKind, SyscallEntry, SyscallResult, SyscallTable};
+    use crate::error::Errno;
+    use crate::process::scheduler::{ReplyWait, SCHEDULER};
+
+    /// IPC系システムコールを登録
+    pub fn register_syscalls(table: &mut SyscallTable) -> KernelResult<()> {
+        table.register(SyscallEntry::new(syscall::SYS_CREATE_CHANNEL, "create_channel", sys_create_channel,
+            &[ArgKind::UserPtr]))?;
+        table.register(SyscallEntry::new(syscall::SYS_SEND_MESSAGE, "send_message", sys_send_message,
+            &[ArgKind::Capability, ArgKind::Flags, ArgKind::UserPtr, ArgKind::Size, ArgKind::Flags, ArgKind::Value]))?;
+        table.register(SyscallEntry::new(syscall::SYS_RECEIVE_MESSAGE, "receive_message", sys_receive_message,
+            &[ArgKind::Capability, ArgKind::UserPtr, ArgKind::Size, ArgKind::Flags, ArgKind::UserPtr]))?;
+        table.register(SyscallEntry::new(syscall::SYS_CREATE_MEMORY_HANDLE, "create_memory_handle", sys_create_memory_handle,
+            &[ArgKind::UserPtr, ArgKind::Size, ArgKind::Flags, ArgKind::Flags]))?;
+        table.register(SyscallEntry::new(syscall::SYS_TRANSFER_MEMORY, "transfer_memory", sys_transfer_memory,
+            &[ArgKind::HandleId, ArgKind::Pid]))?;
+        table.register(SyscallEntry::new(syscall::SYS_RECEIVE_MEMORY_HANDLE, "receive_memory_handle", sys_receive_memory_handle,
+            &[ArgKind::HandleId]))?;
+        table.register(SyscallEntry::new(syscall::SYS_IPC_CALL, "ipc_call", sys_ipc_call,
+            &[ArgKind::Capability, ArgKind::Value, ArgKind::Value, ArgKind::Value, ArgKind::Value, ArgKind::Value]))?;
+        table.register(SyscallEntry::new(syscall::SYS_IPC_REPLY_WAIT, "ipc_reply_wait", sys_ipc_reply_wait,
+            &[ArgKind::Capability, ArgKind::Value, ArgKind::Value, ArgKind::Value, ArgKind::Value, ArgKind::Value]))?;
+        table.register(SyscallEntry::new(syscall::SYS_CLOSE_CHANNEL, "close_channel", sys_close_channel,
+            &[ArgKind::Capability]))?;
+        table.register(SyscallEntry::new(syscall::SYS_REGISTER_SERVICE, "register_service", sys_register_service,
+            &[ArgKind::UserPtr, ArgKind::Size, ArgKind::Capability, ArgKind::Flags]))?;
+        table.register(SyscallEntry::new(syscall::SYS_LOOKUP_SERVICE, "lookup_service", sys_lookup_service,
+            &[ArgKind::UserPtr, ArgKind::Size, ArgKind::Flags]))?;
+        Ok(())
+    }
 
-    /// 現在のプロセスとターゲットプロセス間に新しいIPCチャンネルを作成
-    /// 成功時にチャンネルIDを返す
-    /// 
-    /// # Arguments
-    /// - `target_pid`: PID of the process to create channel with
+    // sys_create_channel: IPCチャネルの作成
+    // 引数: RDI=endpoints_ptr（両端のケイパビリティのスロット番号を u64[2] で書き込む）
+    fn sys_create_channel(args: &SyscallArgs) -> SyscallResult {
+        let current_pid = get_current_thread_group_id();
+        let endpoints_ptr = args.arg1;
+
+        // チャンネルを作ってから書き込みに失敗しないよう、先に検証する
+        uaccess::access_ok(endpoints_ptr, core::mem::size_of::<[u64; 2]>(), true)?;
+
+        crate::println!("IPC: Process {} creating channel", current_pid);
+        match create_channel() {
+            Ok((first, second)) => {
+                crate::println!("IPC: Channel created with capabilities {} and {}", first, second);
+                uaccess::put_user(endpoints_ptr, &[first, second])?;
+                Ok(0)
+            }
+            Err(e) => {
+                crate::println!("IPC: Channel creation failed: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+    // sys_send_message: メッセージ送信
+    // 引数: RDI=slot, RSI=msg_type, RDX=data_ptr, R10=data_len, R8=flags, R9=transfer_slot
+    // キューが満杯なら相手が受信するまで待つ（IPC_NONBLOCKならEAGAIN）
+    // IPC_TRANSFER_CAPABILITYを指定すると、R9のケイパビリティを相手に移す
+    fn sys_send_message(args: &SyscallArgs) -> SyscallResult {
+        let current_pid = get_current_thread_group_id();
+        let slot = args.arg1;
+        let msg_type = args.arg2 as u32;
+        let data_ptr = args.arg3;
+        let data_len = args.arg4;
+        let flags = args.arg5;
+
+        if flags & !(IPC_NONBLOCK | IPC_TRANSFER_CAPABILITY) != 0 {
+            return Err(Errno::EINVAL);
+        }
+        let transfer = (flags & IPC_TRANSFER_CAPABILITY != 0).then_some(args.arg6);
+
+        if data_len > 256 { // IPCメッセージの最大サイズ
+            crate::println!("SECURITY: Message too large: {}", data_len);
+            return Err(IpcError::MessageTooLarge.into());
+        }
+
+        // セキュリティ：ページテーブルを確認しながらカーネル側にコピー
+        let mut data = [0u8; 256];
+        let data_slice = &mut data[..data_len as usize];
+        if let Err(errno) = uaccess::copy_from_user(data_slice, data_ptr) {
+            crate::println!("SECURITY: Invalid data pointer in send_message: {:#x}", data_ptr);
+            return Err(errno);
+        }
+
+        crate::println!("IPC: Process {} sending message through capability {}, type {}, len {}", current_pid, slot, msg_type, data_len);
+        match send_message(slot, msg_type, data_slice, transfer) {
+            Ok(_) => {
+                crate::println!("IPC: Message sent successfully");
+                Ok(0) // 成功
+            }
+            Err(IpcError::ChannelFull) if flags & IPC_NONBLOCK == 0 => {
+                // 相手の受信で起こされたら送信をやり直す
+                let endpoint = CHANNEL_REGISTRY.lock().resolve(current_pid, slot)?;
+                block_current(WaitReason::IpcSend(endpoint.key()))
+            }
+            Err(e) => {
+                crate::println!("IPC: Message send failed: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+    // sys_receive_message: メッセージ受信
+    // 引数: RDI=slot, RSI=buffer_ptr, RDX=buffer_size, R10=flags, R8=capability_ptr
+    // 戻り値: 受信したメッセージのサイズ、または-errno
+    // メッセージがなければ届くまで待つ（IPC_NONBLOCKなら-EAGAIN、シグナルで-EINTR）
+    // capability_ptrがNULLでなければ、メッセージに載っていたケイパビリティの
+    // スロット番号（なければNO_CAPABILITY）を書き込む。NULLのときにケイパビリティの
+    // 載ったメッセージが先頭にあれば、取り出さずに-EINVALを返す
+    fn sys_receive_message(args: &SyscallArgs) -> SyscallResult {
+        let current_pid = get_current_thread_group_id();
+        let slot = args.arg1;
+        let buffer_ptr = args.arg2;
+        let buffer_size = args.arg3;
+        let flags = args.arg4;
+        let capability_ptr = args.arg5;
+
+        if flags & !IPC_NONBLOCK != 0 {
+            return Err(Errno::EINVAL);
+        }
+
+        if buffer_size > 256 { // IPCメッセージの最大サイズ
+            crate::println!("SECURITY: Buffer too large: {}", buffer_size);
+            return Err(Errno::EINVAL);
+        }
+
+        // セキュリティ：メッセージを取り出す前に書き込み先を検証
+        if let Err(errno) = uaccess::access_ok(buffer_ptr, buffer_size as usize, true) {
+            crate::println!("SECURITY: Invalid buffer pointer in receive_message: {:#x}", buffer_ptr);
+            return Err(errno);
+        }
+        if capability_ptr != 0 {
+            uaccess::access_ok(capability_ptr, core::mem::size_of::<u64>(), true)?;
+        }
+
+        crate::println!("IPC: Process {} receiving message through capability {}", current_pid, slot);
+        match receive_message(slot, capability_ptr != 0) {
+            Ok(Some((message, capability))) => {
+                // メッセージを受信したらバッファにコピー
+                let copy_len = core::cmp::min(message.data_len, buffer_size as usize);
+                uaccess::copy_to_user(buffer_ptr, &message.data[..copy_len])?;
+                if capability_ptr != 0 {
+                    uaccess::put_user(capability_ptr, &capability.unwrap_or(NO_CAPABILITY))?;
+                }
+                crate::println!("IPC: Message received, len {}", copy_len);
+                Ok(copy_len as u64) // コピーしたバイト数を返す
+            }
+            Ok(None) if flags & IPC_NONBLOCK == 0 => {
+                // 相手の送信で起こされたら受信をやり直す
+                let endpoint = CHANNEL_REGISTRY.lock().resolve(current_pid, slot)?;
+                block_current(WaitReason::IpcReceive(endpoint.key()))
+            }
+            Ok(None) => {
+                crate::println!("IPC: No message available");
+                Err(IpcError::NoMessage.into()) // メッセージなし
+            }
+            Err(e) => {
+                crate::println!("IPC: Message receive failed: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+    // sys_ipc_call: 同期IPCの呼び出し
+    // 引数: RDI=slot, RSI=label, RDX, R10, R8, R9=メッセージ
+    // 戻り値: 0（RSI, RDX, R10, R8, R9に返信が入る）、または-errno
+    // 相手がipc_reply_waitで待っていれば、スケジューラを通さずそのスレッドに
+    // 切り替え、返信するまで優先度を貸す。シグナルで中断されると-EINTR
+    fn sys_ipc_call(args: &SyscallArgs) -> SyscallResult {
+        let (server_endpoint, servers) = {
+            let registry = CHANNEL_REGISTRY.lock();
+            let peer = registry.resolve(get_current_thread_group_id(), args.arg1)?.peer();
+            (peer, registry.holders_of(peer))
+        };
+
+        let frame = unsafe { &*(args.context_ptr as *const SyscallFrame) };
+        let mut sched = SCHEDULER.lock();
+        if let Some(tid) = sched.ipc_call(get_current_process_id(), server_endpoint.key(), &servers, frame)? {
+            sched.switch_on_return(tid);
+        }
+        // RAXは返信（またはエラー）で上書きされる
+        Ok(0)
+    }
+
+    // sys_ipc_reply_wait: 同期IPCの返信と次の呼び出しの受け取り
+    // 引数: RDI=slot, RSI=label, RDX, R10, R8, R9=前の呼び出しへの返信
+    // 戻り値: 呼び出し元のPID（RSI, RDX, R10, R8, R9にメッセージが入る）、または-errno
+    // 呼び出しがなければ届くまで待つ。待つときは返信した相手に直接切り替える
+    fn sys_ipc_reply_wait(args: &SyscallArgs) -> SyscallResult {
+        let endpoint = CHANNEL_REGISTRY.lock().resolve(get_current_thread_group_id(), args.arg1)?;
+
+        let frame = unsafe { &mut *(args.context_ptr as *mut SyscallFrame) };
+        let mut sched = SCHEDULER.lock();
+        match sched.ipc_reply_wait(get_current_process_id(), endpoint.key(), frame)? {
+            ReplyWait::Received(caller) => Ok(caller),
+            ReplyWait::Blocked(replied) => {
+                if let Some(tid) = replied {
+                    sched.switch_on_return(tid);
+                }
+                // RAXは次の呼び出しで上書きされる
+                Ok(0)
+            }
+        }
+    }
+
+    // sys_close_channel: チャンネルの端点のケイパビリティを閉じる
+    // 引数: RDI=slot
+    // 最後のケイパビリティなら端点が閉じ、相手の送受信はECONNREFUSEDになる
+    fn sys_close_channel(args: &SyscallArgs) -> SyscallResult {
+        close_channel(args.arg1)?;
+        Ok(0)
+    }
+
+    // sys_register_service: 名前サービスにサービスを登録
+    // 引数: RDI=name_ptr, RSI=name_len, RDX=slot, R10=policy（SERVICE_*）
+    // クライアントは検索でslotの端点のケイパビリティを受け取る
+    // 特権を持たないプロセスは登録できない（-EACCES）
+    fn sys_register_service(args: &SyscallArgs) -> SyscallResult {
+        let policy = ServicePolicy::from_raw(args.arg4).ok_or(Errno::EINVAL)?;
+        let name = copy_service_name(args.arg1, args.arg2)?;
+        register_service(&name, args.arg3, policy)?;
+        Ok(0)
+    }
+
+    // sys_lookup_service: 名前サービスでサービスを検索
+    // 引数: RDI=name_ptr, RSI=name_len, RDX=flags
+    // 戻り値: サービスの端点のケイパビリティのスロット番号、または-errno
+    // 登録されていなければ登録されるまで待つ（IPC_NONBLOCKなら-ENOENT）
+    // 待っているあいだにハンドラのあるシグナルが届けば-EINTR（SA_RESTARTならやり直す）
+    fn sys_lookup_service(args: &SyscallArgs) -> SyscallResult {
+        let flags = args.arg3;
+        if flags & !IPC_NONBLOCK != 0 {
+            return Err(Errno::EINVAL);
+        }
+        let name = copy_service_name(args.arg1, args.arg2)?;
+        match lookup_service(&name) {
+            Ok(slot) => Ok(slot),
+            Err(IpcError::ServiceNotFound) if flags & IPC_NONBLOCK == 0 => {
+                // 登録で起こされたら検索をやり直す
+                block_current(WaitReason::ServiceLookup)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // ユーザー空間のサービス名をコピーする（UTF-8で1..=MAX_SERVICE_NAMEバイト）
+    fn copy_service_name(name_ptr: u64, name_len: u64) -> Result<String, Errno> {
+        if name_len == 0 {
+            return Err(Errno::EINVAL);
+        }
+        if name_len > MAX_SERVICE_NAME as u64 {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        let mut buffer = [0u8; MAX_SERVICE_NAME];
+        let bytes = &mut buffer[..name_len as usize];
+        uaccess::copy_from_user(bytes, name_ptr)?;
+        core::str::from_utf8(bytes).map(String::from).map_err(|_| Errno::EINVAL)
+    }
+
+    // 現在のスレッドを`reason`で待たせる。起こされるとシステムコールを
+    // 最初からやり直す。シグナルで起こされてハンドラが動く場合は、
+    // SA_RESTARTがあればやり直し、なければ-EINTRで戻る（deliver_signals）
+    fn block_current(reason: WaitReason) -> SyscallResult {
+        let mut sched = SCHEDULER.lock();
+        let current = sched.current_process_mut().ok_or(Errno::ESRCH)?;
+        current.state = ProcessState::Waiting(reason);
+        Err(Errno::ERESTARTSYS)
+    }
+
+    // sys_create_memory_handle: メモリハンドル作成
+    // 引数: RDI=start_addr, RSI=size, RDX=rights, R10=mode
+    fn sys_create_memory_handle(args: &SyscallArgs) -> SyscallResult {
+        let start_addr = VirtAddr::new(args.arg1);
+        let size = args.arg2 as usize;
+        let rights = match args.arg3 {
+            0 => AccessRights::ReadOnly,
+            1 => AccessRights::ReadWrite,
+            2 => AccessRights::Execute,
+            _ => return Err(Errno::EINVAL),
+        };
+        let mode = match args.arg4 {
+            0 => TransferMode::Ownership,
+            1 => TransferMode::Shared,
+            2 => TransferMode::Exclusive,
+            _ => return Err(Errno::EINVAL),
+        };
+        if size == 0 {
+            return Err(Errno::EINVAL);
+        }
+
+        uaccess::check_user_range(args.arg1, size)?;
+        let address_space = vma::current().ok_or(Errno::EFAULT)?;
+        check_handle_range(&address_space, args.arg1, args.arg1 + size as u64, rights)?;
+
+        create_memory_handle(start_addr, size, rights, mode).map_err(Into::into)
+    }
+
+    /// ハンドルにする範囲が`address_space`のVMAで隙間なく覆われていて、
+    /// 与える権限をすべてのVMAが許可しているか確認する
+    pub fn check_handle_range(address_space: &AddressSpace, start: u64, end: u64, rights: AccessRights) -> Result<(), Errno> {
+        let required: &[Access] = match rights {
+            AccessRights::ReadOnly => &[Access::Read],
+            AccessRights::ReadWrite => &[Access::Read, Access::Write],
+            AccessRights::Execute => &[Access::Execute],
+            AccessRights::None => &[],
+        };
+
+        let mut addr = start;
+        while addr < end {
+            let area = address_space.find_vma(addr).ok_or(Errno::EFAULT)?;
+            if !required.iter().all(|&access| area.allows(access)) {
+                return Err(Errno::EACCES);
+            }
+            addr = area.end;
+        }
+        Ok(())
+    }
+
+    // sys_transfer_memory: メモリハンドル転送
+    // 引数: RDI=handle_id, RSI=target_pid
+    fn sys_transfer_memory(args: &SyscallArgs) -> SyscallResult {
+        let current_pid = get_current_thread_group_id();
+        let handle_id = args.arg1;
+        let target_pid = args.arg2;
+
+        crate::println!("MEMORY IPC: Process {} transferring handle {} to PID {}", current_pid, handle_id, target_pid);
+        match transfer_memory(handle_id, target_pid) {
+            Ok(()) => {
+                crate::println!("MEMORY IPC: Handle {} transferred successfully", handle_id);
+                Ok(0) // 成功
+            }
+            Err(e) => {
+                crate::println!("MEMORY IPC: Handle {} transfer failed: {}", handle_id, e);
+                Err(e.into())
+            }
+        }
+    }
+
+    // sys_receive_memory_handle: メモリハンドル受信
+    // 引数: RDI=handle_id
+    // 戻り値: メモリをマップした先頭アドレス
+    fn sys_receive_memory_handle(args: &SyscallArgs) -> SyscallResult {
+        let current_pid = get_current_thread_group_id();
+        let handle_id = args.arg1;
+
+        crate::println!("MEMORY IPC: Process {} receiving handle {}", current_pid, handle_id);
+        match receive_memory_handle(handle_id) {
+            Ok(range) => {
+                crate::println!("MEMORY IPC: Handle {} received successfully, range {:#x}-{:#x}",
+                    handle_id, range.start_addr, range.start_addr + range.size);
+                Ok(range.start_addr.as_u64())
+            }
+            Err(e) => {
+                crate::println!("MEMORY IPC: Handle {} receive failed: {}", handle_id, e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// 新しいIPCチャンネルを作成
     ///
     /// # Returns
-    /// - `Ok(channel_id)`: Successfully created channel
-    /// - `Err(IpcError::InvalidProcess)`: Target process doesn't exist
-    /// - `Err(IpcError::CircularTransfer)`: Cannot create channel with self
+    /// - `Ok((slot1, slot2))`: 両端のケイパビリティのスロット番号（どちらも現在のプロセスが持つ）
+    /// - `Err(IpcError::TooManyCapabilities)`: スロットが足りない
     ///
     /// # Security
-    /// - Validates that both processes exist
-    /// - Prevents channels with non-existent processes
-    pub fn create_channel(target_pid: u64) -> Result<u64, IpcError> {
-        let current_pid = get_current_process_id();
-        
-        // Security: Prevent self-channels
-        if current_pid == target_pid {
-            return Err(IpcError::CircularTransfer);
-        }
-
-        let mut registry = CHANNEL_REGISTRY.lock();
-        registry.create_channel(current_pid, target_pid)
+    /// - チャンネルはPIDではなくケイパビリティで指すので、相手のPIDを推測して
+    ///   勝手にチャンネルを開くことはできない。片方の端点をメッセージで渡した
+    ///   相手（またはforkした子）とだけ通信できる
+    pub fn create_channel() -> Result<(u64, u64), IpcError> {
+        let current_pid = get_current_thread_group_id();
+        CHANNEL_REGISTRY.lock().create_channel(current_pid)
     }
 
     /// チャンネルを介してメッセージを送信
     /// 
     /// # Arguments
-    /// - `channel_id`: Channel to send through
+    /// - `slot`: 送信に使う端点のケイパビリティ
     /// - `msg_type`: Application-defined message type
     /// - `data`: Message payload (up to 256 bytes)
+    /// - `transfer`: メッセージに載せて相手に移すケイパビリティ
     ///
     /// # Returns
     /// - `Ok(())`: Message successfully queued
-    /// - `Err(IpcError::ChannelNotFound)`: Channel doesn't exist
-    /// - `Err(IpcError::InvalidSender)`: Caller isn't an endpoint
+    /// - `Err(IpcError::InvalidChannelId)`: ケイパビリティを持っていない
+    /// - `Err(IpcError::ConnectionRefused)`: 相手側の端点が閉じている
     /// - `Err(IpcError::ChannelFull)`: Message queue is full
-    pub fn send_message(channel_id: u64, msg_type: u32, data: &[u8]) -> Result<(), IpcError> {
-        let current_pid = get_current_process_id();
+    ///
+    /// 受信を待っている相手のスレッドを起こす。
+    pub fn send_message(slot: u64, msg_type: u32, data: &[u8], transfer: Option<u64>) -> Result<(), IpcError> {
+        let current_pid = get_current_thread_group_id();
         let message = Message::new(current_pid, msg_type, data);
 
-        let mut registry = CHANNEL_REGISTRY.lock();
-        if let Some(channel) = registry.get_channel_mut(channel_id) {
-            channel.send(current_pid, message)
-        } else {
-            Err(IpcError::ChannelNotFound)
+        let (peer, holders) = {
+            let mut registry = CHANNEL_REGISTRY.lock();
+            let peer = registry.resolve(current_pid, slot)?.peer();
+            registry.send(current_pid, slot, message, transfer)?;
+            (peer, registry.holders_of(peer))
+        };
+        let mut sched = SCHEDULER.lock();
+        for holder in holders {
+            sched.wake_waiters(holder, WaitReason::IpcReceive(peer.key()));
         }
+        Ok(())
     }
 
     /// チャンネルからメッセージを受信（非ブロッキング）
-    /// 利用可能なメッセージがない場合はNoneを返す.
-    /// In a real implementation, this would be blocking or use async/await.
-    pub fn receive_message(channel_id: u64) -> Result<Option<Message>, IpcError> {
-        let current_pid = get_current_process_id();
-
-        let mut registry = CHANNEL_REGISTRY.lock();
-        if let Some(channel) = registry.get_channel_mut(channel_id) {
-            Ok(channel.receive(current_pid))
-        } else {
-            Err(IpcError::ChannelNotFound)
+    /// 利用可能なメッセージがない場合はNoneを返す。待つかどうかは呼び出し元が決める
+    /// （システムコールはIPC_NONBLOCKがなければ`WaitReason::IpcReceive`で待つ）。
+    /// メッセージにケイパビリティが載っていれば、現在のプロセスに入れたスロット番号も返す
+    /// （`accept_capability`が偽なら、そのメッセージはキューに残してエラーにする）。
+    ///
+    /// 受信してキューに空きができたら、送信を待っている相手のスレッドを起こす。
+    pub fn receive_message(slot: u64, accept_capability: bool) -> Result<Option<(Message, Option<u64>)>, IpcError> {
+        let current_pid = get_current_thread_group_id();
+
+        let (received, peer, holders) = {
+            let mut registry = CHANNEL_REGISTRY.lock();
+            let peer = registry.resolve(current_pid, slot)?.peer();
+            (registry.receive(current_pid, slot, accept_capability)?, peer, registry.holders_of(peer))
+        };
+        if received.is_some() {
+            let mut sched = SCHEDULER.lock();
+            for holder in holders {
+                sched.wake_waiters(holder, WaitReason::IpcSend(peer.key()));
+            }
+        }
+        Ok(received)
+    }
+
+    /// チャンネルの端点のケイパビリティを閉じる
+    pub fn close_channel(slot: u64) -> Result<(), IpcError> {
+        let current_pid = get_current_thread_group_id();
+        let closed = CHANNEL_REGISTRY.lock().close(current_pid, slot)?;
+        notify_closed(closed);
+        Ok(())
+    }
+
+    /// 名前サービスにサービスを登録し、登録を待っている検索を起こす
+    ///
+    /// # Arguments
+    /// - `name`: サービス名（例: "console"）
+    /// - `slot`: クライアントに渡す端点のケイパビリティ
+    /// - `policy`: 検索できるプロセス
+    ///
+    /// # Returns
+    /// - `Err(IpcError::ServiceExists)`: 同じ名前がすでに登録されている
+    /// - `Err(IpcError::AccessDenied)`: 現在のプロセスが特権を持たない
+    ///
+    /// 登録は、登録したプロセスが終了すると自動的に消える。
+    pub fn register_service(name: &str, slot: u64, policy: ServicePolicy) -> Result<(), IpcError> {
+        let current_pid = get_current_thread_group_id();
+        let credentials = current_credentials()?;
+        CHANNEL_REGISTRY.lock().register_service(current_pid, name, slot, policy, credentials)?;
+        crate::println!("IPC: Process {} registered service \"{}\"", current_pid, name);
+
+        SCHEDULER.lock().wake_all_waiters(WaitReason::ServiceLookup);
+        Ok(())
+    }
+
+    /// 名前サービスでサービスを検索し、その端点のケイパビリティを受け取る
+    ///
+    /// # Returns
+    /// - `Ok(slot)`: 受け取ったケイパビリティのスロット番号
+    /// - `Err(IpcError::ServiceNotFound)`: 登録されていない
+    /// - `Err(IpcError::AccessDenied)`: サービスのポリシーで検索が許されていない
+    pub fn lookup_service(name: &str) -> Result<u64, IpcError> {
+        let current_pid = get_current_thread_group_id();
+        let credentials = current_credentials()?;
+        CHANNEL_REGISTRY.lock().lookup_service(current_pid, name, credentials)
+    }
+
+    // 現在のプロセスのセッションと特権（リーダーが持つ）
+    fn current_credentials() -> Result<Credentials, IpcError> {
+        let mut sched = SCHEDULER.lock();
+        let leader = sched.current_leader_mut().ok_or(IpcError::InvalidProcess)?;
+        Ok(Credentials { session_id: leader.session_id, privileged: leader.privileged })
+    }
+
+    /// forkした子に親のチャンネルのケイパビリティを引き継ぐ
+    pub fn inherit_channels(parent_pid: u64, child_pid: u64) {
+        CHANNEL_REGISTRY.lock().inherit(parent_pid, child_pid);
+    }
+
+    /// 終了したプロセスが持っていたチャンネルのケイパビリティをすべて閉じる
+    pub fn release_channels(pid: u64) {
+        let closed = CHANNEL_REGISTRY.lock().release_process(pid);
+        notify_closed(closed);
+    }
+
+    // 閉じた端点の相手側で送受信を待っているスレッドを起こす（やり直すと
+    // ECONNREFUSEDになる）。閉じた端点への同期IPCの呼び出しも諦めさせる
+    fn notify_closed(closed: Vec<ClosedEndpoint>) {
+        if closed.is_empty() {
+            return;
+        }
+        let mut sched = SCHEDULER.lock();
+        for ClosedEndpoint { endpoint, peer_holders } in closed {
+            let peer = endpoint.peer();
+            for holder in peer_holders {
+                sched.wake_waiters(holder, WaitReason::IpcReceive(peer.key()));
+                sched.wake_waiters(holder, WaitReason::IpcSend(peer.key()));
+            }
+            sched.fail_ipc_callers(endpoint.key());
         }
     }
 
@@ -576,7 +1558,7 @@ pub mod syscalls {
         rights: AccessRights,
         mode: TransferMode
     ) -> Result<u64, IpcError> {
-        let current_pid = get_current_process_id();
+        let current_pid = get_current_thread_group_id();
         let range = PageRange::new(start_addr, size);
         
         let mut registry = HANDLE_REGISTRY.lock();
@@ -596,82 +1578,34 @@ pub mod syscalls {
     /// 1. Verify caller owns the handle
     /// 2. Verify target process exists
     /// 3. Prevent circular transfers
-    /// 4. Check that pages are page-table valid
+    /// 4. Check that the pages are mapped in the caller with the granted rights
     ///
     /// # Page table semantics:
     /// - **Ownership mode**: Sender's pages are UNMAPPED after transfer
-    /// - **Shared mode**: Both processes have READ access (sender keeps R/W)
-    /// - **Exclusive mode**: Both lose access until transfer completes
-    ///
-    /// # TODO for full implementation
-    /// - Actual page table unmapping for Ownership mode
-    /// - Cross-process page table manipulation
+    /// - **Shared mode**: Both processes map the same frames
+    /// - **Exclusive mode**: Both lose access until the receiver accepts
     pub fn transfer_memory(handle_id: u64, target_pid: u64) -> Result<(), IpcError> {
-        let current_pid = get_current_process_id();
-        
-        // Security: Prevent circular transfers
-        if current_pid == target_pid {
-            return Err(IpcError::CircularTransfer);
-        }
+        let current_pid = get_current_thread_group_id();
 
+        // ロックの順序は HANDLE_REGISTRY → SCHEDULER
         let mut registry = HANDLE_REGISTRY.lock();
-        
-        if let Some(handle) = registry.get_handle_mut(handle_id) {
-            // 現在のプロセスがハンドルを所有していることを検証
-            if handle.owner_pid != current_pid {
-                return Err(IpcError::AccessDenied);
-            }
-            
-            // Verify handle is valid
-            if !handle.validate() {
-                return Err(IpcError::InvalidRange);
-            }
-            
-            // Implement actual page table operations based on transfer mode
-            // 所有権を転送: unmap from current_pid, map to target_pid
-            // For Shared: keep in current_pid, map to target_pid as read-only
-            // For Exclusive: unmap from current_pid, map to target_pid
-            
-            // Get physical frames for the memory region
-            let mut phys_frames = alloc::vec::Vec::new();
-            let page_count = handle.range.page_count()?;
-            
-            for i in 0..page_count {
-                let virt_addr = VirtAddr::new(handle.range.start_addr.as_u64() + (i * 4096) as u64);
-                
-                // For now, simulate physical frame creation
-                // In a real implementation, we would get the actual physical frames
-                let phys_frame = x86_64::structures::paging::PhysFrame::<x86_64::structures::paging::Size4KiB>::containing_address(
-                    x86_64::PhysAddr::new(0x100000 + (i * 4096) as u64) // Simulated physical address
-                );
-                phys_frames.push(phys_frame);
-            }
-            
-            // Log the transfer operation
-            crate::println!(
-                "IPC: Memory handle {} transfer: PID {} -> PID {} (mode: {:?}, pages: {})",
-                handle_id, current_pid, target_pid, handle.mode, page_count
-            );
-            
-            // For now, just update handle state without actual page table operations
-            // TODO: Implement actual page table operations when memory manager is accessible
-            
-            // Update handle state
-            handle.holder_pid = target_pid;
-            
-            crate::println!(
-                "IPC: Memory handle {} transfer initiated: PID {} -> PID {}",
-                handle_id, current_pid, target_pid
-            );
-            Ok(())
-        } else {
-            Err(IpcError::HandleNotFound)
+        let mut sched = SCHEDULER.lock();
+        if !sched.processes.iter().any(|p| p.id == target_pid && p.state != ProcessState::Zombie) {
+            return Err(IpcError::InvalidProcess);
         }
+        registry.transfer_handle(&mut *sched, handle_id, current_pid, target_pid)?;
+
+        crate::println!(
+            "IPC: Memory handle {} transferred: PID {} -> PID {}",
+            handle_id, current_pid, target_pid
+        );
+        Ok(())
     }
 
     /// メモリハンドルを受信（転送を受け入れ）
     /// 
     /// This completes the zero-copy memory transfer initiated by the sender.
+    /// The sender's frames are mapped at the same virtual address in this process.
     ///
     /// # Arguments
     /// - `handle_id`: Handle being transferred to this process
@@ -680,64 +1614,19 @@ pub mod syscalls {
     /// - `Ok(PageRange)`: Successfully accepted, returns mapped memory region
     /// - `Err(IpcError::AccessDenied)`: Handle not transferred to this process
     /// - `Err(IpcError::HandleNotFound)`: Handle doesn't exist
-    ///
-    /// # TODO for full implementation
-    /// - Verify pages are accessible
-    /// - Install page table entries in current process
-    /// - Handle race conditions with concurrent revokes
+    /// - `Err(IpcError::MappingFailed)`: The range is already in use in this process
     pub fn receive_memory_handle(handle_id: u64) -> Result<PageRange, IpcError> {
-        let current_pid = get_current_process_id();
-        
+        let current_pid = get_current_thread_group_id();
+
         let mut registry = HANDLE_REGISTRY.lock();
-        if let Some(handle) = registry.get_handle_mut(handle_id) {
-            // ハンドルが現在のプロセスに転送されていることを検証
-            if handle.holder_pid != current_pid {
-                return Err(IpcError::AccessDenied);
-            }
-            
-            // Verify handle is valid
-            if !handle.validate() {
-                return Err(IpcError::InvalidRange);
-            }
-            
-            // Install pages in current process's page table
-            // For now, simulate the installation process
-            // In a real implementation, we would:
-            // 1. Get the physical frames from the handle
-            // 2. Map them into the current process's address space
-            // 3. Update the handle's mapping state
-            
-            let page_count = handle.range.page_count()?;
-            
-            // Simulate page table installation
-            for i in 0..page_count {
-                let virt_addr = VirtAddr::new(handle.range.start_addr.as_u64() + (i * 4096) as u64);
-                
-                // In a real implementation, we would:
-                // - Get the physical frame for this virtual address
-                // - Map it into the current process's page table with appropriate flags
-                // - Flush TLB entries
-                
-                crate::println!("IPC: Installing page {:#x} for PID {}", virt_addr.as_u64(), current_pid);
-            }
-            
-            // Update handle state to indicate it's mapped
-            handle.is_mapped = true;
-            handle.holder_virt_addr = Some(handle.range.start_addr);
-            
-            crate::println!(
-                "IPC: Memory handle {} installed for PID {} ({} pages)",
-                handle_id, current_pid, page_count
-            );
-            
-            crate::println!(
-                "IPC: PID {} accepted memory handle {}",
-                current_pid, handle_id
-            );
-            Ok(handle.range)
-        } else {
-            Err(IpcError::HandleNotFound)
-        }
+        let mut sched = SCHEDULER.lock();
+        let range = registry.accept_handle(&mut *sched, handle_id, current_pid)?;
+
+        crate::println!(
+            "IPC: PID {} accepted memory handle {} ({} pages)",
+            current_pid, handle_id, range.size / 4096
+        );
+        Ok(range)
     }
 
     /// メモリハンドルを無効化
@@ -750,65 +1639,18 @@ pub mod syscalls {
     ///
     /// # Security
     /// - Only the owner (creator) can revoke
-    /// - Revocation is immediate
-    ///
-    /// # TODO for full implementation
-    /// - Unmap pages from the holder's address space
-    /// - Flush TLB entries
-    /// - Handle case where holder is currently running
+    /// - Revocation is immediate: the holder's pages are unmapped and the TLB flushed
     pub fn revoke_memory_handle(handle_id: u64) -> Result<(), IpcError> {
-        let current_pid = get_current_process_id();
-        
+        let current_pid = get_current_thread_group_id();
+
         let mut registry = HANDLE_REGISTRY.lock();
-        if let Some(handle) = registry.get_handle_mut(handle_id) {
-            // 所有者のみが無効化可能
-            if handle.owner_pid != current_pid {
-                return Err(IpcError::AccessDenied);
-            }
-            
-            let holder_pid = handle.holder_pid;
-            handle.revoke();
-            
-            // Unmap from holder's address space
-            // For now, simulate the unmapping process
-            // In a real implementation, we would:
-            // 1. Get the holder's page table
-            // 2. Unmap all pages in the memory range
-            // 3. Flush TLB entries for the holder
-            // 4. Handle the case where the holder is currently executing
-            
-            if handle.is_mapped {
-                let page_count = handle.range.page_count()?;
-                
-                // Simulate page table unmapping
-                for i in 0..page_count {
-                    let virt_addr = VirtAddr::new(handle.range.start_addr.as_u64() + (i * 4096) as u64);
-                    
-                    // In a real implementation, we would:
-                    // - Unmap the page from the holder's address space
-                    // - Flush TLB entries for the holder process
-                    // - Handle any access violations that might occur
-                    
-                    crate::println!("IPC: Unmapping page {:#x} from PID {}", virt_addr.as_u64(), holder_pid);
-                }
-                
-                // Update handle state
-                handle.is_mapped = false;
-                handle.holder_virt_addr = None;
-                
-                crate::println!(
-                    "IPC: Unmapped {} pages from PID {}",
-                    page_count, holder_pid
-                );
-            }
-            
-            crate::println!(
-                "IPC: Handle {} revoked by PID {} (was held by PID {})",
-                handle_id, current_pid, holder_pid
-            );
-            Ok(())
-        } else {
-            Err(IpcError::HandleNotFound)
-        }
+        let mut sched = SCHEDULER.lock();
+        let holder_pid = registry.revoke_and_unmap(&mut *sched, handle_id, current_pid)?;
+
+        crate::println!(
+            "IPC: Handle {} revoked by PID {} (was held by PID {})",
+            handle_id, current_pid, holder_pid
+        );
+        Ok(())
     }
 }
@@ -12,6 +12,8 @@ pub mod vga_buffer;
 // TODO: 部分的な外部タスク化
 pub mod interrupts;
 pub mod gdt;
+// FPU/SSE/AVXのレジスタ状態
+pub mod fpu;
 // メモリ管理
 pub mod memory;
 pub mod allocator;
@@ -34,10 +36,15 @@ pub mod testing;
 // テストスイート (ブートテスト用に常時利用可能)
 pub mod tests;
 
+/// 割り込み・システムコールなどCPUまわりの初期化
+///
+/// ヒープは初期化しない。呼び出し側は`allocator::init_heap`の後で
+/// `syscall::table::init`を呼んでシステムコールテーブルを構築する。
 pub fn init() {
     serial::init(); // 最初にシリアルポートを初期化（デバッグ用）
     interrupts::init_idt();
     gdt::init();
+    fpu::init();
     syscall::init();
     timer::init(); // タイマーを再有効化
     unsafe { interrupts::PICS.lock().initialize() };
@@ -6,8 +6,35 @@ use x86_64::{
 };
 use bootloader::bootinfo::MemoryRegionType;
 use bootloader::bootinfo::MemoryMap;
+use core::sync::atomic::{AtomicU64, Ordering};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use spin::Mutex;
 
+pub mod mmap;
+pub mod paging;
 pub mod scalable;
+pub mod syscalls;
+pub mod uaccess;
+pub mod vma;
+
+// 物理メモリ全体がマップされている仮想アドレスのオフセット（initで設定）
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+// ブート時のカーネルのL4ページテーブル（initで設定）
+static KERNEL_PAGE_TABLE: AtomicU64 = AtomicU64::new(0);
+
+/// カーネルのL4ページテーブルのフレームを取得
+///
+/// プロセスを実行していないとき（アイドル時やアドレス空間の解放時）に使う。
+pub fn kernel_page_table() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_PAGE_TABLE.load(Ordering::Acquire)))
+}
+
+/// 物理メモリの直接マップのオフセットを取得
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Acquire))
+}
 
 // ブートローダのメモリマップから、使用可能な
 // フレームを返すFrameAllocator
@@ -53,8 +80,108 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 
+// ブート後に全サブシステムで共有する物理フレームアロケータ
+// 解放されたフレームは再利用リストに積み、ブートローダのメモリマップより先に使う
+struct KernelFrameAllocator {
+    boot: BootInfoFrameAllocator,
+    free_frames: Vec<PhysFrame>,
+    // 複数のページテーブルエントリから参照されているフレームの参照数
+    // （2以上のものだけを持ち、載っていないフレームの参照数は1とみなす）
+    shared_frames: BTreeMap<PhysFrame, usize>,
+}
+
+static KERNEL_FRAME_ALLOCATOR: Mutex<Option<KernelFrameAllocator>> = Mutex::new(None);
+
+/// ブート用のフレームアロケータをカーネル全体で共有するアロケータとして登録する
+///
+/// これ以降のフレーム割り当てはすべて`allocate_frame`（または`GlobalFrameAllocator`）
+/// を経由する。同じメモリマップから別のアロケータを作ってはならない。
+pub fn init_frame_allocator(boot: BootInfoFrameAllocator) {
+    *KERNEL_FRAME_ALLOCATOR.lock() = Some(KernelFrameAllocator {
+        boot,
+        free_frames: Vec::new(),
+        shared_frames: BTreeMap::new(),
+    });
+}
+
+/// 物理フレームを1つ割り当てる
+pub fn allocate_frame() -> Option<PhysFrame> {
+    let mut allocator = KERNEL_FRAME_ALLOCATOR.lock();
+    let allocator = allocator.as_mut()?;
+    allocator.free_frames.pop().or_else(|| allocator.boot.allocate_frame())
+}
+
+/// 物理フレームを解放して再利用できるようにする
+pub fn deallocate_frame(frame: PhysFrame) {
+    if let Some(allocator) = KERNEL_FRAME_ALLOCATOR.lock().as_mut() {
+        allocator.free_frames.push(frame);
+    }
+}
+
+/// フレームを参照するページテーブルエントリが1つ増えたことを記録する
+///
+/// 新しい参照数を返す。COW forkで親子がフレームを共有するときに使う。
+pub fn share_frame(frame: PhysFrame) -> usize {
+    let mut allocator = KERNEL_FRAME_ALLOCATOR.lock();
+    match allocator.as_mut() {
+        Some(allocator) => {
+            let count = allocator.shared_frames.entry(frame).or_insert(1);
+            *count += 1;
+            *count
+        }
+        None => 1,
+    }
+}
+
+/// フレームへの参照を1つ手放し、最後の参照であればフレームを解放する
+///
+/// 残りの参照数を返す（0ならフレームは解放された）。
+pub fn release_frame(frame: PhysFrame) -> usize {
+    let mut allocator = KERNEL_FRAME_ALLOCATOR.lock();
+    let allocator = match allocator.as_mut() {
+        Some(allocator) => allocator,
+        None => return 0,
+    };
+    match allocator.shared_frames.get_mut(&frame) {
+        Some(count) => {
+            *count -= 1;
+            let remaining = *count;
+            if remaining <= 1 {
+                allocator.shared_frames.remove(&frame);
+            }
+            remaining
+        }
+        None => {
+            allocator.free_frames.push(frame);
+            0
+        }
+    }
+}
+
+/// フレームの参照数（共有されていなければ1）
+pub fn frame_ref_count(frame: PhysFrame) -> usize {
+    KERNEL_FRAME_ALLOCATOR.lock()
+        .as_ref()
+        .and_then(|allocator| allocator.shared_frames.get(&frame).copied())
+        .unwrap_or(1)
+}
+
+/// 共有フレームアロケータへのハンドル
+///
+/// `FrameAllocator`を要求するAPI（`Mapper::map_to`など）に渡すために使う。
+pub struct GlobalFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        allocate_frame()
+    }
+}
+
 // ページテーブルの初期化
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Release);
+    let (kernel_l4, _) = x86_64::registers::control::Cr3::read();
+    KERNEL_PAGE_TABLE.store(kernel_l4.start_address().as_u64(), Ordering::Release);
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
         OffsetPageTable::new(level_4_table, physical_memory_offset)
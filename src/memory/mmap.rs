@@ -0,0 +1,72 @@
+//! 匿名メモリマッピングの定数と補助関数
+//!
+//! mmap / munmap / mprotect のフラグと、保護フラグからページテーブルの
+//! フラグへの変換。領域そのものは`vma::AddressSpace`が管理する。
+
+use x86_64::structures::paging::PageTableFlags;
+
+use crate::process::elf::USER_STACK_LOWEST;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+// mprotect / mmap の保護フラグ
+pub const PROT_NONE: u64 = 0x0;
+pub const PROT_READ: u64 = 0x1;
+pub const PROT_WRITE: u64 = 0x2;
+pub const PROT_EXEC: u64 = 0x4;
+
+// mmap のフラグ
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+/// マップできる最小のアドレス（NULL参照を捕まえるため先頭は空けておく）
+pub const MMAP_MIN_ADDR: u64 = 0x10000;
+/// アドレスを指定しないmmapが探索を始める位置
+pub const MMAP_BASE: u64 = 0x0000_1000_0000_0000;
+/// mmapで使える領域の終端（ユーザースタック用に予約した領域の下）
+pub const MMAP_END: u64 = USER_STACK_LOWEST;
+
+/// 保護フラグをページテーブルのフラグに変換する
+///
+/// x86_64では書き込みのみ・実行のみのページは作れないので、
+/// PROT_NONE以外は常に読み取り可能になる。
+pub fn prot_to_flags(prot: u64) -> PageTableFlags {
+    if prot == PROT_NONE {
+        return PageTableFlags::empty();
+    }
+    let mut flags = PageTableFlags::PRESENT;
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    flags
+}
+
+/// ページテーブルのフラグを保護フラグに変換する（`prot_to_flags`の逆）
+pub fn flags_to_prot(flags: PageTableFlags) -> u64 {
+    if !flags.contains(PageTableFlags::PRESENT) {
+        return PROT_NONE;
+    }
+    let mut prot = PROT_READ;
+    if flags.contains(PageTableFlags::WRITABLE) {
+        prot |= PROT_WRITE;
+    }
+    if !flags.contains(PageTableFlags::NO_EXECUTE) {
+        prot |= PROT_EXEC;
+    }
+    prot
+}
+
+/// 保護フラグとして有効な値か
+pub fn valid_prot(prot: u64) -> bool {
+    prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) == 0
+}
+
+/// アドレスをページ境界に切り上げる（オーバーフロー時は`None`）
+pub fn page_align_up(value: u64) -> Option<u64> {
+    Some(value.checked_add(PAGE_SIZE - 1)? & !(PAGE_SIZE - 1))
+}
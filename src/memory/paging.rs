@@ -0,0 +1,397 @@
+//! プロセスのページテーブル操作
+//!
+//! fork時のアドレス空間の複製（コピーオンライト）や解放など、現在アクティブで
+//! ないページテーブルも物理メモリの直接マップ経由で操作します。
+
+use core::ops::Range;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{Page, PageTable, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::error::{AllocError, KernelResult};
+use crate::kerror;
+
+/// 物理アドレスにあるページテーブルへの参照を取得
+///
+/// # Safety
+/// `addr`はページテーブルとして使われているフレームでなければならない。
+pub unsafe fn table_at(addr: PhysAddr) -> &'static mut PageTable {
+    let virt = super::physical_memory_offset() + addr.as_u64();
+    unsafe { &mut *virt.as_mut_ptr::<PageTable>() }
+}
+
+/// ゼロクリアした新しいページテーブルを割り当てる
+pub fn allocate_table() -> KernelResult<PhysFrame> {
+    let frame = match super::allocate_frame() {
+        Some(frame) => frame,
+        None => return kerror!(AllocError::OutOfMemory),
+    };
+    unsafe { table_at(frame.start_address()).zero() };
+    Ok(frame)
+}
+
+/// コピーオンライト（COW）で共有しているページを示すフラグ
+///
+/// OSが自由に使えるビット9を使う。このフラグの付いたページはWRITABLEを
+/// 外してあり、書き込みでページフォルトが起きたときに`resolve_cow`が
+/// 書き込んだプロセス専用のコピーを作る。
+pub const COW: PageTableFlags = PageTableFlags::BIT_9;
+
+// 物理フレームの内容をコピーする
+fn copy_frame(src: PhysFrame, dst: PhysFrame) {
+    let offset = super::physical_memory_offset();
+    let src_ptr: *const u8 = (offset + src.start_address().as_u64()).as_ptr();
+    let dst_ptr: *mut u8 = (offset + dst.start_address().as_u64()).as_mut_ptr();
+    unsafe { core::ptr::copy_nonoverlapping(src_ptr, dst_ptr, 4096) };
+}
+
+/// アドレス空間を複製して、新しいL4テーブルのフレームを返す
+///
+/// ユーザーページはコピーせず、コピーオンライトで共有する（書き込み可能な
+/// ページは両方のテーブルで読み取り専用＋`COW`にし、フレームの参照数を増やす）。
+/// カーネル専用のエントリ（上位テーブルを含む）はそのまま共有する。カーネルと
+/// ユーザーが同じ上位テーブルを使っている場合（L4[0]など）は、テーブル自体を
+/// 複製してカーネル側のエントリだけを共有する。
+///
+/// `shared`の範囲（IPCのメモリハンドルで受け取った領域）のページはCOWにせず、
+/// フレームの参照数だけ増やして書き込み可能なまま共有する。
+pub fn clone_address_space(src_l4: PhysFrame, shared: &[Range<u64>]) -> KernelResult<PhysFrame> {
+    let result = clone_table(src_l4.start_address(), 4, 0, shared);
+    // 元のテーブルのエントリも読み取り専用にしたので、古いTLBエントリを捨てる
+    x86_64::instructions::tlb::flush_all();
+    result
+}
+
+// `base`はこのテーブルが受け持つ仮想アドレスの先頭
+fn clone_table(src_addr: PhysAddr, level: u8, base: u64, shared: &[Range<u64>]) -> KernelResult<PhysFrame> {
+    let new_frame = allocate_table()?;
+    let src = unsafe { table_at(src_addr) };
+    let dst = unsafe { table_at(new_frame.start_address()) };
+
+    for index in 0..512 {
+        let entry = &mut src[index];
+        if entry.is_unused() {
+            continue;
+        }
+        let flags = entry.flags();
+        let addr = base + ((index as u64) << (12 + 9 * (level as u64 - 1)));
+
+        // カーネル専用のマッピング、ヒュージページはそのまま共有
+        if !flags.contains(PageTableFlags::USER_ACCESSIBLE) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            dst[index] = entry.clone();
+            continue;
+        }
+
+        if level == 1 {
+            // ユーザーページ：フレームを共有し、書き込み可能なものはCOWにする
+            // （共有メモリの領域は書き込みを互いに見せるので、そのまま共有する）
+            let mut shared_flags = flags;
+            if flags.contains(PageTableFlags::WRITABLE) && !shared.iter().any(|range| range.contains(&addr)) {
+                shared_flags.remove(PageTableFlags::WRITABLE);
+                shared_flags.insert(COW);
+                entry.set_flags(shared_flags);
+            }
+            super::share_frame(PhysFrame::containing_address(entry.addr()));
+            dst[index].set_addr(entry.addr(), shared_flags);
+        } else {
+            // ユーザー空間を含む上位テーブル：再帰的に複製
+            let child = match clone_table(entry.addr(), level - 1, addr, shared) {
+                Ok(child) => child,
+                Err(e) => {
+                    // 途中まで作ったテーブルを片付ける
+                    free_table(new_frame.start_address(), level);
+                    return Err(e);
+                }
+            };
+            dst[index].set_addr(child.start_address(), flags);
+        }
+    }
+
+    Ok(new_frame)
+}
+
+/// ユーザーページを持たない新しいアドレス空間を作る
+///
+/// カーネルのL4エントリを共有する（ユーザーからは見えないようUSER_ACCESSIBLEは
+/// 外す）。ユーザーページは`map_user_page`で追加する。
+pub fn new_address_space() -> KernelResult<PhysFrame> {
+    let new_frame = allocate_table()?;
+    let src = unsafe { table_at(super::kernel_page_table().start_address()) };
+    let dst = unsafe { table_at(new_frame.start_address()) };
+    for (index, entry) in src.iter().enumerate() {
+        if !entry.is_unused() {
+            dst[index].set_addr(entry.addr(), entry.flags() - PageTableFlags::USER_ACCESSIBLE);
+        }
+    }
+    Ok(new_frame)
+}
+
+// 上位テーブルのエントリが、このアドレス空間専用のユーザー用テーブルを指すようにする
+//
+// 未使用なら新しいテーブルを割り当て、カーネルと共有しているテーブル
+// （USER_ACCESSIBLEなし）なら複製してから差し替える。
+fn ensure_user_table(entry: &mut PageTableEntry) -> KernelResult<PhysAddr> {
+    let flags = entry.flags();
+    if flags.contains(PageTableFlags::HUGE_PAGE) {
+        return kerror!(AllocError::AlreadyInUse);
+    }
+    if entry.is_unused() {
+        let table = allocate_table()?;
+        entry.set_addr(table.start_address(),
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+    } else if !flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+        let table = allocate_table()?;
+        copy_frame(PhysFrame::containing_address(entry.addr()), table);
+        entry.set_addr(table.start_address(),
+            flags | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE);
+    }
+    Ok(entry.addr())
+}
+
+/// `l4`のアドレス空間にユーザーページをマップする
+///
+/// `flags`にはPRESENTとUSER_ACCESSIBLEが自動的に加えられる。途中のテーブルは
+/// 必要に応じて割り当て、カーネルと共有しているテーブルは変更しない。
+/// すでにマップされている場合はエラー。
+pub fn map_user_page(l4: PhysFrame, page: Page, frame: PhysFrame, flags: PageTableFlags) -> KernelResult<()> {
+    let addr = page.start_address();
+    if addr.as_u64() >= super::uaccess::USER_SPACE_END {
+        return kerror!(AllocError::InvalidAddress);
+    }
+
+    let mut table_addr = l4.start_address();
+    for index in [addr.p4_index(), addr.p3_index(), addr.p2_index()] {
+        let table = unsafe { table_at(table_addr) };
+        table_addr = ensure_user_table(&mut table[index])?;
+    }
+
+    let table = unsafe { table_at(table_addr) };
+    let entry = &mut table[addr.p1_index()];
+    if !entry.is_unused() {
+        return kerror!(AllocError::AlreadyInUse);
+    }
+    entry.set_addr(frame.start_address(), flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE);
+    Ok(())
+}
+
+/// ゼロクリアした新しいフレームを割り当て、ユーザーページとしてマップする
+///
+/// マップしたフレームを返す（内容は直接マップ経由で書き込める）。
+pub fn map_zeroed_user_page(l4: PhysFrame, page: Page, flags: PageTableFlags) -> KernelResult<PhysFrame> {
+    let frame = match super::allocate_frame() {
+        Some(frame) => frame,
+        None => return kerror!(AllocError::OutOfMemory),
+    };
+    unsafe { table_at(frame.start_address()).zero() };
+    if let Err(e) = map_user_page(l4, page, frame, flags) {
+        super::deallocate_frame(frame);
+        return Err(e);
+    }
+    Ok(frame)
+}
+
+/// 物理フレームへのカーネル側のポインタ（直接マップ経由）
+pub fn frame_ptr(frame: PhysFrame) -> *mut u8 {
+    (super::physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+// `l4`のアドレス空間で`addr`を含むL1エントリを取得する（4KiBページのみ）
+fn user_pte(l4: PhysFrame, addr: VirtAddr) -> Option<&'static mut PageTableEntry> {
+    let indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index()];
+    let mut table = unsafe { table_at(l4.start_address()) };
+    for index in indexes {
+        let entry = &table[index];
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        table = unsafe { table_at(entry.addr()) };
+    }
+    Some(&mut table[addr.p1_index()])
+}
+
+/// `l4`のアドレス空間でユーザーページにマップされているフレームを取得
+pub fn user_page_frame(l4: PhysFrame, page: Page) -> Option<PhysFrame> {
+    let entry = user_pte(l4, page.start_address())?;
+    if entry.flags().contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE) {
+        Some(PhysFrame::containing_address(entry.addr()))
+    } else {
+        None
+    }
+}
+
+/// `l4`のアドレス空間（現在のCR3でなくてもよい）のユーザーメモリに書き込む
+///
+/// 書き込み先はマップ済みでなければならない。ページの権限は確認しない
+/// （カーネルがロード中のイメージを初期化するためのもの）。
+pub fn write_user_memory(l4: PhysFrame, addr: u64, data: &[u8]) -> KernelResult<()> {
+    let mut done = 0usize;
+    while done < data.len() {
+        let user_addr = addr + done as u64;
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(user_addr));
+        let frame = match user_page_frame(l4, page) {
+            Some(frame) => frame,
+            None => return kerror!(AllocError::InvalidAddress),
+        };
+        let offset = (user_addr % 4096) as usize;
+        let chunk = core::cmp::min(4096 - offset, data.len() - done);
+        unsafe {
+            core::ptr::copy_nonoverlapping(data[done..].as_ptr(), frame_ptr(frame).add(offset), chunk);
+        }
+        done += chunk;
+    }
+    Ok(())
+}
+
+/// `l4`のアドレス空間でユーザーページのフラグを取得
+pub fn user_page_flags(l4: PhysFrame, page: Page) -> Option<PageTableFlags> {
+    let entry = user_pte(l4, page.start_address())?;
+    if entry.flags().contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE) {
+        Some(entry.flags())
+    } else {
+        None
+    }
+}
+
+/// マップ済みのユーザーページの権限を広げる（書き込み・実行の許可を加える）
+pub fn merge_user_page_flags(l4: PhysFrame, page: Page, flags: PageTableFlags) {
+    if let Some(entry) = user_pte(l4, page.start_address()) {
+        let mut merged = entry.flags() | (flags & PageTableFlags::WRITABLE);
+        if !flags.contains(PageTableFlags::NO_EXECUTE) {
+            merged.remove(PageTableFlags::NO_EXECUTE);
+        }
+        entry.set_flags(merged);
+    }
+}
+
+// `l4`が現在のアドレス空間なら、`page`の古いTLBエントリを捨てる
+fn flush_if_current(l4: PhysFrame, page: Page) {
+    if Cr3::read().0 == l4 {
+        x86_64::instructions::tlb::flush(page.start_address());
+    }
+}
+
+/// `addr`の古いTLBエントリを、そのページをキャッシュしているかもしれない
+/// すべてのCPUから捨てる
+///
+/// 他のプロセスのページテーブルを書き換えたあとに呼ぶ。ユーザープロセスを
+/// 動かすのはまだBSPだけなので、このCPUで無効化すれば足りる。別のアドレス空間の
+/// エントリはCR3を切り替えたときに捨てられる（PCIDは使っていない）。
+pub fn shootdown_tlb_entry(addr: VirtAddr) {
+    x86_64::instructions::tlb::flush(addr);
+}
+
+/// ユーザーページのマッピングを外し、マップされていたフレームを返す
+///
+/// アクセス不可にしたページ（PRESENTなし）も対象。フレームの参照は
+/// 呼び出し元が`release_frame`で手放す。
+pub fn unmap_user_page(l4: PhysFrame, page: Page) -> Option<PhysFrame> {
+    let entry = user_pte(l4, page.start_address())?;
+    if entry.is_unused() || !entry.flags().contains(PageTableFlags::USER_ACCESSIBLE) {
+        return None;
+    }
+    let frame = PhysFrame::containing_address(entry.addr());
+    entry.set_unused();
+    flush_if_current(l4, page);
+    Some(frame)
+}
+
+/// マップ済みのユーザーページの権限を置き換える
+///
+/// `flags`はPRESENT / WRITABLE / NO_EXECUTEの組み合わせで、PRESENTを含まない
+/// 場合はアクセス不可のページになる（フレームは保持したまま）。共有中のフレームに
+/// 書き込みを許可する場合は、WRITABLEの代わりに`COW`を付ける。
+/// ページがマップされていなければ`false`を返す。
+pub fn protect_user_page(l4: PhysFrame, page: Page, flags: PageTableFlags) -> bool {
+    let entry = match user_pte(l4, page.start_address()) {
+        Some(entry) if !entry.is_unused() && entry.flags().contains(PageTableFlags::USER_ACCESSIBLE) => entry,
+        _ => return false,
+    };
+    let frame = PhysFrame::containing_address(entry.addr());
+    let mut new_flags = flags | PageTableFlags::USER_ACCESSIBLE;
+    if new_flags.contains(PageTableFlags::WRITABLE) && super::frame_ref_count(frame) > 1 {
+        new_flags.remove(PageTableFlags::WRITABLE);
+        new_flags.insert(COW);
+    }
+    entry.set_flags(new_flags);
+    flush_if_current(l4, page);
+    true
+}
+
+/// ユーザーページのエントリがフレームを持っているか（アクセス不可のページを含む）
+pub fn has_user_frame(l4: PhysFrame, page: Page) -> bool {
+    user_pte(l4, page.start_address())
+        .is_some_and(|entry| !entry.is_unused() && entry.flags().contains(PageTableFlags::USER_ACCESSIBLE))
+}
+
+/// コピーオンライトのページに書き込めるようにする
+///
+/// 他に参照がなければそのまま書き込み可能に戻し、共有中であれば新しい
+/// フレームにコピーして差し替える。`page`がCOWページでなければ`false`。
+pub fn resolve_cow(l4: PhysFrame, page: Page) -> bool {
+    let entry = match user_pte(l4, page.start_address()) {
+        Some(entry) => entry,
+        None => return false,
+    };
+    let flags = entry.flags();
+    if !flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | COW) {
+        return false;
+    }
+
+    let old_frame = PhysFrame::containing_address(entry.addr());
+    let mut new_flags = flags;
+    new_flags.remove(COW);
+    new_flags.insert(PageTableFlags::WRITABLE);
+
+    if super::frame_ref_count(old_frame) == 1 {
+        // 他のプロセスはすでにコピーを持っている：このページを占有する
+        entry.set_flags(new_flags);
+    } else {
+        let new_frame = match super::allocate_frame() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        copy_frame(old_frame, new_frame);
+        entry.set_addr(new_frame.start_address(), new_flags);
+        super::release_frame(old_frame);
+    }
+
+    flush_if_current(l4, page);
+    true
+}
+
+/// アドレス空間を解放する
+///
+/// ユーザーページの参照を手放し（最後の参照ならフレームを解放）、プロセスが
+/// 所有するページテーブルを解放する。カーネル専用のエントリは共有なので触らない。
+/// `l4`が現在のCR3であれば、先にカーネルのページテーブルに切り替える。
+pub fn free_address_space(l4: PhysFrame) {
+    if l4 == super::kernel_page_table() {
+        return;
+    }
+    let (current, flags) = Cr3::read();
+    if current == l4 {
+        unsafe { Cr3::write(super::kernel_page_table(), flags) };
+    }
+    free_table(l4.start_address(), 4);
+}
+
+fn free_table(addr: PhysAddr, level: u8) {
+    let table = unsafe { table_at(addr) };
+    for entry in table.iter() {
+        let flags = entry.flags();
+        if entry.is_unused()
+            || !flags.contains(PageTableFlags::USER_ACCESSIBLE)
+            || flags.contains(PageTableFlags::HUGE_PAGE)
+        {
+            continue;
+        }
+        if level == 1 {
+            super::release_frame(PhysFrame::containing_address(entry.addr()));
+        } else {
+            free_table(entry.addr(), level - 1);
+        }
+    }
+    super::deallocate_frame(PhysFrame::containing_address(addr));
+}
@@ -0,0 +1,125 @@
+//! メモリ管理系システムコール
+//!
+//! mmap / munmap / mprotect / brk のハンドラと、その登録処理。
+//! アドレス空間とメモリ使用量はスレッドグループで共有するので、リーダーのものを使う。
+
+use crate::error::{Errno, KernelResult};
+use crate::process::scheduler::SCHEDULER;
+use crate::syscall::table::{ArgKind, SyscallEntry, SyscallResult, SyscallTable};
+use crate::syscall::{self, SyscallArgs};
+use super::mmap::{self, MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MAP_SHARED, PAGE_SIZE};
+
+/// メモリ管理系システムコールを登録
+pub fn register_syscalls(table: &mut SyscallTable) -> KernelResult<()> {
+    table.register(SyscallEntry::new(syscall::SYS_MMAP, "mmap", sys_mmap,
+        &[ArgKind::Value, ArgKind::Size, ArgKind::Flags, ArgKind::Flags, ArgKind::Fd, ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_MPROTECT, "mprotect", sys_mprotect,
+        &[ArgKind::Value, ArgKind::Size, ArgKind::Flags]))?;
+    table.register(SyscallEntry::new(syscall::SYS_MUNMAP, "munmap", sys_munmap,
+        &[ArgKind::Value, ArgKind::Size]))?;
+    table.register(SyscallEntry::new(syscall::SYS_BRK, "brk", sys_brk, &[ArgKind::Value]))?;
+    Ok(())
+}
+
+// mmap: Map anonymous memory
+// Arguments: RDI=addr, RSI=length, RDX=prot, R10=flags, R8=fd, R9=offset
+fn sys_mmap(args: &SyscallArgs) -> SyscallResult {
+    let (addr, prot, flags, offset) = (args.arg1, args.arg3, args.arg4, args.arg6);
+
+    if args.arg2 == 0 || !mmap::valid_prot(prot) || !offset.is_multiple_of(PAGE_SIZE) {
+        return Err(Errno::EINVAL);
+    }
+    let len = mmap::page_align_up(args.arg2).ok_or(Errno::ENOMEM)?;
+    if flags & !(MAP_SHARED | MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    // ファイルがないので匿名マッピングのみ。共有マッピングはまだサポートしない
+    if flags & MAP_ANONYMOUS == 0 {
+        return Err(Errno::ENODEV);
+    }
+    if flags & (MAP_SHARED | MAP_PRIVATE) != MAP_PRIVATE {
+        return Err(Errno::EINVAL);
+    }
+    if flags & MAP_FIXED != 0 && !addr.is_multiple_of(PAGE_SIZE) {
+        return Err(Errno::EINVAL);
+    }
+
+    let mut sched = SCHEDULER.lock();
+    let process = sched.current_leader_mut().ok_or(Errno::ESRCH)?;
+
+    // ResourceLimits::max_memory を超える確保は拒否する
+    if process.stats.memory_used.saturating_add(len) > process.resource_limits.max_memory {
+        return Err(Errno::ENOMEM);
+    }
+
+    let start = process.address_space.map_anonymous(addr & !(PAGE_SIZE - 1), len, prot, flags)?;
+    // MAP_FIXEDで既存の領域を置き換えた場合もあるので、VMAの合計から求め直す
+    process.stats.memory_used = process.address_space.total_size();
+    Ok(start)
+}
+
+// munmap: Unmap memory
+// Arguments: RDI=addr, RSI=length
+fn sys_munmap(args: &SyscallArgs) -> SyscallResult {
+    let addr = args.arg1;
+    if !addr.is_multiple_of(PAGE_SIZE) || args.arg2 == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let len = mmap::page_align_up(args.arg2).ok_or(Errno::EINVAL)?;
+
+    let mut sched = SCHEDULER.lock();
+    let process = sched.current_leader_mut().ok_or(Errno::ESRCH)?;
+    process.address_space.unmap(addr, len);
+    process.stats.memory_used = process.address_space.total_size();
+    Ok(0)
+}
+
+// mprotect: Change protection of mapped memory
+// Arguments: RDI=addr, RSI=length, RDX=prot
+fn sys_mprotect(args: &SyscallArgs) -> SyscallResult {
+    let (addr, prot) = (args.arg1, args.arg3);
+    if !addr.is_multiple_of(PAGE_SIZE) || !mmap::valid_prot(prot) {
+        return Err(Errno::EINVAL);
+    }
+    let len = mmap::page_align_up(args.arg2).ok_or(Errno::ENOMEM)?;
+    if len == 0 {
+        return Ok(0);
+    }
+
+    let mut sched = SCHEDULER.lock();
+    let process = sched.current_leader_mut().ok_or(Errno::ESRCH)?;
+    process.address_space.protect(addr, len, prot)?;
+    Ok(0)
+}
+
+// brk: Change the program break
+// Arguments: RDI=new break (0 to query)
+// Linuxと同じく、失敗してもエラー番号ではなく現在のブレークを返す。
+// sbrk(n)はユーザー側で brk(brk(0) + n) として実装する。
+fn sys_brk(args: &SyscallArgs) -> SyscallResult {
+    let requested = args.arg1;
+
+    let mut sched = SCHEDULER.lock();
+    let process = sched.current_leader_mut().ok_or(Errno::ESRCH)?;
+    let current = process.address_space.program_break();
+    if requested == 0 || requested < current.start {
+        return Ok(current.current);
+    }
+
+    // ページ単位で増える分を ResourceLimits::max_memory と照らし合わせる
+    let growth = match (mmap::page_align_up(requested), mmap::page_align_up(current.current)) {
+        (Some(new_end), Some(old_end)) => new_end.saturating_sub(old_end),
+        _ => return Ok(current.current),
+    };
+    if process.stats.memory_used.saturating_add(growth) > process.resource_limits.max_memory {
+        return Ok(current.current);
+    }
+
+    match process.address_space.set_program_break(requested) {
+        Ok(new_break) => {
+            process.stats.memory_used = process.address_space.total_size();
+            Ok(new_break)
+        }
+        Err(_) => Ok(current.current),
+    }
+}
@@ -0,0 +1,176 @@
+//! ユーザー空間メモリへの安全なアクセス
+//!
+//! システムコールハンドラはユーザーポインタを直接参照せず、ここにある
+//! `copy_from_user` / `copy_to_user` / `strncpy_from_user` を使います。
+//! 各ページについて現在のページテーブル（CR3）を辿り、PRESENT・USER_ACCESSIBLE
+//! （書き込み時はWRITABLEも）を確認してから、物理メモリの直接マップ経由で
+//! コピーするため、不正なポインタでカーネルがページフォルトすることはありません。
+
+use core::mem::{size_of, MaybeUninit};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::error::Errno;
+use super::vma::Access;
+
+/// ユーザー空間の上限（正規アドレスの下半分）
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// ユーザー範囲 `[addr, addr + len)` がユーザー空間に収まっているか確認
+pub fn check_user_range(addr: u64, len: usize) -> Result<(), Errno> {
+    if addr == 0 {
+        return Err(Errno::EFAULT);
+    }
+    let end = addr.checked_add(len as u64).ok_or(Errno::EFAULT)?;
+    if end > USER_SPACE_END {
+        return Err(Errno::EFAULT);
+    }
+    Ok(())
+}
+
+/// ユーザー仮想アドレスを物理アドレスに変換する
+///
+/// 途中の全階層で PRESENT と USER_ACCESSIBLE を要求し、`write` の場合は
+/// WRITABLE も要求する（CPUと同じ判定）。まだ割り当てていないページや
+/// コピーオンライトのページは、CPUのページフォルトと同じように現在の
+/// アドレス空間のVMAに従って解決してから変換する。
+pub fn translate_user(addr: VirtAddr, write: bool) -> Result<PhysAddr, Errno> {
+    if addr.as_u64() >= USER_SPACE_END {
+        return Err(Errno::EFAULT);
+    }
+
+    let access = if write { Access::Write } else { Access::Read };
+    match walk_user(addr, write) {
+        Err(Errno::EFAULT) if super::vma::handle_user_fault(addr, access) => walk_user(addr, write),
+        result => result,
+    }
+}
+
+// 現在のページテーブルを辿って変換する
+fn walk_user(addr: VirtAddr, write: bool) -> Result<PhysAddr, Errno> {
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        required |= PageTableFlags::WRITABLE;
+    }
+
+    let phys_offset = super::physical_memory_offset();
+    let (level_4_table_frame, _) = Cr3::read();
+    let mut table_phys = level_4_table_frame.start_address();
+
+    let indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    for (level, &index) in indexes.iter().enumerate() {
+        let table: &PageTable = unsafe { &*(phys_offset + table_phys.as_u64()).as_ptr() };
+        let entry = &table[index];
+        let flags = entry.flags();
+
+        if !flags.contains(required) {
+            return Err(Errno::EFAULT);
+        }
+
+        // 1GiB / 2MiB のヒュージページ（L3 / L2）
+        if flags.contains(PageTableFlags::HUGE_PAGE) && (level == 1 || level == 2) {
+            let page_size: u64 = if level == 1 { 1 << 30 } else { 1 << 21 };
+            return Ok(entry.addr() + (addr.as_u64() & (page_size - 1)));
+        }
+
+        table_phys = entry.addr();
+    }
+
+    Ok(table_phys + u64::from(addr.page_offset()))
+}
+
+/// ユーザー範囲の全ページがアクセス可能か検証する（長さ0は常に成功）
+///
+/// 取り消せない操作（メッセージの取り出しなど）の前に、書き戻し先を
+/// 先に検証しておくために使う。
+pub fn access_ok(addr: u64, len: usize, write: bool) -> Result<(), Errno> {
+    if len == 0 {
+        return Ok(());
+    }
+    check_user_range(addr, len)?;
+
+    let mut page = addr & !(PAGE_SIZE - 1);
+    while page < addr + len as u64 {
+        translate_user(VirtAddr::new(page), write)?;
+        page += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+// ユーザー範囲をページ単位に分割し、各断片の (カーネル側アドレス, オフセット, 長さ) を渡す
+fn for_each_user_chunk(
+    addr: u64,
+    len: usize,
+    write: bool,
+    mut f: impl FnMut(*mut u8, usize, usize),
+) -> Result<(), Errno> {
+    // 途中で失敗しても部分的にコピーしないよう、先に全ページを検証する
+    access_ok(addr, len, write)?;
+
+    let phys_offset = super::physical_memory_offset();
+    let mut done = 0usize;
+    while done < len {
+        let user_addr = addr + done as u64;
+        let in_page = (PAGE_SIZE - (user_addr % PAGE_SIZE)) as usize;
+        let chunk = core::cmp::min(in_page, len - done);
+
+        let phys = translate_user(VirtAddr::new(user_addr), write)?;
+        let kernel_ptr: *mut u8 = (phys_offset + phys.as_u64()).as_mut_ptr();
+        f(kernel_ptr, done, chunk);
+
+        done += chunk;
+    }
+    Ok(())
+}
+
+/// ユーザー空間 `src` から `dst.len()` バイトをコピーする
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), Errno> {
+    for_each_user_chunk(src, dst.len(), false, |ptr, offset, len| unsafe {
+        core::ptr::copy_nonoverlapping(ptr as *const u8, dst[offset..].as_mut_ptr(), len);
+    })
+}
+
+/// `src` をユーザー空間 `dst` にコピーする（書き込み可能なページが必要）
+pub fn copy_to_user(dst: u64, src: &[u8]) -> Result<(), Errno> {
+    for_each_user_chunk(dst, src.len(), true, |ptr, offset, len| unsafe {
+        core::ptr::copy_nonoverlapping(src[offset..].as_ptr(), ptr, len);
+    })
+}
+
+/// ユーザー空間のNUL終端文字列を最大 `dst.len()` バイトまでコピーする
+///
+/// NULを含まない文字列の長さを返す。`dst.len()` と等しい場合は
+/// NULが見つからなかった（切り詰められた）ことを意味する。
+pub fn strncpy_from_user(dst: &mut [u8], src: u64) -> Result<usize, Errno> {
+    let mut copied = 0usize;
+    while copied < dst.len() {
+        let user_addr = src.checked_add(copied as u64).ok_or(Errno::EFAULT)?;
+        let in_page = (PAGE_SIZE - (user_addr % PAGE_SIZE)) as usize;
+        let chunk = core::cmp::min(in_page, dst.len() - copied);
+
+        // ページ境界をまたがない範囲ずつ読むことで、NULの先のページは検証しない
+        copy_from_user(&mut dst[copied..copied + chunk], user_addr)?;
+        if let Some(nul) = dst[copied..copied + chunk].iter().position(|&b| b == 0) {
+            return Ok(copied + nul);
+        }
+        copied += chunk;
+    }
+    Ok(copied)
+}
+
+/// ユーザー空間から値を1つ読み取る（整数などの単純な型のみ）
+pub fn get_user<T: Copy>(src: u64) -> Result<T, Errno> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    copy_from_user(bytes, src)?;
+    Ok(unsafe { value.assume_init() })
+}
+
+/// ユーザー空間に値を1つ書き込む
+pub fn put_user<T: Copy>(dst: u64, value: &T) -> Result<(), Errno> {
+    let bytes = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(dst, bytes)
+}
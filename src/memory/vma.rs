@@ -0,0 +1,450 @@
+//! プロセスのアドレス空間と仮想メモリ領域（VMA）
+//!
+//! `AddressSpace`はページテーブルと、有効な仮想アドレス範囲（VMA）の一覧を
+//! まとめたもの。ELFイメージ・スタック・mmapの領域はすべてVMAとして記録され、
+//! ページはアクセスされたときに初めてゼロクリアしたフレームを割り当てる
+//! （デマンドページング）。VMAの外へのアクセスや権限のないアクセスは
+//! ページフォルトハンドラでプロセスを終了させる。
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::VirtAddr;
+
+use crate::error::{Errno, KernelResult};
+use super::mmap::{self, MAP_FIXED, MMAP_BASE, MMAP_END, MMAP_MIN_ADDR, PAGE_SIZE, PROT_EXEC, PROT_NONE, PROT_READ, PROT_WRITE};
+use super::paging;
+
+/// VMAの用途
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    /// ELFイメージのセグメント
+    Image,
+    /// ユーザースタック（下に向かって伸びる）
+    Stack,
+    /// brkで伸び縮みするヒープ
+    Heap,
+    /// mmapで作った匿名メモリ
+    Anonymous,
+    /// IPCのメモリハンドルで他のプロセスから受け取ったメモリ
+    Shared,
+}
+
+/// 仮想メモリ領域（`start`..`end`、ページ境界）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    pub start: u64,
+    pub end: u64,
+    pub prot: u64,
+    pub kind: VmaKind,
+}
+
+impl Vma {
+    pub fn new(start: u64, end: u64, prot: u64, kind: VmaKind) -> Self {
+        Self { start, end, prot, kind }
+    }
+
+    /// 領域のサイズ（バイト）
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// この領域に対するアクセスが許可されているか
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.prot != PROT_NONE,
+            Access::Write => self.prot & PROT_WRITE != 0,
+            Access::Execute => self.prot & PROT_EXEC != 0,
+        }
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        (self.start..self.end).step_by(PAGE_SIZE as usize)
+            .map(|addr| Page::containing_address(VirtAddr::new(addr)))
+    }
+}
+
+/// ページフォルトの原因となったアクセスの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// プロセスのアドレス空間
+///
+/// L4テーブルは生成時に決まり変わらない。VMAの一覧は内部のロックで守られて
+/// いるので、`Arc`で共有したまま操作できる。破棄されるとユーザーページと
+/// ページテーブルを解放する。
+#[derive(Debug)]
+pub struct AddressSpace {
+    page_table_frame: PhysFrame,
+    vmas: Mutex<BTreeMap<u64, Vma>>,
+    program_break: Mutex<ProgramBreak>,
+}
+
+/// プログラムブレーク（ヒープの開始位置と現在の終端）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProgramBreak {
+    pub start: u64,
+    pub current: u64,
+}
+
+impl AddressSpace {
+    /// ユーザーページを持たない新しいアドレス空間を作る
+    pub fn new() -> KernelResult<Self> {
+        Ok(Self::from_page_table(paging::new_address_space()?))
+    }
+
+    /// 用意済みのページテーブルを引き取る（VMAは空）
+    pub fn from_page_table(page_table_frame: PhysFrame) -> Self {
+        Self {
+            page_table_frame,
+            vmas: Mutex::new(BTreeMap::new()),
+            program_break: Mutex::new(ProgramBreak::default()),
+        }
+    }
+
+    /// L4テーブルの物理フレーム
+    pub fn page_table_frame(&self) -> PhysFrame {
+        self.page_table_frame
+    }
+
+    /// fork用にコピーオンライトで複製する
+    ///
+    /// メモリハンドルで受け取った領域（`VmaKind::Shared`）は子とも同じフレームを
+    /// 書き込み可能なまま共有する。
+    pub fn fork(&self) -> KernelResult<Self> {
+        let program_break = self.program_break.lock();
+        let vmas = self.vmas.lock();
+        let shared: Vec<_> = vmas.values()
+            .filter(|vma| vma.kind == VmaKind::Shared)
+            .map(|vma| vma.start..vma.end)
+            .collect();
+        let page_table_frame = paging::clone_address_space(self.page_table_frame, &shared)?;
+        Ok(Self {
+            page_table_frame,
+            vmas: Mutex::new(vmas.clone()),
+            program_break: Mutex::new(*program_break),
+        })
+    }
+
+    /// すべてのVMA（アドレス順）のコピー
+    pub fn vmas(&self) -> Vec<Vma> {
+        self.vmas.lock().values().copied().collect()
+    }
+
+    /// `addr`を含むVMAを探す
+    pub fn find_vma(&self, addr: u64) -> Option<Vma> {
+        find(&self.vmas.lock(), addr).copied()
+    }
+
+    /// VMAの合計サイズ（バイト）
+    pub fn total_size(&self) -> u64 {
+        self.vmas.lock().values().map(Vma::len).sum()
+    }
+
+    /// 新しいVMAを追加する（既存の領域と重なる場合は EEXIST）
+    ///
+    /// ページは割り当てない。最初のアクセスで割り当てられる。
+    pub fn insert_vma(&self, vma: Vma) -> Result<(), Errno> {
+        let mut vmas = self.vmas.lock();
+        if vma.is_empty() || !overlapping(&vmas, vma.start, vma.end).is_empty() {
+            return Err(Errno::EEXIST);
+        }
+        vmas.insert(vma.start, vma);
+        Ok(())
+    }
+
+    /// 匿名メモリの領域を作り、その開始アドレスを返す
+    ///
+    /// `addr`と`len`はページ境界に揃っていること。MAP_FIXEDなら`addr`に
+    /// そのまま置き（重なる領域は置き換える）、そうでなければ`addr`を
+    /// ヒントとして空いている場所を選ぶ。
+    pub fn map_anonymous(&self, addr: u64, len: u64, prot: u64, flags: u64) -> Result<u64, Errno> {
+        let mut vmas = self.vmas.lock();
+        let start = if flags & MAP_FIXED != 0 {
+            let end = addr.checked_add(len).ok_or(Errno::ENOMEM)?;
+            if addr < MMAP_MIN_ADDR || end > MMAP_END {
+                return Err(Errno::EINVAL);
+            }
+            self.unmap_locked(&mut vmas, addr, end);
+            addr
+        } else {
+            let hint_fits = addr >= MMAP_MIN_ADDR
+                && addr.checked_add(len).is_some_and(|end| end <= MMAP_END && overlapping(&vmas, addr, end).is_empty());
+            if hint_fits { addr } else { find_free(&vmas, len).ok_or(Errno::ENOMEM)? }
+        };
+
+        vmas.insert(start, Vma::new(start, start + len, prot, VmaKind::Anonymous));
+        Ok(start)
+    }
+
+    /// 既存のフレームを`start`から順にマップし、新しい領域にする
+    ///
+    /// IPCのメモリハンドルで他のプロセスのフレームを受け取るためのもの。
+    /// フレームの参照数を1つずつ増やすので、外すときは他のページと同じく
+    /// 参照を手放すだけでよい。既存の領域と重なる場合は EEXIST。
+    pub fn map_frames(&self, start: u64, frames: &[PhysFrame], prot: u64) -> Result<(), Errno> {
+        let len = (frames.len() as u64).checked_mul(PAGE_SIZE).ok_or(Errno::ENOMEM)?;
+        let end = start.checked_add(len).ok_or(Errno::ENOMEM)?;
+        if len == 0 || !start.is_multiple_of(PAGE_SIZE) || start < MMAP_MIN_ADDR || end > MMAP_END {
+            return Err(Errno::EINVAL);
+        }
+
+        let mut vmas = self.vmas.lock();
+        if !overlapping(&vmas, start, end).is_empty() {
+            return Err(Errno::EEXIST);
+        }
+        let vma = Vma::new(start, end, prot, VmaKind::Shared);
+        let flags = mmap::prot_to_flags(prot);
+        for (page, &frame) in vma.pages().zip(frames) {
+            super::share_frame(frame);
+            if paging::map_user_page(self.page_table_frame, page, frame, flags).is_err() {
+                super::release_frame(frame);
+                // ここまでにマップしたページを外す
+                let mapped_end = page.start_address().as_u64();
+                if mapped_end > start {
+                    vmas.insert(start, Vma { end: mapped_end, ..vma });
+                    self.unmap_locked(&mut vmas, start, mapped_end);
+                }
+                return Err(Errno::ENOMEM);
+            }
+        }
+        vmas.insert(start, vma);
+        Ok(())
+    }
+
+    /// `start`から`len`バイトの領域を外し、外したバイト数を返す
+    ///
+    /// 範囲内で領域のない部分は無視する。部分的に重なる領域は分割される。
+    pub fn unmap(&self, start: u64, len: u64) -> u64 {
+        let mut vmas = self.vmas.lock();
+        self.unmap_locked(&mut vmas, start, start.saturating_add(len))
+    }
+
+    fn unmap_locked(&self, vmas: &mut BTreeMap<u64, Vma>, start: u64, end: u64) -> u64 {
+        split_at(vmas, start);
+        split_at(vmas, end);
+
+        let mut freed = 0;
+        for key in overlapping(vmas, start, end) {
+            if let Some(vma) = vmas.remove(&key) {
+                for page in vma.pages() {
+                    if let Some(frame) = paging::unmap_user_page(self.page_table_frame, page) {
+                        super::release_frame(frame);
+                    }
+                }
+                freed += vma.len();
+            }
+        }
+        freed
+    }
+
+    /// `start`から`len`バイトの保護フラグを変更する
+    ///
+    /// 範囲全体が領域で覆われていなければ ENOMEM（何も変更しない）。
+    pub fn protect(&self, start: u64, len: u64, prot: u64) -> Result<(), Errno> {
+        let end = start.checked_add(len).ok_or(Errno::ENOMEM)?;
+        let mut vmas = self.vmas.lock();
+
+        let mut covered = start;
+        for key in overlapping(&vmas, start, end) {
+            let vma = vmas[&key];
+            if vma.start > covered {
+                return Err(Errno::ENOMEM);
+            }
+            covered = vma.end;
+        }
+        if covered < end {
+            return Err(Errno::ENOMEM);
+        }
+
+        split_at(&mut vmas, start);
+        split_at(&mut vmas, end);
+        let page_flags = mmap::prot_to_flags(prot);
+        for key in overlapping(&vmas, start, end) {
+            if let Some(vma) = vmas.get_mut(&key) {
+                vma.prot = prot;
+                // まだ割り当てていないページはフォルト時に新しい権限で作られる
+                for page in vma.pages() {
+                    paging::protect_user_page(self.page_table_frame, page, page_flags);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// ヒープの開始位置を設定する（ELFのロード時、イメージの直後）
+    pub fn init_program_break(&self, start: u64) {
+        *self.program_break.lock() = ProgramBreak { start, current: start };
+    }
+
+    /// 現在のプログラムブレーク
+    pub fn program_break(&self) -> ProgramBreak {
+        *self.program_break.lock()
+    }
+
+    /// プログラムブレークを`new_break`に動かす
+    ///
+    /// ヒープのVMAはページ単位で伸び縮みし、縮めた分のページは解放する。
+    /// ヒープの開始位置より下や、他の領域とぶつかる位置には動かせない（ENOMEM）。
+    pub fn set_program_break(&self, new_break: u64) -> Result<u64, Errno> {
+        let mut program_break = self.program_break.lock();
+        if new_break < program_break.start || new_break > MMAP_END {
+            return Err(Errno::ENOMEM);
+        }
+
+        let old_end = page_end(program_break.current);
+        let new_end = page_end(new_break);
+        let mut vmas = self.vmas.lock();
+        if new_end > old_end {
+            if !overlapping(&vmas, old_end, new_end).is_empty() {
+                return Err(Errno::ENOMEM);
+            }
+            // 直前のヒープ領域を伸ばす（mprotectで分割されていれば新しい領域を作る）
+            let heap_prot = PROT_READ | PROT_WRITE;
+            match vmas.range_mut(..old_end).next_back() {
+                Some((_, vma)) if vma.end == old_end && vma.kind == VmaKind::Heap && vma.prot == heap_prot => {
+                    vma.end = new_end;
+                }
+                _ => {
+                    vmas.insert(old_end, Vma::new(old_end, new_end, heap_prot, VmaKind::Heap));
+                }
+            }
+        } else if new_end < old_end {
+            self.unmap_locked(&mut vmas, new_end, old_end);
+        }
+
+        program_break.current = new_break;
+        Ok(new_break)
+    }
+
+    /// ページフォルトを解決する
+    ///
+    /// `addr`を含むVMAが`access`を許可していれば、未割り当てのページには
+    /// ゼロクリアしたフレームを割り当て、コピーオンライトのページには専用の
+    /// コピーを作る。解決できた場合は`true`を返し、呼び出し元は同じ命令を
+    /// 再実行すればよい。
+    pub fn handle_fault(&self, addr: VirtAddr, access: Access) -> bool {
+        let vma = match self.find_vma(addr.as_u64()) {
+            Some(vma) if vma.allows(access) => vma,
+            _ => return false,
+        };
+        let page = Page::containing_address(addr);
+        let l4 = self.page_table_frame;
+
+        match paging::user_page_flags(l4, page) {
+            // フレームを持ったままアクセス不可になっていたページ：VMAの権限に戻す
+            None if paging::has_user_frame(l4, page) => {
+                paging::protect_user_page(l4, page, mmap::prot_to_flags(vma.prot))
+            }
+            // 未割り当て：デマンドゼロページ
+            None => paging::map_zeroed_user_page(l4, page, mmap::prot_to_flags(vma.prot)).is_ok(),
+            Some(flags) if access == Access::Write && flags.contains(paging::COW) => {
+                paging::resolve_cow(l4, page)
+            }
+            // ページテーブルがすでに許可している（別の経路で解決済み）
+            Some(flags) => match access {
+                Access::Read => true,
+                Access::Write => flags.contains(PageTableFlags::WRITABLE),
+                Access::Execute => !flags.contains(PageTableFlags::NO_EXECUTE),
+            },
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        paging::free_address_space(self.page_table_frame);
+    }
+}
+
+// アドレスをページ境界に切り上げる（ユーザー空間のアドレスは溢れない）
+fn page_end(addr: u64) -> u64 {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+// `addr`を含むVMA
+fn find(vmas: &BTreeMap<u64, Vma>, addr: u64) -> Option<&Vma> {
+    vmas.range(..=addr).next_back()
+        .map(|(_, vma)| vma)
+        .filter(|vma| vma.contains(addr))
+}
+
+// `start..end`と重なるVMAの開始アドレス
+fn overlapping(vmas: &BTreeMap<u64, Vma>, start: u64, end: u64) -> Vec<u64> {
+    let mut keys = Vec::new();
+    for (&key, vma) in vmas.range(..end).rev() {
+        if vma.end <= start {
+            break;
+        }
+        keys.push(key);
+    }
+    keys.reverse();
+    keys
+}
+
+// `addr`を境にVMAを2つに分ける
+fn split_at(vmas: &mut BTreeMap<u64, Vma>, addr: u64) {
+    let vma = match find(vmas, addr) {
+        Some(vma) if vma.start != addr => *vma,
+        _ => return,
+    };
+    vmas.insert(vma.start, Vma { end: addr, ..vma });
+    vmas.insert(addr, Vma { start: addr, ..vma });
+}
+
+// `len`バイトの空き領域をMMAP_BASEから探す
+fn find_free(vmas: &BTreeMap<u64, Vma>, len: u64) -> Option<u64> {
+    let mut candidate = MMAP_BASE;
+    loop {
+        let end = candidate.checked_add(len)?;
+        if end > MMAP_END {
+            return None;
+        }
+        match overlapping(vmas, candidate, end).last() {
+            // 重なった領域の後ろから探し直す
+            Some(key) => candidate = vmas[key].end,
+            None => return Some(candidate),
+        }
+    }
+}
+
+// 現在CPUで動いているプロセスのアドレス空間
+static CURRENT: Mutex<Option<Arc<AddressSpace>>> = Mutex::new(None);
+
+/// 現在のアドレス空間を設定する（コンテキストスイッチ時に呼ばれる）
+pub fn set_current(address_space: Option<Arc<AddressSpace>>) {
+    *CURRENT.lock() = address_space;
+}
+
+/// 現在のアドレス空間
+pub fn current() -> Option<Arc<AddressSpace>> {
+    CURRENT.lock().clone()
+}
+
+/// 現在のアドレス空間でユーザーアドレスへのフォルトを解決する
+///
+/// CPUのページフォルトと、カーネルがユーザーメモリにアクセスするとき
+/// （`uaccess`）の両方から使われる。
+pub fn handle_user_fault(addr: VirtAddr, access: Access) -> bool {
+    if addr.as_u64() >= super::uaccess::USER_SPACE_END {
+        return false;
+    }
+    match current() {
+        Some(address_space) => address_space.handle_fault(addr, access),
+        None => false,
+    }
+}
@@ -0,0 +1,349 @@
+//! ELF64 実行ファイルローダー
+//!
+//! 静的リンクされたx86_64のELF実行ファイル（ET_EXEC）を解析し、PT_LOADセグメントを
+//! 新しいアドレス空間にマップします。セグメントのR/W/Xはページの
+//! WRITABLE / NO_EXECUTE に反映され、ファイルにない部分（.bss）はゼロで埋められます。
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::VirtAddr;
+
+use crate::error::{KernelResult, ProcessError};
+use crate::kerror;
+use crate::memory::mmap::{self, PROT_EXEC, PROT_READ, PROT_WRITE};
+use crate::memory::paging;
+use crate::memory::vma::{AddressSpace, Vma, VmaKind};
+
+/// ユーザースタックの最上部
+pub const USER_STACK_TOP: u64 = 0x0000_7FFF_FFFF_F000;
+/// ロード時に割り当てておくユーザースタックのページ数
+pub const USER_STACK_PAGES: u64 = 8;
+/// ユーザースタックの最大サイズ（この範囲はフォルト時に割り当てる）
+pub const USER_STACK_MAX_SIZE: u64 = 8 * 1024 * 1024;
+/// ユーザースタック用に予約した領域の下端
+pub const USER_STACK_LOWEST: u64 = USER_STACK_TOP - USER_STACK_MAX_SIZE;
+
+/// ユーザープログラムをロードできる最低アドレス（NULLページは使わない）
+const MIN_LOAD_ADDRESS: u64 = 0x1000;
+
+const PAGE_SIZE: u64 = 4096;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 0x3E;
+
+const ELF_HEADER_SIZE: usize = 64;
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// ロード可能なセグメント
+pub const PT_LOAD: u32 = 1;
+
+/// セグメントの実行許可
+pub const PF_X: u32 = 1;
+/// セグメントの書き込み許可
+pub const PF_W: u32 = 2;
+/// セグメントの読み取り許可
+pub const PF_R: u32 = 4;
+
+/// プログラムヘッダ（必要なフィールドのみ）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+impl ProgramHeader {
+    // セグメントの属性に対応する保護フラグ（x86_64では常に読み取り可能）
+    fn prot(&self) -> u64 {
+        let mut prot = PROT_READ;
+        if self.flags & PF_W != 0 {
+            prot |= PROT_WRITE;
+        }
+        if self.flags & PF_X != 0 {
+            prot |= PROT_EXEC;
+        }
+        prot
+    }
+
+    // 仮想アドレスがこのセグメントに含まれるか
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.vaddr && addr - self.vaddr < self.memsz
+    }
+}
+
+// リトルエンディアンの整数を読み取る（範囲は呼び出し側で検証済み）
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// 検証済みのELF64実行ファイル
+pub struct ElfImage<'a> {
+    data: &'a [u8],
+    entry: u64,
+    phoff: u64,
+    phnum: u64,
+    segments: Vec<ProgramHeader>,
+}
+
+impl<'a> ElfImage<'a> {
+    /// ヘッダとプログラムヘッダを解析・検証する
+    ///
+    /// 対応していない形式や、ファイル外・カーネル空間を指すセグメントは
+    /// `ProcessError::InvalidExecutable`になる。
+    pub fn parse(data: &'a [u8]) -> KernelResult<Self> {
+        if data.len() < ELF_HEADER_SIZE || data[0..4] != ELF_MAGIC {
+            return kerror!(ProcessError::InvalidExecutable);
+        }
+        if data[4] != ELFCLASS64 || data[5] != ELFDATA2LSB || data[6] != EV_CURRENT {
+            return kerror!(ProcessError::InvalidExecutable);
+        }
+        if read_u16(data, 16) != ET_EXEC || read_u16(data, 18) != EM_X86_64 {
+            return kerror!(ProcessError::InvalidExecutable);
+        }
+
+        let entry = read_u64(data, 24);
+        let phoff = read_u64(data, 32) as usize;
+        let phentsize = read_u16(data, 54) as usize;
+        let phnum = read_u16(data, 56) as usize;
+        if phentsize != PROGRAM_HEADER_SIZE {
+            return kerror!(ProcessError::InvalidExecutable);
+        }
+        let table_end = phnum
+            .checked_mul(PROGRAM_HEADER_SIZE)
+            .and_then(|size| size.checked_add(phoff));
+        match table_end {
+            Some(end) if end <= data.len() => {}
+            _ => return kerror!(ProcessError::InvalidExecutable),
+        }
+
+        let mut segments = Vec::new();
+        for index in 0..phnum {
+            let base = phoff + index * PROGRAM_HEADER_SIZE;
+            let header = ProgramHeader {
+                p_type: read_u32(data, base),
+                flags: read_u32(data, base + 4),
+                offset: read_u64(data, base + 8),
+                vaddr: read_u64(data, base + 16),
+                filesz: read_u64(data, base + 32),
+                memsz: read_u64(data, base + 40),
+            };
+            if header.p_type != PT_LOAD || header.memsz == 0 {
+                continue;
+            }
+            validate_segment(&header, data.len())?;
+            segments.push(header);
+        }
+
+        // エントリポイントは実行可能なセグメントの中になければならない
+        let entry_ok = segments.iter().any(|s| s.flags & PF_X != 0 && s.contains(entry));
+        if !entry_ok {
+            return kerror!(ProcessError::InvalidExecutable);
+        }
+
+        Ok(ElfImage { data, entry, phoff: phoff as u64, phnum: phnum as u64, segments })
+    }
+
+    /// エントリポイント
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// PT_LOADセグメントの一覧
+    pub fn segments(&self) -> &[ProgramHeader] {
+        &self.segments
+    }
+
+    /// プログラムヘッダ表がロードされる仮想アドレス（補助ベクタのAT_PHDR用）
+    ///
+    /// 表を含むPT_LOADセグメントがなければ`None`。
+    pub fn phdr_address(&self) -> Option<u64> {
+        let table_size = self.phnum * PROGRAM_HEADER_SIZE as u64;
+        self.segments
+            .iter()
+            .find(|s| self.phoff >= s.offset && self.phoff + table_size <= s.offset + s.filesz)
+            .map(|s| s.vaddr + (self.phoff - s.offset))
+    }
+
+    /// プログラムヘッダの数
+    pub fn phnum(&self) -> u64 {
+        self.phnum
+    }
+
+    /// 全セグメントの終端（ページ境界に切り上げ）。ヒープの開始位置に使う
+    pub fn image_end(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|s| align_up(s.vaddr + s.memsz))
+            .max()
+            .unwrap_or(MIN_LOAD_ADDRESS)
+    }
+}
+
+// セグメントがファイル内・ユーザー空間内に収まっているか検証する
+fn validate_segment(header: &ProgramHeader, file_len: usize) -> KernelResult<()> {
+    if header.filesz > header.memsz || header.vaddr < MIN_LOAD_ADDRESS {
+        return kerror!(ProcessError::InvalidExecutable);
+    }
+    let file_end = header.offset.checked_add(header.filesz);
+    if !matches!(file_end, Some(end) if end <= file_len as u64) {
+        return kerror!(ProcessError::InvalidExecutable);
+    }
+    let mem_end = header.vaddr.checked_add(header.memsz);
+    if !matches!(mem_end, Some(end) if end <= USER_STACK_LOWEST) {
+        return kerror!(ProcessError::InvalidExecutable);
+    }
+    Ok(())
+}
+
+fn align_up(addr: u64) -> u64 {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// ロード結果
+#[derive(Debug)]
+pub struct LoadedImage {
+    /// イメージとスタックをマップした新しいアドレス空間
+    pub address_space: AddressSpace,
+    /// エントリポイント
+    pub entry: u64,
+    /// ユーザースタックの最上部
+    pub stack_top: u64,
+    /// イメージの終端（ページ境界）
+    pub image_end: u64,
+    /// メモリ上のプログラムヘッダ表のアドレス（0ならロードされていない）
+    pub phdr: u64,
+    /// プログラムヘッダの数
+    pub phnum: u64,
+}
+
+/// ELF実行ファイルを新しいアドレス空間にロードする
+pub fn load(data: &[u8]) -> KernelResult<LoadedImage> {
+    let image = ElfImage::parse(data)?;
+    // 失敗した場合、作りかけのアドレス空間はドロップ時に解放される
+    let address_space = AddressSpace::new()?;
+    map_image(&image, &address_space)?;
+
+    Ok(LoadedImage {
+        address_space,
+        entry: image.entry(),
+        stack_top: USER_STACK_TOP,
+        image_end: image.image_end(),
+        phdr: image.phdr_address().unwrap_or(0),
+        phnum: image.phnum(),
+    })
+}
+
+/// 解析済みのイメージとユーザースタックをアドレス空間にマップする
+///
+/// セグメントの内容はすぐにコピーし、それぞれの範囲をVMAとして登録する。
+/// スタックは`USER_STACK_MAX_SIZE`分のVMAを予約し、上端の
+/// `USER_STACK_PAGES`ページだけを先に割り当てる。プログラムブレークは
+/// イメージの終端に置く。
+pub fn map_image(image: &ElfImage, address_space: &AddressSpace) -> KernelResult<()> {
+    let l4 = address_space.page_table_frame();
+    // ページごとの保護フラグ（ページを共有するセグメントは権限を合わせる）
+    let mut page_prot: BTreeMap<u64, u64> = BTreeMap::new();
+    for segment in image.segments() {
+        map_segment(image.data, segment, l4)?;
+        let mut page_addr = segment.vaddr & !(PAGE_SIZE - 1);
+        while page_addr < align_up(segment.vaddr + segment.memsz) {
+            *page_prot.entry(page_addr).or_insert(0) |= segment.prot();
+            page_addr += PAGE_SIZE;
+        }
+    }
+
+    // 連続していて権限が同じページを1つのVMAにまとめる
+    let mut current: Option<Vma> = None;
+    for (&page_addr, &prot) in page_prot.iter() {
+        match current.as_mut() {
+            Some(vma) if vma.end == page_addr && vma.prot == prot => vma.end += PAGE_SIZE,
+            _ => {
+                if let Some(vma) = current.replace(Vma::new(page_addr, page_addr + PAGE_SIZE, prot, VmaKind::Image)) {
+                    insert_vma(address_space, vma)?;
+                }
+            }
+        }
+    }
+    if let Some(vma) = current {
+        insert_vma(address_space, vma)?;
+    }
+
+    insert_vma(address_space, Vma::new(USER_STACK_LOWEST, USER_STACK_TOP, PROT_READ | PROT_WRITE, VmaKind::Stack))?;
+    // ヒープ（brk）はイメージの直後から始まる
+    address_space.init_program_break(image.image_end());
+    map_user_stack(l4)
+}
+
+fn insert_vma(address_space: &AddressSpace, vma: Vma) -> KernelResult<()> {
+    if address_space.insert_vma(vma).is_err() {
+        return kerror!(ProcessError::InvalidExecutable);
+    }
+    Ok(())
+}
+
+// セグメントをページ単位でマップし、ファイルの内容をコピーする
+fn map_segment(data: &[u8], segment: &ProgramHeader, l4: PhysFrame) -> KernelResult<()> {
+    let flags = mmap::prot_to_flags(segment.prot());
+    let start = segment.vaddr & !(PAGE_SIZE - 1);
+    let end = align_up(segment.vaddr + segment.memsz);
+    let file_start = segment.vaddr;
+    let file_end = segment.vaddr + segment.filesz;
+
+    let mut page_addr = start;
+    while page_addr < end {
+        let page = Page::containing_address(VirtAddr::new(page_addr));
+        let frame = match paging::user_page_frame(l4, page) {
+            // 前のセグメントと同じページを共有している場合は権限を合わせる
+            Some(frame) => {
+                paging::merge_user_page_flags(l4, page, flags);
+                frame
+            }
+            None => paging::map_zeroed_user_page(l4, page, flags)?,
+        };
+
+        // このページに含まれるファイル部分をコピー（残りは.bssとしてゼロのまま）
+        let copy_start = core::cmp::max(page_addr, file_start);
+        let copy_end = core::cmp::min(page_addr + PAGE_SIZE, file_end);
+        if copy_start < copy_end {
+            let src = (segment.offset + (copy_start - file_start)) as usize;
+            let len = (copy_end - copy_start) as usize;
+            unsafe {
+                let dst = paging::frame_ptr(frame).add((copy_start - page_addr) as usize);
+                core::ptr::copy_nonoverlapping(data[src..src + len].as_ptr(), dst, len);
+            }
+        }
+
+        page_addr += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+// ユーザースタックをマップする（書き込み可能・実行不可）
+fn map_user_stack(l4: PhysFrame) -> KernelResult<()> {
+    let bottom = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+    for index in 0..USER_STACK_PAGES {
+        let page = Page::containing_address(VirtAddr::new(bottom + index * PAGE_SIZE));
+        paging::map_zeroed_user_page(l4, page, PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)?;
+    }
+    Ok(())
+}
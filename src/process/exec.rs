@@ -0,0 +1,217 @@
+//! execve の実装
+//!
+//! ファイルシステムがまだ無いため、実行可能なELFイメージはパス名をキーにした
+//! プログラムレジストリに登録しておき、execveはそこから検索する。
+//! ロード後はSystem V ABIの初期スタック（argc / argv / envp / auxv）を構築する。
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::registers::model_specific::FsBase;
+use x86_64::VirtAddr;
+
+use crate::error::{Errno, KernelResult};
+use crate::memory::{paging, uaccess, vma};
+use super::elf::{self, LoadedImage};
+use super::scheduler::SCHEDULER;
+use super::ProcessContext;
+
+/// パス名の最大長（NULを含む）
+pub const PATH_MAX: usize = 256;
+/// argv / envp それぞれの最大要素数
+pub const MAX_ARG_COUNT: usize = 64;
+/// argv と envp の文字列の合計サイズの上限（NULを含む）
+pub const ARG_MAX: usize = 16 * 1024;
+
+// 補助ベクタのタイプ
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_RANDOM: u64 = 25;
+
+lazy_static! {
+    // パス名 -> ELFイメージ
+    static ref PROGRAMS: Mutex<BTreeMap<String, Arc<Vec<u8>>>> = Mutex::new(BTreeMap::new());
+}
+
+/// 実行可能イメージをパス名で登録する（同じパスは上書き）
+pub fn register_program(path: &str, image: Vec<u8>) {
+    PROGRAMS.lock().insert(String::from(path), Arc::new(image));
+}
+
+/// 登録済みのイメージを検索する
+pub fn find_program(path: &str) -> Option<Arc<Vec<u8>>> {
+    PROGRAMS.lock().get(path).cloned()
+}
+
+/// ユーザー空間からパス名をコピーする
+pub fn copy_path_from_user(ptr: u64) -> Result<String, Errno> {
+    let mut buf = [0u8; PATH_MAX];
+    let len = uaccess::strncpy_from_user(&mut buf, ptr)?;
+    if len == PATH_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    if len == 0 {
+        return Err(Errno::ENOENT);
+    }
+    let path = core::str::from_utf8(&buf[..len]).map_err(|_| Errno::ENOENT)?;
+    Ok(String::from(path))
+}
+
+/// ユーザー空間のNULL終端ポインタ配列（argv / envp）をコピーする
+///
+/// `ptr` が0なら空の配列として扱う。`total` はこれまでにコピーした
+/// 文字列の合計サイズで、`ARG_MAX` を超えると E2BIG を返す。
+pub fn copy_string_array(ptr: u64, total: &mut usize) -> Result<Vec<Vec<u8>>, Errno> {
+    let mut strings = Vec::new();
+    if ptr == 0 {
+        return Ok(strings);
+    }
+
+    loop {
+        let slot = ptr.checked_add((strings.len() * 8) as u64).ok_or(Errno::EFAULT)?;
+        let string_ptr: u64 = uaccess::get_user(slot)?;
+        if string_ptr == 0 {
+            return Ok(strings);
+        }
+        if strings.len() == MAX_ARG_COUNT {
+            return Err(Errno::E2BIG);
+        }
+
+        // 残りの枠に収まらなければNULが見つからない
+        let remaining = ARG_MAX.saturating_sub(*total);
+        let mut buf = alloc::vec![0u8; remaining];
+        let len = uaccess::strncpy_from_user(&mut buf, string_ptr)?;
+        if len == remaining {
+            return Err(Errno::E2BIG);
+        }
+        buf.truncate(len + 1); // NULを含める
+        *total += len + 1;
+        strings.push(buf);
+    }
+}
+
+/// System V ABI の初期スタックを構築し、argc を指すスタックポインタを返す
+///
+/// 上位アドレスから順に 文字列 / AT_RANDOM用の16バイト / 補助ベクタ /
+/// envp / argv / argc を配置する。文字列はNUL終端済みであること。
+pub fn build_initial_stack(loaded: &LoadedImage, argv: &[Vec<u8>], envp: &[Vec<u8>]) -> KernelResult<u64> {
+    let l4 = loaded.address_space.page_table_frame();
+    let mut sp = loaded.stack_top;
+
+    // 1. 文字列本体
+    let push_strings = |strings: &[Vec<u8>], sp: &mut u64| -> KernelResult<Vec<u64>> {
+        let mut addresses = Vec::with_capacity(strings.len());
+        for string in strings {
+            *sp -= string.len() as u64;
+            paging::write_user_memory(l4, *sp, string)?;
+            addresses.push(*sp);
+        }
+        Ok(addresses)
+    };
+    let envp_addrs = push_strings(envp, &mut sp)?;
+    let argv_addrs = push_strings(argv, &mut sp)?;
+
+    // 2. AT_RANDOM 用のバイト列
+    sp -= 16;
+    let random_addr = sp;
+    paging::write_user_memory(l4, random_addr, &random_bytes())?;
+    sp &= !0xF;
+
+    // 3. ポインタ領域（補助ベクタ + envp + argv + argc）
+    let mut auxv: Vec<(u64, u64)> = Vec::new();
+    if loaded.phdr != 0 {
+        auxv.push((AT_PHDR, loaded.phdr));
+    }
+    auxv.push((AT_PHENT, elf::PROGRAM_HEADER_SIZE as u64));
+    auxv.push((AT_PHNUM, loaded.phnum));
+    auxv.push((AT_PAGESZ, 4096));
+    auxv.push((AT_ENTRY, loaded.entry));
+    auxv.push((AT_RANDOM, random_addr));
+    auxv.push((AT_NULL, 0));
+
+    let mut words: Vec<u64> = Vec::new();
+    words.push(argv.len() as u64);
+    words.extend_from_slice(&argv_addrs);
+    words.push(0);
+    words.extend_from_slice(&envp_addrs);
+    words.push(0);
+    for (key, value) in auxv {
+        words.push(key);
+        words.push(value);
+    }
+
+    // argc の位置が16バイト境界になるよう調整
+    sp -= (words.len() * 8) as u64;
+    sp &= !0xF;
+
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    paging::write_user_memory(l4, sp, &bytes)?;
+    Ok(sp)
+}
+
+// AT_RANDOM 用の疑似乱数（TSCから生成）
+fn random_bytes() -> [u8; 16] {
+    let mut state = unsafe { core::arch::x86_64::_rdtsc() } | 1;
+    let mut bytes = [0u8; 16];
+    for chunk in bytes.chunks_mut(8) {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        chunk.copy_from_slice(&state.to_le_bytes());
+    }
+    bytes
+}
+
+/// イメージをロードし、初期スタックまで構築する
+///
+/// 失敗した場合、作成途中のアドレス空間は解放される。
+pub fn prepare_image(image: &[u8], argv: &[Vec<u8>], envp: &[Vec<u8>]) -> KernelResult<(LoadedImage, u64)> {
+    let loaded = elf::load(image)?;
+    let sp = build_initial_stack(&loaded, argv, envp)?;
+    Ok((loaded, sp))
+}
+
+/// 現在のプロセスのイメージを置き換える
+///
+/// PID・親子関係・プロセスグループ・セッションはそのまま引き継ぎ、
+/// アドレス空間とレジスタだけを新しいイメージのものにする。
+/// `tid`は呼び出したスレッド。成功すると `context` は新しいエントリポイントから
+/// 始まる状態になる。
+pub fn execve(tid: u64, context: &mut ProcessContext, path: &str, argv: &[Vec<u8>], envp: &[Vec<u8>]) -> Result<(), Errno> {
+    let image = find_program(path).ok_or(Errno::ENOENT)?;
+    let (loaded, user_rsp) = prepare_image(&image, argv, envp).map_err(Errno::from)?;
+
+    let mut sched = SCHEDULER.lock();
+    // 新しいイメージはメインスレッドだけで始まる。ほかのスレッドは古いイメージと
+    // 一緒に終了させ、メインスレッド以外から呼ばれたならそのスレッドがPIDを引き継ぐ
+    let pid = sched.exec_single_thread(tid).map_err(Errno::from)?;
+    crate::syscall::set_current_process_id(pid);
+    let process = sched.processes.iter_mut().find(|p| p.id == pid).ok_or(Errno::ESRCH)?;
+
+    // 新しいアドレス空間へ切り替える。古い方は最後の参照がなくなったときに解放される
+    let address_space = Arc::new(loaded.address_space);
+    unsafe {
+        Cr3::write(address_space.page_table_frame(), Cr3Flags::empty());
+    }
+    vma::set_current(Some(address_space.clone()));
+    process.stats.memory_used = address_space.total_size();
+    process.address_space = address_space;
+    // 古いイメージのシグナルハンドラとTLS、FPUの状態はもう存在しない
+    process.signals.reset_for_exec();
+    process.fs_base = 0;
+    FsBase::write(VirtAddr::zero());
+    process.fpu = crate::fpu::ExtendedState::new();
+    crate::fpu::release(pid);
+
+    *context = ProcessContext::new_user(loaded.entry, user_rsp);
+    Ok(())
+}
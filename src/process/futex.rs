@@ -0,0 +1,99 @@
+//! フューテックス（ユーザー空間の同期の土台）
+//!
+//! FUTEX_WAIT / FUTEX_WAKE を提供する。待ち行列のキーはフューテックス語の
+//! 物理アドレスなので、fork後の共有ページや`MemoryHandle`で共有した領域でも
+//! 別々のプロセスが同じフューテックスで待ち合わせられる。待っているプロセスは
+//! `WaitReason::Futex`で止まり、FUTEX_WAKE・タイムアウト・シグナルで
+//! スケジューラから起こされる。
+
+use x86_64::VirtAddr;
+
+use crate::error::{Errno, KernelResult};
+use crate::memory::uaccess;
+use crate::syscall::table::{ArgKind, SyscallEntry, SyscallResult, SyscallTable};
+use crate::syscall::{self, SyscallArgs};
+use crate::timer::{self, Timespec};
+use super::scheduler::SCHEDULER;
+
+// futex の op（Linux x86_64と同じ値）
+pub const FUTEX_WAIT: u64 = 0;
+pub const FUTEX_WAKE: u64 = 1;
+/// プロセス内だけのフューテックス（キーが物理アドレスなので区別は不要）
+pub const FUTEX_PRIVATE_FLAG: u64 = 128;
+/// タイムアウトを実時間で指定する（実時間時計がないので未サポート）
+pub const FUTEX_CLOCK_REALTIME: u64 = 256;
+
+/// フューテックスのキー（フューテックス語の物理アドレス）を求める
+///
+/// 書き込みとして変換し、コピーオンライトのページはここで複製しておく。
+/// そうしないとfork直後の親子が同じキーになり、複製後にキーが変わってしまう。
+/// 読み取り専用の領域は読み取りとして変換する。
+pub fn futex_key(uaddr: u64) -> Result<u64, Errno> {
+    if !uaddr.is_multiple_of(4) {
+        return Err(Errno::EINVAL);
+    }
+    uaccess::check_user_range(uaddr, 4)?;
+    let addr = VirtAddr::new(uaddr);
+    let phys = match uaccess::translate_user(addr, true) {
+        Err(Errno::EFAULT) => uaccess::translate_user(addr, false)?,
+        result => result?,
+    };
+    Ok(phys.as_u64())
+}
+
+/// フューテックス系システムコールを登録
+pub fn register_syscalls(table: &mut SyscallTable) -> KernelResult<()> {
+    table.register(SyscallEntry::new(syscall::SYS_FUTEX, "futex", sys_futex,
+        &[ArgKind::UserPtr, ArgKind::Flags, ArgKind::Value, ArgKind::UserPtr, ArgKind::UserPtr, ArgKind::Value]))?;
+    Ok(())
+}
+
+// futex: フューテックスで待つ・起こす
+// 引数: RDI=uaddr, RSI=op, RDX=val, R10=timeout, R8=uaddr2, R9=val3
+fn sys_futex(args: &SyscallArgs) -> SyscallResult {
+    let (uaddr, op, val) = (args.arg1, args.arg2, args.arg3);
+    if op & FUTEX_CLOCK_REALTIME != 0 {
+        return Err(Errno::ENOSYS);
+    }
+    match op & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => futex_wait(uaddr, val as u32, args.arg4),
+        FUTEX_WAKE => futex_wake(uaddr, val as u32),
+        _ => Err(Errno::ENOSYS),
+    }
+}
+
+// *uaddr == val のあいだ待つ。timeout（相対時間）が0でなければその時間で諦める。
+// 起こされたときは0、タイムアウトはETIMEDOUT、シグナルはEINTRで戻る
+fn futex_wait(uaddr: u64, val: u32, timeout: u64) -> SyscallResult {
+    let key = futex_key(uaddr)?;
+    let deadline = if timeout == 0 {
+        None
+    } else {
+        let request: Timespec = uaccess::get_user(timeout)?;
+        let nanos = request.to_nanos().ok_or(Errno::EINVAL)?;
+        Some(timer::get_global_tick().saturating_add(timer::nanos_to_ticks(nanos)))
+    };
+
+    // システムコール中は割り込みが入らないので、値の確認から待ち行列に入るまでの
+    // あいだにFUTEX_WAKEが割り込むことはない
+    let current: u32 = uaccess::get_user(uaddr)?;
+    if current != val {
+        return Err(Errno::EAGAIN);
+    }
+    if let Some(deadline) = deadline
+        && deadline <= timer::get_global_tick()
+    {
+        return Err(Errno::ETIMEDOUT);
+    }
+
+    let current_pid = syscall::get_current_process_id();
+    SCHEDULER.lock().futex_wait(current_pid, key, deadline)?;
+    Ok(0)
+}
+
+// uaddr で待っているプロセスを最大 val 個起こし、起こした数を返す
+fn futex_wake(uaddr: u64, val: u32) -> SyscallResult {
+    let key = futex_key(uaddr)?;
+    let woken = SCHEDULER.lock().futex_wake(key, val as usize);
+    Ok(woken as u64)
+}
@@ -1,9 +1,10 @@
-use x86_64::{structures::paging::{PhysFrame, Size4KiB, FrameAllocator, OffsetPageTable}};
+use x86_64::structures::paging::PhysFrame;
 use spin::Mutex;
 use lazy_static::lazy_static;
-use crate::error::{KernelError, ProcessError};
+use crate::error::{AllocError, KernelError, ProcessError};
 use crate::error::KernelResult;
 use crate::kerror;
+use crate::memory::vma::AddressSpace;
 use core::{future::Future, pin::Pin, task::{Context, Poll}};
 use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
 use crossbeam_queue::ArrayQueue;
@@ -13,14 +14,21 @@ use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use futures_util::task::AtomicWaker;
 use futures_util::stream::{Stream, StreamExt};
 
+pub mod elf;
+pub mod exec;
+pub mod futex;
+pub mod resource;
 pub mod scheduler;
+pub mod signal;
+pub mod syscalls;
 
 pub const DEFAULT_PRIORITY: u8 = 10;
 
+/// プロセスごとのカーネルスタックのサイズ
+pub const KERNEL_STACK_SIZE: usize = 4096 * 4;
+
 lazy_static! {
     static ref NEXT_PID: Mutex<u64> = Mutex::new(1);
-    static ref NEXT_PGID: Mutex<u64> = Mutex::new(1);
-    static ref NEXT_SID: Mutex<u64> = Mutex::new(1);
 }
 
 // Simple timestamp counter for process creation times
@@ -40,20 +48,6 @@ pub fn allocate_pid() -> u64 {
     current
 }
 
-pub fn allocate_pgid() -> u64 {
-    let mut pgid = NEXT_PGID.lock();
-    let current = *pgid;
-    *pgid += 1;
-    current
-}
-
-pub fn allocate_sid() -> u64 {
-    let mut sid = NEXT_SID.lock();
-    let current = *sid;
-    *sid += 1;
-    current
-}
-
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ProcessState {
     Running,
@@ -66,9 +60,21 @@ pub enum ProcessState {
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum WaitReason {
     Child(u64),
+    /// メッセージの受信待ち（端点のキー）
     IpcReceive(u64),
+    /// 受信キューの空き待ち（送信側の端点のキー）
     IpcSend(u64),
     Sleep(u64),
+    /// フューテックス待ち（キーの物理アドレス, タイムアウトのティック）
+    Futex(u64, Option<u64>),
+    /// スレッドの終了待ち（join）
+    Thread(u64),
+    /// 同期IPCの返信待ち（ipc_call、呼び出した端点のキー）
+    IpcCall(u64),
+    /// 同期IPCの呼び出し待ち（ipc_reply_wait、自分の端点のキー）
+    IpcReplyWait(u64),
+    /// 名前サービスへのサービスの登録待ち（lookup_service）
+    ServiceLookup,
     AsyncPoll,
 }
 
@@ -121,6 +127,132 @@ pub struct ProcessContext {
     ss: u64,
 }
 
+// 割り込み・システムコールの入口のアセンブリは、汎用レジスタ15個と
+// IRETQ用フレーム5個をこの並びで積む
+const _: () = assert!(core::mem::size_of::<ProcessContext>() == 20 * 8);
+
+impl ProcessContext {
+    /// ユーザーモードで`entry_point`から実行を始めるコンテキスト
+    pub fn new_user(entry_point: u64, user_stack_top: u64) -> Self {
+        ProcessContext {
+            r15: 0, r14: 0, r13: 0, r12: 0,
+            rbp: 0, rbx: 0,
+            r11: 0, r10: 0, r9: 0, r8: 0,
+            rdi: 0, rsi: 0, rdx: 0, rcx: 0, rax: 0,
+
+            rip: entry_point,
+            cs: 0x23,         // ユーザーコードセグメント (GDTのインデックスに合わせて！)
+            rflags: 0x202,    // 割り込み許可フラグ
+            rsp: user_stack_top, // ユーザーモードでのスタックポインタ
+            ss: 0x1b,         // ユーザーデータセグメント
+        }
+    }
+
+    /// カーネルモード（Ring 0）で`rip`から実行を始めるコンテキスト
+    pub fn new_kernel(rip: u64, rsp: u64) -> Self {
+        ProcessContext {
+            rip,
+            cs: 0x08,         // カーネルコードセグメント
+            ss: 0x10,         // カーネルデータセグメント
+            rsp,
+            ..Self::new_user(0, 0)
+        }
+    }
+
+    /// システムコールの戻り値（RAX）を設定
+    pub fn set_return_value(&mut self, value: u64) {
+        self.rax = value;
+    }
+
+    /// システムコール番号（RAX）
+    pub fn syscall_number(&self) -> u64 {
+        self.rax
+    }
+
+    /// システムコールの引数（Linux x86_64の規約でRDI, RSI, RDX, R10, R8, R9の順）
+    pub fn syscall_args(&self) -> [u64; 6] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+
+    /// システムコールの引数レジスタを書き換える（`syscall_args`と同じ順）
+    ///
+    /// 同期IPCでは、受け取ったメッセージをこれらのレジスタに載せて復帰させる。
+    pub fn set_syscall_args(&mut self, args: [u64; 6]) {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9] = args;
+    }
+
+    /// 復帰後にシステムコール命令をもう一度実行させる
+    ///
+    /// `syscall`と`int 0x80`はどちらも2バイト命令なので、RIPを2戻せばよい。
+    /// RAXにはシステムコール番号が残っている必要がある。
+    pub fn restart_syscall(&mut self) {
+        self.rip -= 2;
+    }
+
+    /// `restart_syscall`を取り消し、`value`を戻り値にしてシステムコールの次から再開させる
+    pub fn cancel_restart(&mut self, value: u64) {
+        self.rip += 2;
+        self.rax = value;
+    }
+
+    pub fn instruction_pointer(&self) -> u64 {
+        self.rip
+    }
+
+    pub fn stack_pointer(&self) -> u64 {
+        self.rsp
+    }
+
+    /// ユーザーモード（Ring 3）に戻るコンテキストか
+    pub fn is_user_mode(&self) -> bool {
+        self.cs & 3 == 3
+    }
+}
+
+/// プロセスごとのカーネルスタック
+///
+/// 割り込み・システムコールでRing 0に入ると、CPUはこのスタックの先頭に
+/// `ProcessContext`を積む。プロセスが切り替わっても互いのコンテキストを
+/// 壊さないよう、プロセスごとに別の領域をヒープから確保する。
+pub struct KernelStack {
+    memory: Box<[u64]>,
+}
+
+impl KernelStack {
+    pub fn new() -> Self {
+        KernelStack {
+            memory: alloc::vec![0u64; KERNEL_STACK_SIZE / 8].into_boxed_slice(),
+        }
+    }
+
+    /// ヒープが足りなければパニックせずにエラーを返す（fork・cloneで使う）
+    pub fn try_new() -> KernelResult<Self> {
+        let mut memory = alloc::vec::Vec::new();
+        if memory.try_reserve_exact(KERNEL_STACK_SIZE / 8).is_err() {
+            return kerror!(AllocError::OutOfMemory);
+        }
+        memory.resize(KERNEL_STACK_SIZE / 8, 0u64);
+        Ok(KernelStack { memory: memory.into_boxed_slice() })
+    }
+
+    /// スタックの最上部（16バイト境界）
+    pub fn top(&self) -> u64 {
+        let end = self.memory.as_ptr() as u64 + (self.memory.len() * 8) as u64;
+        end & !0xF
+    }
+
+    /// スタック最上部に置いたコンテキストへのポインタ
+    pub fn initial_context_ptr(&self) -> u64 {
+        self.top() - core::mem::size_of::<ProcessContext>() as u64
+    }
+}
+
+impl Default for KernelStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct AsyncTask {
     id: TaskId,
     future: Pin<Box<dyn Future<Output = ()> + Send>>,
@@ -157,7 +289,7 @@ impl Default for ResourceLimits {
     fn default() -> Self {
         Self {
             max_memory: 64 * 1024 * 1024,  // 64MB default
-            max_cpu_time: 30 * 1000,       // 30 seconds default
+            max_cpu_time: u64::MAX,        // No CPU time limit by default
             max_processes: 32,             // 32 processes default
             max_files: 16,                 // 16 files default
         }
@@ -166,106 +298,227 @@ impl Default for ResourceLimits {
 
 #[derive(Debug)]
 pub struct ProcessStats {
-    pub cpu_time_used: u64,    // CPU time used in milliseconds
+    pub cpu_time_used: u64,    // CPU time used in milliseconds (user + system)
+    pub user_time: u64,         // CPU time spent in user mode in milliseconds
+    pub system_time: u64,       // CPU time spent in the kernel in milliseconds
     pub memory_used: u64,       // Memory currently used in bytes
     pub children_count: u32,    // Number of living children
     pub files_opened: u32,     // Number of open files
+    pub children_user_time: u64,   // User time of reaped children in milliseconds
+    pub children_system_time: u64, // System time of reaped children in milliseconds
+    pub system_nanos_carry: u64,   // システム時間のミリ秒未満の端数（次の課金に繰り越す）
 }
 
 impl Default for ProcessStats {
     fn default() -> Self {
         Self {
             cpu_time_used: 0,
+            user_time: 0,
+            system_time: 0,
             memory_used: 0,
             children_count: 0,
             files_opened: 0,
+            children_user_time: 0,
+            children_system_time: 0,
+            system_nanos_carry: 0,
         }
     }
 }
 
 pub struct Process {
     pub id: u64,
+    /// 属するプロセス（スレッドグループ）のID。メインスレッドでは`id`と同じ
+    pub thread_group_id: u64,
     pub context_ptr: u64,
-    pub page_table_frame: PhysFrame,
+    pub kernel_stack: KernelStack,
+    pub address_space: Arc<AddressSpace>,  // ページテーブルとVMA
     pub state: ProcessState,
     pub parent_id: u64,
     pub children: alloc::vec::Vec<u64>,
     pub exit_code: i32,
     pub priority: u8,           // Priority level (0-31, lower = higher priority)
-    pub resource_limits: ResourceLimits,
+    pub donated_priority: Option<u8>, // ipc_callの呼び出し元から借りている優先度（返信するまで）
+    pub wait_age: u8,           // 実行可能なのに選ばれなかった回数（エージングで優先度を上げる）
+    pub resource_limits: ResourceLimits, // ソフトリミット（実際に適用される値）
+    pub hard_limits: ResourceLimits,     // ハードリミット（ソフトリミットの上限）
+    pub privileged: bool,                // 優先度を上げる・ハードリミットを上げる特権
     pub stats: ProcessStats,
     pub process_group_id: u64,   // Process group ID
     pub session_id: u64,         // Session ID
     pub creation_time: u64,      // Process creation timestamp
+    pub signals: signal::SignalState, // 保留中・ブロック中のシグナルとその動作
+    pub job_status: Option<signal::JobStatus>, // 親のwait4にまだ報告していない停止・再開
+    pub fs_base: u64,            // FS_BASE（TLS）。切り替え時に保存・復元する
+    pub fpu: crate::fpu::ExtendedState, // FPU/SSE/AVXレジスタ（遅延して保存・復元する）
+    pub sleep_rem: Option<(u64, u64)>, // nanosleep中の (残り時間の書き込み先, 期限のティック)
+    pub syscall_restart: bool,   // 最後のシステムコールがERESTARTSYSでやり直し待ちか
 }
 
 impl Process {
-    pub fn new(id: u64, entry_point: u64, stack_top: u64, mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Self {
-        // 1. ProcessContext構造体のサイズ分だけスタックの「下」を指す
-        let context_ptr = (stack_top - core::mem::size_of::<ProcessContext>() as u64) as *mut ProcessContext;
+    pub fn new(id: u64, entry_point: u64, stack_top: u64) -> Self {
+        // プロセス固有のページテーブルを作成
+        let page_table_frame = create_process_page_table_with_user_mappings();
+        Self::with_address_space(id, AddressSpace::from_page_table(page_table_frame), entry_point, stack_top)
+    }
 
-        // 2. プロセス固有のページテーブルを作成
-        let page_table_frame = create_process_page_table_with_user_mappings(mapper, frame_allocator);
+    /// ELF実行ファイルをロードしてプロセスを作成する
+    ///
+    /// 新しいアドレス空間にPT_LOADセグメントとユーザースタックをマップし、
+    /// ELFのエントリポイントから実行を始める。
+    pub fn from_elf(id: u64, image: &[u8]) -> KernelResult<Self> {
+        let (loaded, user_rsp) = exec::prepare_image(image, &[], &[])?;
+        Ok(Self::with_address_space(id, loaded.address_space, loaded.entry, user_rsp))
+    }
+
+    // 用意済みのアドレス空間でプロセスを作成する
+    fn with_address_space(id: u64, address_space: AddressSpace, entry_point: u64, stack_top: u64) -> Self {
+        // 1. カーネルスタックを確保し、その最上部にコンテキストを置く
+        let kernel_stack = KernelStack::new();
+        let context_ptr = kernel_stack.initial_context_ptr() as *mut ProcessContext;
 
         unsafe {
-            // 3. その場所に初期値を書き込む
-            (*context_ptr) = ProcessContext {
-                r15: 0, r14: 0, r13: 0, r12: 0,
-                rbp: 0, rbx: 0,
-                r11: 0, r10: 0, r9: 0, r8: 0,
-                rdi: 0, rsi: 0, rdx: 0, rcx: 0, rax: 0,
-
-                rip: entry_point,
-                cs: 0x23,         // ユーザーコードセグメント (GDTのインデックスに合わせて！)
-                rflags: 0x202,    // 割り込み許可フラグ
-                rsp: stack_top,   // ユーザーモードでのスタックポインタ
-                ss: 0x1b,         // ユーザーデータセグメント
-            };
+            // 2. その場所に初期値を書き込む
+            (*context_ptr) = ProcessContext::new_user(entry_point, stack_top);
         }
 
+        let stats = ProcessStats {
+            memory_used: address_space.total_size(),
+            ..ProcessStats::default()
+        };
+
         Process {
             id,
+            thread_group_id: id,
             context_ptr: context_ptr as u64,
-            page_table_frame,
+            kernel_stack,
+            address_space: Arc::new(address_space),
             state: ProcessState::Ready,
             parent_id: 0,
             children: alloc::vec::Vec::new(),
             exit_code: 0,
             priority: DEFAULT_PRIORITY,  // Default priority (medium)
+            donated_priority: None,
+            wait_age: 0,
+            sleep_rem: None,
+            syscall_restart: false,
             resource_limits: ResourceLimits::default(),
-            stats: ProcessStats::default(),
+            hard_limits: ResourceLimits::default(),
+            privileged: false, // 特権は起動時にカーネルが与える（forkでは引き継がない）
+            stats,
             process_group_id: id,  // Initially, process is its own group leader
             session_id: id,        // Initially, process is its own session leader
             creation_time: get_current_time(),
+            signals: signal::SignalState::new(),
+            job_status: None,
+            fs_base: 0,
+            fpu: crate::fpu::ExtendedState::new(),
         }
     }
 
-    pub fn fork(&self, mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<Self, &'static str> {
+    /// 子プロセスを作成する
+    ///
+    /// `parent_context`はシステムコール入口で保存された親のレジスタ。子は
+    /// 親のユーザー空間をコピーオンライトで共有し、自分のカーネルスタックを
+    /// 持って同じ位置からRAX=0で実行を再開する。
+    pub fn fork(&self, parent_context: &ProcessContext) -> KernelResult<Self> {
+        // カーネルスタックはヒープから取るので、足りなければ何も複製せずに失敗する
+        let kernel_stack = KernelStack::try_new()?;
+
+        // Duplicate the user address space (copy-on-write)
+        let address_space = Arc::new(self.address_space.fork()?);
+
         // Allocate new PID for child
         let child_pid = allocate_pid();
-        
-        // Copy the current context (registers will be set after fork)
-        let current_context = unsafe { &*(self.context_ptr as *const ProcessContext) };
-        
-        // Create child process with same entry point and stack
-        let mut child = Self::new(child_pid, current_context.rip, current_context.rsp, mapper, frame_allocator);
-        
-        // Set parent-child relationship
-        child.parent_id = self.id;
-        child.process_group_id = self.process_group_id;  // Inherit process group
-        child.session_id = self.session_id;              // Inherit session
-        child.priority = self.priority;                  // Inherit priority
-        child.resource_limits = self.resource_limits.clone(); // Inherit limits
-        
-        // Copy register state from parent
-        let child_context = unsafe { &mut *(child.context_ptr as *mut ProcessContext) };
-        *child_context = current_context.clone();
-        
+
+        // Copy register state from parent onto the child's kernel stack
+        let context_ptr = kernel_stack.initial_context_ptr() as *mut ProcessContext;
+        let mut child_context = *parent_context;
         // Set return values:
         // Parent gets child PID, child gets 0
-        child_context.rax = 0; // Child returns 0
-        
-        Ok(child)
+        child_context.set_return_value(0);
+        unsafe { *context_ptr = child_context };
+
+        Ok(Process {
+            id: child_pid,
+            thread_group_id: child_pid,
+            context_ptr: context_ptr as u64,
+            kernel_stack,
+            address_space,
+            state: ProcessState::Ready,
+            parent_id: self.id,
+            children: alloc::vec::Vec::new(),
+            exit_code: 0,
+            priority: self.priority,                          // Inherit priority
+            donated_priority: None,
+            wait_age: 0,
+            sleep_rem: None,
+            syscall_restart: false,
+            resource_limits: self.resource_limits.clone(),    // Inherit limits
+            hard_limits: self.hard_limits.clone(),
+            privileged: false,                                // 特権は子に引き継がない
+            stats: ProcessStats {
+                memory_used: self.stats.memory_used,          // The address space is shared copy-on-write
+                ..ProcessStats::default()
+            },
+            process_group_id: self.process_group_id,          // Inherit process group
+            session_id: self.session_id,                      // Inherit session
+            creation_time: get_current_time(),
+            signals: self.signals.fork(),                     // Inherit handlers and mask
+            job_status: None,
+            fs_base: self.fs_base,
+            fpu: self.fpu.duplicate(self.id),
+        })
+    }
+
+    /// 同じプロセスに新しいスレッドを作成する
+    ///
+    /// スレッドはページテーブル（`AddressSpace`）を共有し、自分のTID・
+    /// カーネルスタック・レジスタを持つ。`context`（clone呼び出し時のレジスタ）
+    /// から、スタックを`user_stack`に替えてRAX=0で実行を再開する。FS_BASEは
+    /// 呼び出し元と同じ値で始まる（CLONE_SETTLSで変えられる）。FPU/SSEの状態も引き継ぐ。
+    /// メモリ使用量・CPU時間・リソース制限はスレッドグループのリーダーが持つ。
+    pub fn spawn_thread(&self, context: &ProcessContext, user_stack: u64) -> KernelResult<Self> {
+        let kernel_stack = KernelStack::try_new()?;
+        let tid = allocate_pid();
+
+        let context_ptr = kernel_stack.initial_context_ptr() as *mut ProcessContext;
+        let mut thread_context = *context;
+        thread_context.rsp = user_stack;
+        thread_context.set_return_value(0);
+        unsafe { *context_ptr = thread_context };
+
+        Ok(Process {
+            id: tid,
+            thread_group_id: self.thread_group_id,
+            context_ptr: context_ptr as u64,
+            kernel_stack,
+            address_space: self.address_space.clone(),
+            state: ProcessState::Ready,
+            parent_id: self.parent_id,
+            children: alloc::vec::Vec::new(),
+            exit_code: 0,
+            priority: self.priority,
+            donated_priority: None,
+            wait_age: 0,
+            sleep_rem: None,
+            syscall_restart: false,
+            resource_limits: self.resource_limits.clone(),
+            hard_limits: self.hard_limits.clone(),
+            privileged: self.privileged,
+            stats: ProcessStats::default(),
+            process_group_id: self.process_group_id,
+            session_id: self.session_id,
+            creation_time: get_current_time(),
+            signals: self.signals.fork(),
+            job_status: None,
+            fs_base: self.fs_base,
+            fpu: self.fpu.duplicate(self.id),
+        })
+    }
+
+    /// メインスレッド以外のスレッドか
+    pub fn is_thread(&self) -> bool {
+        self.id != self.thread_group_id
     }
 
     /// Exit the current process with the given exit code
@@ -318,8 +571,8 @@ impl Process {
     /// Check if the process can be safely terminated
     pub fn can_terminate(&self) -> bool {
         match self.state {
-            ProcessState::Running | ProcessState::Ready | ProcessState::Waiting(_) => true,
-            ProcessState::Zombie | ProcessState::Stopped => false,
+            ProcessState::Running | ProcessState::Ready | ProcessState::Waiting(_) | ProcessState::Stopped => true,
+            ProcessState::Zombie => false,
         }
     }
 
@@ -332,6 +585,14 @@ impl Process {
         Ok(())
     }
 
+    /// スケジューリングに使う優先度
+    ///
+    /// 同期IPCの呼び出しを処理しているあいだは、呼び出し元から借りた優先度の方が
+    /// 高ければそちらを使う。
+    pub fn effective_priority(&self) -> u8 {
+        self.donated_priority.map_or(self.priority, |donated| donated.min(self.priority))
+    }
+
     /// Update resource limits
     pub fn set_resource_limits(&mut self, limits: ResourceLimits) -> KernelResult<()> {
         // Validate limits
@@ -392,6 +653,44 @@ impl Process {
         self.stats.cpu_time_used += cpu_time;
     }
 
+    /// タイマー割り込み1回分のCPU時間を課金する
+    ///
+    /// `user_mode`は割り込まれたのがユーザーモードかどうか。CPU時間の制限を
+    /// 超えた場合は送るべきシグナル（SIGXCPU / SIGKILL）を返す。
+    pub fn charge_tick(&mut self, user_mode: bool) -> Option<u64> {
+        self.charge_cpu_time(crate::timer::MILLIS_PER_TICK, user_mode)
+    }
+
+    /// システムコールで使ったCPU時間（ナノ秒）をシステム時間として課金する
+    ///
+    /// ミリ秒未満の端数は次の課金に繰り越す。戻り値は`charge_tick`と同じ。
+    pub fn charge_system_time(&mut self, nanos: u64) -> Option<u64> {
+        const NANOS_PER_MILLI: u64 = 1_000_000;
+        let total = self.stats.system_nanos_carry + nanos;
+        self.stats.system_nanos_carry = total % NANOS_PER_MILLI;
+        self.charge_cpu_time(total / NANOS_PER_MILLI, false)
+    }
+
+    // CPU時間を課金し、制限を超えていれば送るべきシグナルを返す
+    fn charge_cpu_time(&mut self, millis: u64, user_mode: bool) -> Option<u64> {
+        if millis == 0 {
+            return None;
+        }
+        let before = self.stats.cpu_time_used;
+        self.update_cpu_usage(millis);
+        if user_mode {
+            self.stats.user_time += millis;
+        } else {
+            self.stats.system_time += millis;
+        }
+        resource::cpu_limit_signal(
+            before,
+            self.stats.cpu_time_used,
+            self.resource_limits.max_cpu_time,
+            self.hard_limits.max_cpu_time,
+        )
+    }
+
     /// Update memory usage statistics
     pub fn update_memory_usage(&mut self, memory_used: u64) {
         self.stats.memory_used = memory_used;
@@ -412,10 +711,10 @@ impl Process {
     }
 
     /// Create a new process group with this process as leader
+    /// プロセスグループIDはリーダーのPIDと同じになる
     pub fn create_process_group(&mut self) -> KernelResult<u64> {
-        let new_pgid = allocate_pgid();
-        self.process_group_id = new_pgid;
-        Ok(new_pgid)
+        self.process_group_id = self.id;
+        Ok(self.id)
     }
 
     /// Join an existing process group
@@ -428,15 +727,26 @@ impl Process {
     }
 
     /// Create a new session with this process as leader
+    /// 新しいセッションと、その中の新しいプロセスグループのリーダーになる
     pub fn create_session(&mut self) -> KernelResult<u64> {
-        // Only process group leaders can create sessions
-        if self.process_group_id != self.id {
+        // Process group leaders cannot create sessions (their group would span two sessions)
+        if self.is_group_leader() {
             return kerror!(ProcessError::InvalidState);
         }
-        
-        let new_sid = allocate_sid();
-        self.session_id = new_sid;
-        Ok(new_sid)
+
+        self.session_id = self.id;
+        self.process_group_id = self.id;
+        Ok(self.id)
+    }
+
+    /// Check if this process leads its process group
+    pub fn is_group_leader(&self) -> bool {
+        self.process_group_id == self.id
+    }
+
+    /// Check if this process leads its session
+    pub fn is_session_leader(&self) -> bool {
+        self.session_id == self.id
     }
 }
 
@@ -628,29 +938,11 @@ pub async fn process_keyboard_input() {
     }
 }
 
-// プロセス固有のページテーブルを作成し、ユーザー空間のマッピングをコピーする関数
-fn create_process_page_table_with_user_mappings(mapper: &mut OffsetPageTable, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> PhysFrame {
-    use x86_64::structures::paging::PageTable;
-
-    // 新しいL4ページテーブルフレームを割り当てる
-    let page_table_frame = frame_allocator.allocate_frame().expect("no frames available for page table");
-
-    // 物理メモリオフセットを取得
-    let phys_offset = mapper.phys_offset();
-
-    // 新しいページテーブルの仮想アドレスを取得
-    let new_table_virt = phys_offset + page_table_frame.start_address().as_u64();
-    let new_table = unsafe { &mut *(new_table_virt.as_mut_ptr() as *mut PageTable) };
-
-    // 現在のページテーブル（カーネルページテーブル）を取得
-    let current_table = mapper.level_4_table();
-
-    // 全てのエントリをコピー（カーネルマッピング + ユーザーマッピング）
-    for i in 0..512 {
-        new_table[i] = current_table[i].clone();
-    }
-
-    page_table_frame
+// プロセス固有のページテーブルを作成する関数
+// カーネルのページテーブルを複製し、ブート時にマップしたユーザーページはCOWで共有する
+fn create_process_page_table_with_user_mappings() -> PhysFrame {
+    crate::memory::paging::clone_address_space(crate::memory::kernel_page_table(), &[])
+        .expect("no frames available for page table")
 }
 
 #[unsafe(no_mangle)]
@@ -671,7 +963,17 @@ pub extern "C" fn handle_switch(current_context_ptr: u64) -> u64 {
     println!("Switching! Task User RSP: {:#x}", ctx.rsp);
 
     let mut sched = SCHEDULER.lock();
-    // 2. 切り替えロジック
-    sched.schedule(current_context_ptr)
-
+    // 2. 実行中だったプロセスにCPU時間を課金する
+    //    （FS_BASEなどレジスタ以外の状態はscheduleでの切り替え時に保存・復元する）
+    sched.charge_current_tick(ctx.is_user_mode());
+    // 3. 期限の来たスリープ中のプロセスを起こす
+    sched.wake_sleepers(crate::timer::get_global_tick());
+    // 4. 切り替えロジック
+    let context_ptr = sched.schedule(current_context_ptr);
+    // 5. ユーザーモードに戻る前に保留中のシグナルを配送する
+    let context_ptr = sched.deliver_signals(context_ptr);
+    drop(sched);
+    // 6. 終了したプロセスのチャンネルを解放する（SCHEDULERのロックの外で）
+    scheduler::release_exited();
+    context_ptr
 }
@@ -0,0 +1,182 @@
+//! 優先度とリソース制限
+//!
+//! getpriority / setpriority のnice値と`Process::priority`の対応、
+//! getrlimit / setrlimit / prlimit64 の`struct rlimit`と`ResourceLimits`の対応、
+//! getrusage の`struct rusage`。ソフトリミットは`Process::resource_limits`、
+//! ハードリミットは`Process::hard_limits`に持つ。
+
+use crate::error::Errno;
+use crate::timer::Timeval;
+use super::scheduler::{MAX_PRIORITY, MIN_PRIORITY, DEFAULT_PRIORITY};
+use super::signal::{SIGKILL, SIGXCPU};
+use super::ResourceLimits;
+
+// getpriority / setpriority の which
+pub const PRIO_PROCESS: u64 = 0;
+pub const PRIO_PGRP: u64 = 1;
+pub const PRIO_USER: u64 = 2;
+
+/// nice値の範囲
+pub const NICE_MIN: i64 = -20;
+pub const NICE_MAX: i64 = 19;
+
+// リソースの種類（Linux x86_64と同じ番号）
+pub const RLIMIT_CPU: u64 = 0;
+pub const RLIMIT_NPROC: u64 = 6;
+pub const RLIMIT_NOFILE: u64 = 7;
+pub const RLIMIT_AS: u64 = 9;
+/// リソースの種類の数
+pub const RLIM_NLIMITS: u64 = 16;
+/// 制限なし
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+// getrusage の who
+pub const RUSAGE_SELF: i64 = 0;
+pub const RUSAGE_CHILDREN: i64 = -1;
+
+/// nice値を優先度に変換する（nice 0 が既定の優先度。範囲外は丸める）
+pub fn nice_to_priority(nice: i64) -> u8 {
+    (DEFAULT_PRIORITY as i64 + nice.clamp(NICE_MIN, NICE_MAX))
+        .clamp(MAX_PRIORITY as i64, MIN_PRIORITY as i64) as u8
+}
+
+/// 優先度をnice値に変換する
+pub fn priority_to_nice(priority: u8) -> i64 {
+    (priority as i64 - DEFAULT_PRIORITY as i64).clamp(NICE_MIN, NICE_MAX)
+}
+
+/// 優先度を`current`から`new`に変えてよいか
+///
+/// 優先度を上げる（niceを下げる）には特権が必要。Linuxのsetpriorityと同じく
+/// EACCESで拒否する。
+pub fn check_priority_change(current: u8, new: u8, privileged: bool) -> Result<(), Errno> {
+    if new < current && !privileged {
+        return Err(Errno::EACCES);
+    }
+    Ok(())
+}
+
+/// CPU時間の制限を超えたときに送るシグナル
+///
+/// `before`から`now`（ミリ秒）まで課金したときに、ハードリミットに達していれば
+/// SIGKILL、ソフトリミットを超えていればSIGXCPUを返す。SIGXCPUはLinuxと
+/// 同じく、ソフトリミットを超えてからも1秒ごとに送る。
+pub fn cpu_limit_signal(before: u64, now: u64, soft: u64, hard: u64) -> Option<u64> {
+    if now >= hard {
+        Some(SIGKILL)
+    } else if now >= soft && (before < soft || before / 1000 != now / 1000) {
+        Some(SIGXCPU)
+    } else {
+        None
+    }
+}
+
+/// ユーザー空間とやり取りする`struct rlimit`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rlimit {
+    pub rlim_cur: u64,
+    pub rlim_max: u64,
+}
+
+// 制限値の単位の変換（RLIMIT_CPUは秒、ResourceLimitsはミリ秒）
+fn cpu_millis_to_rlim(millis: u64) -> u64 {
+    if millis == u64::MAX { RLIM_INFINITY } else { millis / 1000 }
+}
+
+fn cpu_rlim_to_millis(seconds: u64) -> u64 {
+    seconds.saturating_mul(1000)
+}
+
+fn count_to_rlim(count: u32) -> u64 {
+    if count == u32::MAX { RLIM_INFINITY } else { count as u64 }
+}
+
+fn rlim_to_count(value: u64) -> u32 {
+    value.min(u32::MAX as u64) as u32
+}
+
+/// `ResourceLimits`から1つのリソースの値を取り出す
+///
+/// 管理していないリソースは`None`（getrlimitでは制限なしとして返す）。
+pub fn limit_of(limits: &ResourceLimits, resource: u64) -> Option<u64> {
+    match resource {
+        RLIMIT_CPU => Some(cpu_millis_to_rlim(limits.max_cpu_time)),
+        RLIMIT_NPROC => Some(count_to_rlim(limits.max_processes)),
+        RLIMIT_NOFILE => Some(count_to_rlim(limits.max_files)),
+        RLIMIT_AS => Some(limits.max_memory),
+        _ => None,
+    }
+}
+
+/// `ResourceLimits`の1つのリソースの値を設定する
+pub fn set_limit(limits: &mut ResourceLimits, resource: u64, value: u64) -> Result<(), Errno> {
+    match resource {
+        RLIMIT_CPU => limits.max_cpu_time = cpu_rlim_to_millis(value),
+        RLIMIT_NPROC => limits.max_processes = rlim_to_count(value),
+        RLIMIT_NOFILE => limits.max_files = rlim_to_count(value),
+        RLIMIT_AS => limits.max_memory = value,
+        _ => return Err(Errno::EINVAL),
+    }
+    Ok(())
+}
+
+/// ソフト・ハードリミットから`struct rlimit`を作る
+pub fn get_rlimit(soft: &ResourceLimits, hard: &ResourceLimits, resource: u64) -> Result<Rlimit, Errno> {
+    if resource >= RLIM_NLIMITS {
+        return Err(Errno::EINVAL);
+    }
+    Ok(Rlimit {
+        rlim_cur: limit_of(soft, resource).unwrap_or(RLIM_INFINITY),
+        rlim_max: limit_of(hard, resource).unwrap_or(RLIM_INFINITY),
+    })
+}
+
+/// `struct rlimit`でソフト・ハードリミットを変更する
+///
+/// ハードリミットを上げるには特権が必要。ソフトリミットはハードリミットまで
+/// 自由に変えられる。どちらかが失敗した場合は何も変更しない。
+pub fn apply_rlimit(soft: &mut ResourceLimits, hard: &mut ResourceLimits, resource: u64, new: &Rlimit, privileged: bool) -> Result<(), Errno> {
+    if resource >= RLIM_NLIMITS || new.rlim_cur > new.rlim_max {
+        return Err(Errno::EINVAL);
+    }
+    let current_max = limit_of(hard, resource).ok_or(Errno::EINVAL)?;
+    if new.rlim_max > current_max && !privileged {
+        return Err(Errno::EPERM);
+    }
+
+    let mut new_soft = soft.clone();
+    let mut new_hard = hard.clone();
+    set_limit(&mut new_soft, resource, new.rlim_cur)?;
+    set_limit(&mut new_hard, resource, new.rlim_max)?;
+    // Process::set_resource_limits と同じく、0（リソースを一切使えない）は無効
+    if new_soft.max_memory == 0 || new_soft.max_cpu_time == 0 {
+        return Err(Errno::EINVAL);
+    }
+    *soft = new_soft;
+    *hard = new_hard;
+    Ok(())
+}
+
+/// ユーザー空間とやり取りする`struct rusage`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rusage {
+    pub ru_utime: Timeval,
+    pub ru_stime: Timeval,
+    /// 最大常駐セットサイズ（KiB）
+    pub ru_maxrss: i64,
+    pub ru_ixrss: i64,
+    pub ru_idrss: i64,
+    pub ru_isrss: i64,
+    pub ru_minflt: i64,
+    pub ru_majflt: i64,
+    pub ru_nswap: i64,
+    pub ru_inblock: i64,
+    pub ru_oublock: i64,
+    pub ru_msgsnd: i64,
+    pub ru_msgrcv: i64,
+    pub ru_nsignals: i64,
+    pub ru_nvcsw: i64,
+    pub ru_nivcsw: i64,
+}
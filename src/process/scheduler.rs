@@ -1,10 +1,13 @@
-use alloc::collections::VecDeque;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
 use spin::Mutex;
-use super::{Process, ProcessState, WaitReason, TaskBehavior, TaskType, AsyncTask};
-use crate::error::{KernelError, ProcessError};
+use super::{Process, ProcessContext, ProcessState, WaitReason, TaskBehavior, TaskType, AsyncTask};
+use super::signal::{self, DefaultAction, JobStatus, SIG_DFL, SIG_IGN, SA_NOCLDSTOP, SA_NODEFER, SA_RESETHAND};
+use crate::error::{IpcError, KernelError, ProcessError};
+use crate::ipc::CallMessage;
 use crate::error::KernelResult;
 use crate::kerror;
 use lazy_static::lazy_static;
+use x86_64::registers::model_specific::FsBase;
 use alloc::boxed::Box;
 
 /// Priority levels (0-31, lower = higher priority)
@@ -19,20 +22,84 @@ pub struct Scheduler {
     orphans: alloc::vec::Vec<u64>, // List of orphaned process IDs
     current_priority: u8, // Current priority being scheduled
     task_queue: alloc::collections::VecDeque<u64>, // Unified task queue for scheduling
+    sleep_queue: BTreeSet<(u64, u64)>, // (wake-up tick, PID), earliest first
+    futex_queues: BTreeMap<u64, VecDeque<u64>>, // futex key (physical address) -> waiting PIDs in FIFO order
+    ipc_callers: BTreeMap<u64, VecDeque<u64>>, // server endpoint key -> TIDs in ipc_call not yet received
+    ipc_replies: BTreeMap<u64, u64>, // server TID -> caller TID waiting for its reply
+    direct_switch: Option<u64>, // TID to switch to on return from the current syscall
+    exited: alloc::vec::Vec<u64>, // exited thread groups whose IPC resources are not yet released
+}
+
+/// wait4で待つ子
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitTarget {
+    /// どの子でもよい（pid = -1）
+    Any,
+    /// 指定したPIDの子（pid > 0）
+    Pid(u64),
+    /// 指定したプロセスグループの子（pid = 0 なら自分のグループ、pid < -1 なら -pid）
+    Group(u64),
+}
+
+impl WaitTarget {
+    /// 子`process`が待つ対象か
+    pub fn matches(&self, process: &Process) -> bool {
+        match *self {
+            WaitTarget::Any => true,
+            WaitTarget::Pid(pid) => process.id == pid,
+            WaitTarget::Group(pgid) => process.process_group_id == pgid,
+        }
+    }
+
+    /// 子の状態変化で起こしてもらうための`WaitReason::Child`の値
+    ///
+    /// グループの場合はどの子の変化でも起こし、wait4のやり直しで確かめる。
+    pub fn wait_pid(&self) -> u64 {
+        match *self {
+            WaitTarget::Pid(pid) => pid,
+            WaitTarget::Any | WaitTarget::Group(_) => u64::MAX,
+        }
+    }
+}
+
+/// ipc_reply_waitの結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyWait {
+    /// 待ち行列の呼び出しを受け取った（呼び出し元のTGID）
+    Received(u64),
+    /// 呼び出しが来るまで待つ。返信した呼び出し元がいればそのTID（直接切り替える）
+    Blocked(Option<u64>),
 }
 
 lazy_static! {
-    pub static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler {
-        processes: VecDeque::new(),
-        async_tasks: VecDeque::new(),
-        process_tree: alloc::collections::BTreeMap::new(),
-        orphans: alloc::vec::Vec::new(),
-        current_priority: DEFAULT_PRIORITY,
-        task_queue: alloc::collections::VecDeque::new(),
-    });
+    pub static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scheduler {
+    /// プロセスを持たない空のスケジューラ
+    pub fn new() -> Self {
+        Scheduler {
+            processes: VecDeque::new(),
+            async_tasks: VecDeque::new(),
+            process_tree: alloc::collections::BTreeMap::new(),
+            orphans: alloc::vec::Vec::new(),
+            current_priority: DEFAULT_PRIORITY,
+            task_queue: alloc::collections::VecDeque::new(),
+            sleep_queue: BTreeSet::new(),
+            futex_queues: BTreeMap::new(),
+            ipc_callers: BTreeMap::new(),
+            ipc_replies: BTreeMap::new(),
+            direct_switch: None,
+            exited: alloc::vec::Vec::new(),
+        }
+    }
+
     pub fn add_process(&mut self, process: Process) {
         // Add to process tree
         self.process_tree.insert(process.id, process.parent_id);
@@ -52,15 +119,30 @@ impl Scheduler {
     }
 
     /// Get next process based on priority scheduling
+    ///
+    /// 選ばれずに待った回数（`wait_age`）の分だけ優先度を上げて比べる（エージング）。
+    /// 優先度の高いプロセスがCPUを使い続けても、低いプロセスは優先度の差の
+    /// 回数だけ待てば選ばれる。
     fn get_next_process_by_priority(&mut self) -> Option<u64> {
-        // Find highest priority process in main processes list
-        for process in &mut self.processes {
-            if matches!(process.state, ProcessState::Ready | ProcessState::Running) {
-                return Some(process.id);
+        // Find highest priority process in main processes list.
+        // 同じ値ならキューの先頭（待ち時間の長い方）を選ぶ
+        self.processes.iter()
+            .filter(|p| matches!(p.state, ProcessState::Ready | ProcessState::Running))
+            .min_by_key(|p| p.effective_priority().saturating_sub(p.wait_age))
+            .map(|p| p.id)
+    }
+
+    /// 次に実行するプロセスを選び、選ばれなかった実行可能なプロセスを古くする
+    pub fn pick_next(&mut self) -> Option<u64> {
+        let next_pid = self.get_next_process_by_priority()?;
+        for process in self.processes.iter_mut() {
+            if process.id == next_pid {
+                process.wait_age = 0;
+            } else if process.state == ProcessState::Ready {
+                process.wait_age = process.wait_age.saturating_add(1);
             }
         }
-        
-        None
+        Some(next_pid)
     }
     
     /// Get next async task that's ready to run
@@ -110,7 +192,29 @@ impl Scheduler {
     }
 
     /// Handle process exit and clean up parent-child relationships
+    ///
+    /// スレッドを指定した場合もプロセス（スレッドグループ）全体が終了する。
+    /// ほかのスレッドはゾンビになり、親がリーダーを回収するときに一緒に消える。
     pub fn handle_process_exit(&mut self, exiting_pid: u64, exit_code: i32) -> KernelResult<()> {
+        let exiting_pid = self.processes.iter()
+            .find(|p| p.id == exiting_pid)
+            .map_or(exiting_pid, |p| p.thread_group_id);
+        for thread in self.processes.iter_mut().filter(|p| p.thread_group_id == exiting_pid && p.is_thread()) {
+            if thread.state != ProcessState::Zombie {
+                thread.state = ProcessState::Zombie;
+                thread.exit_code = exit_code;
+            }
+        }
+
+        // 同期IPCで処理中の呼び出しは、もう返信されない
+        let threads: alloc::vec::Vec<u64> = self.processes.iter()
+            .filter(|p| p.thread_group_id == exiting_pid)
+            .map(|p| p.id)
+            .collect();
+        for tid in threads {
+            self.abort_ipc(tid);
+        }
+
         // Find the exiting process
         let mut parent_pid = 0;
         for process in &mut self.processes {
@@ -154,28 +258,73 @@ impl Scheduler {
         }
 
         // Wake up parent if it's waiting for this child
-        if parent_pid != 0 {
-            for process in &mut self.processes {
-                if process.id == parent_pid {
-                    if let ProcessState::Waiting(WaitReason::Child(waiting_pid)) = process.state {
-                        if waiting_pid == exiting_pid || waiting_pid == u64::MAX {
-                            process.state = ProcessState::Ready;
-                        }
-                    }
-                    break;
-                }
-            }
+        self.notify_parent(exiting_pid, parent_pid, false);
+
+        // チャンネルはSCHEDULERのロックを外してから解放する（release_exited）
+        if !self.exited.contains(&exiting_pid) {
+            self.exited.push(exiting_pid);
         }
 
         Ok(())
     }
 
-    /// Find all zombie children of a parent process
-    pub fn find_zombie_children(&self, parent_pid: u64) -> alloc::vec::Vec<u64> {
+    /// 終了してまだIPCの資源を解放していないスレッドグループを取り出す
+    pub fn take_exited(&mut self) -> alloc::vec::Vec<u64> {
+        core::mem::take(&mut self.exited)
+    }
+
+    // 子の状態が変わった（終了・停止・再開）ことを親に通知する
+    //
+    // wait4で待っている親のスレッドを起こし、SIGCHLDを送る（既定では無視される）。
+    // `job_control`が真なら、親がSA_NOCLDSTOPを指定していればSIGCHLDは送らない。
+    fn notify_parent(&mut self, child_pid: u64, parent_pid: u64, job_control: bool) {
+        if parent_pid == 0 {
+            return;
+        }
+        for waiter in self.processes.iter_mut().filter(|p| p.thread_group_id == parent_pid) {
+            if let ProcessState::Waiting(WaitReason::Child(waiting_pid)) = waiter.state
+                && (waiting_pid == child_pid || waiting_pid == u64::MAX)
+            {
+                waiter.state = ProcessState::Ready;
+            }
+        }
+        let parent = match self.processes.iter_mut().find(|p| p.id == parent_pid) {
+            Some(parent) => parent,
+            None => return,
+        };
+        if job_control && parent.signals.action(signal::SIGCHLD).flags & SA_NOCLDSTOP != 0 {
+            return;
+        }
+        let _ = self.send_signal(parent_pid, signal::SIGCHLD);
+    }
+
+    /// 親にまだ報告していない子の停止・再開を取り出す（wait4のWUNTRACED / WCONTINUED）
+    ///
+    /// `target`に合う子のうち最初のものについて、子のPIDと
+    /// wait4が返すステータスを返す。
+    pub fn take_job_status(&mut self, parent_pid: u64, target: WaitTarget, stopped: bool, continued: bool) -> Option<(u64, i32)> {
+        let child = self.processes.iter_mut().find(|p| {
+            p.parent_id == parent_pid
+                && !p.is_thread()
+                && target.matches(p)
+                && match p.job_status {
+                    Some(JobStatus::Stopped(_)) => stopped && p.state == ProcessState::Stopped,
+                    Some(JobStatus::Continued) => continued,
+                    None => false,
+                }
+        })?;
+        let status = child.job_status.take()?;
+        Some((child.id, status.wait_status()))
+    }
+
+    /// Find all zombie children of a parent process that match `target`
+    pub fn find_zombie_children(&self, parent_pid: u64, target: WaitTarget) -> alloc::vec::Vec<u64> {
         let mut zombies = alloc::vec::Vec::new();
         
         for process in &self.processes {
-            if process.parent_id == parent_pid && process.state == ProcessState::Zombie {
+            if process.parent_id == parent_pid && !process.is_thread() && process.state == ProcessState::Zombie
+                && target.matches(process)
+            {
                 zombies.push(process.id);
             }
         }
@@ -186,13 +335,18 @@ impl Scheduler {
     /// Reap a zombie child and return its exit code
     pub fn reap_zombie_child(&mut self, parent_pid: u64, child_pid: u64) -> KernelResult<i32> {
         let mut exit_code = 0;
+        let mut children_times = (0, 0);
         let mut found = false;
         
         // Find and remove the zombie child
         self.processes.retain(|process| {
-            if process.id == child_pid && process.parent_id == parent_pid {
+            if process.id == child_pid && process.parent_id == parent_pid && !process.is_thread() {
                 if process.state == ProcessState::Zombie {
                     exit_code = process.exit_code;
+                    children_times = (
+                        process.stats.user_time + process.stats.children_user_time,
+                        process.stats.system_time + process.stats.children_system_time,
+                    );
                     found = true;
                     false // Remove from scheduler
                 } else {
@@ -205,9 +359,15 @@ impl Scheduler {
 
         // Update parent's children list
         if found {
+            // 子のスレッド（すでにゾンビ）も一緒に消す
+            self.processes.retain(|process| process.thread_group_id != child_pid);
+
             for process in &mut self.processes {
                 if process.id == parent_pid {
                     process.remove_child(child_pid);
+                    // getrusage(RUSAGE_CHILDREN)用に子の使用量を合算する
+                    process.stats.children_user_time += children_times.0;
+                    process.stats.children_system_time += children_times.1;
                     break;
                 }
             }
@@ -221,6 +381,102 @@ impl Scheduler {
         }
     }
 
+    /// スレッドを終了させ、そのスレッドをjoinで待っているスレッドを起こす
+    ///
+    /// 終了したスレッドはjoinで回収されるまでゾンビとして残る。
+    pub fn handle_thread_exit(&mut self, tid: u64, exit_code: i32) -> KernelResult<()> {
+        let thread = match self.processes.iter_mut().find(|p| p.id == tid) {
+            Some(thread) => thread,
+            None => return kerror!(ProcessError::NotFound),
+        };
+        thread.state = ProcessState::Zombie;
+        thread.exit_code = exit_code;
+        let tgid = thread.thread_group_id;
+        self.abort_ipc(tid);
+
+        for waiter in self.processes.iter_mut().filter(|p| p.thread_group_id == tgid) {
+            if waiter.state == ProcessState::Waiting(WaitReason::Thread(tid)) {
+                waiter.state = ProcessState::Ready;
+            }
+        }
+        Ok(())
+    }
+
+    /// 終了したスレッドを回収して終了コードを返す
+    pub fn reap_thread(&mut self, tgid: u64, tid: u64) -> KernelResult<i32> {
+        let pos = match self.processes.iter().position(|p| {
+            p.id == tid && p.thread_group_id == tgid && p.is_thread() && p.state == ProcessState::Zombie
+        }) {
+            Some(pos) => pos,
+            None => return kerror!(ProcessError::NotFound),
+        };
+        let thread = self.processes.remove(pos).ok_or(KernelError::Process(ProcessError::NotFound))?;
+        Ok(thread.exit_code)
+    }
+
+    /// スレッドグループの終了していないスレッドの数（メインスレッドを含む）
+    pub fn thread_count(&self, tgid: u64) -> usize {
+        self.processes.iter()
+            .filter(|p| p.thread_group_id == tgid && p.state != ProcessState::Zombie)
+            .count()
+    }
+
+    /// シグナルの動作をスレッドグループのすべてのスレッドに設定する
+    ///
+    /// 動作はプロセス全体で共有するものなので、各スレッドが持つ写しを揃えておく。
+    pub fn set_signal_action(&mut self, tgid: u64, sig: u64, action: signal::SigAction) -> Result<(), crate::error::Errno> {
+        for process in self.processes.iter_mut().filter(|p| p.thread_group_id == tgid) {
+            process.signals.set_action(sig, action)?;
+        }
+        Ok(())
+    }
+
+    /// execveのために、スレッドグループを呼び出したスレッドだけにする
+    ///
+    /// ほかのスレッドは終了させて取り除く。呼び出したのがメインスレッド以外なら
+    /// そのスレッドがリーダーのPIDを引き継ぐ：実行中のカーネルスタックとシグナルの
+    /// 状態をリーダーのエントリへ移し、スレッドのエントリを取り除く。
+    /// 戻り値は引き継いだ後のID（スレッドグループID）。
+    pub fn exec_single_thread(&mut self, tid: u64) -> KernelResult<u64> {
+        let tgid = match self.processes.iter().find(|p| p.id == tid) {
+            Some(process) => process.thread_group_id,
+            None => return kerror!(ProcessError::NotFound),
+        };
+        let others: alloc::vec::Vec<u64> = self.processes.iter()
+            .filter(|p| p.thread_group_id == tgid && p.id != tid && p.id != tgid)
+            .map(|p| p.id)
+            .collect();
+        for other in others {
+            self.abort_ipc(other);
+            crate::fpu::release(other);
+            self.processes.retain(|p| p.id != other);
+        }
+        if tid == tgid {
+            return Ok(tgid);
+        }
+
+        self.abort_ipc(tgid);
+        crate::fpu::release(tgid);
+        let pos = match self.processes.iter().position(|p| p.id == tid) {
+            Some(pos) => pos,
+            None => return kerror!(ProcessError::NotFound),
+        };
+        let mut thread = self.processes.remove(pos).ok_or(KernelError::Process(ProcessError::NotFound))?;
+        let leader = match self.processes.iter_mut().find(|p| p.id == tgid) {
+            Some(leader) => leader,
+            None => return kerror!(ProcessError::NotFound),
+        };
+        // 入れ替えたリーダーの古いカーネルスタックは、スレッドのエントリと一緒に解放される
+        core::mem::swap(&mut leader.kernel_stack, &mut thread.kernel_stack);
+        leader.context_ptr = thread.context_ptr;
+        leader.signals = thread.signals.clone();
+        leader.state = thread.state;
+        leader.syscall_restart = false;
+        leader.sleep_rem = None;
+        crate::fpu::release(tid);
+        Ok(tgid)
+    }
+
     /// Get all orphan processes
     pub fn get_orphans(&self) -> &alloc::vec::Vec<u64> {
         &self.orphans
@@ -250,14 +506,12 @@ impl Scheduler {
     pub fn cleanup_terminated_process(&mut self, pid: u64) -> KernelResult<()> {
         // Find the process in the scheduler
         let mut process_found = false;
-        let mut page_table_frame = None;
+        let mut is_zombie = false;
         
         for process in &self.processes {
             if process.id == pid {
                 process_found = true;
-                if process.state == ProcessState::Zombie {
-                    page_table_frame = Some(process.page_table_frame);
-                }
+                is_zombie = process.state == ProcessState::Zombie;
                 break;
             }
         }
@@ -267,13 +521,12 @@ impl Scheduler {
         }
 
         // Only clean up zombie processes
-        if let Some(frame) = page_table_frame {
-            // In a real implementation, this would:
-            // 1. Free all user-space memory pages
-            // 2. Free the page table itself
-            // 3. Close all file descriptors
-            // 4. Release IPC resources
-            // 5. Clean up any other kernel resources
+        if is_zombie {
+            // User-space pages and the page table are freed when the last
+            // reference to the process's AddressSpace is dropped. In a real implementation, this would also:
+            // 1. Close all file descriptors
+            // 2. Release IPC resources
+            // 3. Clean up any other kernel resources
             
             crate::println!("Cleanup: Freed resources for terminated process {}", pid);
             
@@ -353,75 +606,702 @@ impl Scheduler {
         true
     }
 
-    pub fn schedule(&mut self, current_context_ptr: u64) -> u64 {
-        // Try to get next process using priority scheduling first
-        if let Some(next_pid) = self.get_next_process_by_priority() {
-            // Find the process in our main processes list
-            if let Some(pos) = self.processes.iter().position(|p| p.id == next_pid) {
-                let mut next_process = self.processes.remove(pos).unwrap();
-                
-                // Update current process and move it to back of list
-                if let Some(prev_process) = self.processes.front_mut() {
-                    prev_process.context_ptr = current_context_ptr;
-                    
-                    // Update state if it was running
-                    if prev_process.state == ProcessState::Running {
-                        prev_process.state = ProcessState::Ready;
-                    }
-                }
-                
-                // Set next process as running
-                next_process.state = ProcessState::Running;
-                self.current_priority = next_process.priority;
-                
-                // Store context pointer before moving
-                let context_ptr = next_process.context_ptr;
-                let page_table_frame = next_process.page_table_frame;
-                let process_id = next_process.id;
-                
-                // Move next process to front
-                self.processes.push_front(next_process);
-                
-                unsafe {
-                    crate::syscall::CPU_DATA.current_process_id = process_id;
+    /// プロセスを`deadline`のティックまでスリープさせる
+    ///
+    /// 状態を`Waiting(Sleep)`にしてスリープキューに入れる。呼び出し元
+    /// （システムコール）から戻るときに別のプロセスに切り替わる。シグナルで
+    /// 中断された場合は、再開時に残り時間を`rem`（0なら書き込まない）に書き込む。
+    pub fn sleep_until(&mut self, pid: u64, deadline: u64, rem: u64) -> KernelResult<()> {
+        let process = match self.processes.iter_mut().find(|p| p.id == pid) {
+            Some(process) => process,
+            None => return kerror!(ProcessError::NotFound),
+        };
+        process.state = ProcessState::Waiting(WaitReason::Sleep(deadline));
+        process.sleep_rem = (rem != 0).then_some((rem, deadline));
+        self.sleep_queue.insert((deadline, pid));
+        Ok(())
+    }
+
+    /// 期限が`now`以前のスリープ中のプロセスを起こす（タイマー割り込みから呼ばれる）
+    pub fn wake_sleepers(&mut self, now: u64) {
+        while let Some(&(deadline, pid)) = self.sleep_queue.first() {
+            if deadline > now {
+                break;
+            }
+            self.sleep_queue.pop_first();
+            // 終了済み・別の理由で待っているプロセスはそのまま
+            let Some(process) = self.processes.iter_mut().find(|p| p.id == pid) else {
+                continue;
+            };
+            match process.state {
+                ProcessState::Waiting(WaitReason::Sleep(d)) if d == deadline => {
+                    process.state = ProcessState::Ready;
+                    process.sleep_rem = None;
                 }
-                
-                // CR3レジスタを新しいプロセスのページテーブルに切り替え
-                unsafe {
-                    x86_64::registers::control::Cr3::write(page_table_frame, x86_64::registers::control::Cr3Flags::empty());
+                // フューテックス待ちのタイムアウトはETIMEDOUTで戻る
+                ProcessState::Waiting(WaitReason::Futex(key, Some(d))) if d == deadline => {
+                    let context = unsafe { &mut *(process.context_ptr as *mut ProcessContext) };
+                    context.set_return_value(crate::error::Errno::ETIMEDOUT.as_syscall_return() as u64);
+                    process.state = ProcessState::Ready;
+                    self.remove_futex_waiter(key, pid);
                 }
-                
-                return context_ptr;
+                _ => {}
             }
         }
-        
-        // Fallback to simple round-robin if no priority processes are ready
-        // 1. 現在のタスクを後ろに回す（ただしプロセスが存在する場合のみ）
-        if let Some(mut prev) = self.processes.pop_front() {
-            prev.context_ptr = current_context_ptr;
-            self.processes.push_back(prev);
+    }
+
+    /// `tgid`のスレッドのうち`reason`で待っているものをすべて起こし、その数を返す
+    ///
+    /// IPCの送受信で相手側を起こすのに使う。起こされたスレッドはシステムコールを
+    /// やり直すので、条件がまだ満たされていなければもう一度待つ。
+    pub fn wake_waiters(&mut self, tgid: u64, reason: WaitReason) -> usize {
+        let mut woken = 0;
+        for waiter in self.processes.iter_mut().filter(|p| p.thread_group_id == tgid) {
+            if waiter.state == ProcessState::Waiting(reason) {
+                waiter.state = ProcessState::Ready;
+                woken += 1;
+            }
         }
+        woken
+    }
 
-        // 2. 次のタスクを新しく先頭から取る
-        if let Some(next) = self.processes.front() {
-            // CPU_DATAに現在のプロセスIDを設定
-            unsafe {
-                crate::syscall::CPU_DATA.current_process_id = next.id;
+    /// `reason`で待っているスレッドをプロセスを問わずすべて起こし、その数を返す
+    ///
+    /// 名前サービスへの登録で、サービスの登録を待っているスレッドを起こすのに使う。
+    pub fn wake_all_waiters(&mut self, reason: WaitReason) -> usize {
+        let mut woken = 0;
+        for waiter in self.processes.iter_mut() {
+            if waiter.state == ProcessState::Waiting(reason) {
+                waiter.state = ProcessState::Ready;
+                woken += 1;
             }
-            // CR3レジスタを新しいプロセスのページテーブルに切り替え
-            unsafe {
-                x86_64::registers::control::Cr3::write(next.page_table_frame, x86_64::registers::control::Cr3Flags::empty());
+        }
+        woken
+    }
+
+    /// プロセスをフューテックスのキー（物理アドレス）で待たせる
+    ///
+    /// `deadline`を指定した場合はスリープキューにも入れ、そのティックまでに
+    /// 起こされなければETIMEDOUTで戻る。起こされたときの戻り値は0。
+    pub fn futex_wait(&mut self, pid: u64, key: u64, deadline: Option<u64>) -> KernelResult<()> {
+        let process = match self.processes.iter_mut().find(|p| p.id == pid) {
+            Some(process) => process,
+            None => return kerror!(ProcessError::NotFound),
+        };
+        process.state = ProcessState::Waiting(WaitReason::Futex(key, deadline));
+        self.futex_queues.entry(key).or_default().push_back(pid);
+        if let Some(deadline) = deadline {
+            self.sleep_queue.insert((deadline, pid));
+        }
+        Ok(())
+    }
+
+    /// フューテックスのキーで待っているプロセスを、待ち始めた順に最大`count`個起こす
+    ///
+    /// 起こしたプロセスの数を返す。
+    pub fn futex_wake(&mut self, key: u64, count: usize) -> usize {
+        let Some(queue) = self.futex_queues.get_mut(&key) else {
+            return 0;
+        };
+        let mut woken = 0;
+        while woken < count {
+            let Some(pid) = queue.pop_front() else {
+                break;
+            };
+            if let Some(process) = self.processes.iter_mut().find(|p| p.id == pid)
+                && let ProcessState::Waiting(WaitReason::Futex(k, _)) = process.state
+                && k == key
+            {
+                process.state = ProcessState::Ready;
+                woken += 1;
             }
-            next.context_ptr
-        } else {
-            // プロセスがない場合はアイドル状態
-            crate::println!("No processes available - entering idle state");
-            unsafe {
-                crate::syscall::CPU_DATA.current_process_id = 0;
+        }
+        if queue.is_empty() {
+            self.futex_queues.remove(&key);
+        }
+        woken
+    }
+
+    // タイムアウトやシグナルで待ちをやめたプロセスをフューテックスの待ち行列から外す
+    fn remove_futex_waiter(&mut self, key: u64, pid: u64) {
+        if let Some(queue) = self.futex_queues.get_mut(&key) {
+            queue.retain(|&waiter| waiter != pid);
+            if queue.is_empty() {
+                self.futex_queues.remove(&key);
+            }
+        }
+    }
+
+    /// 同期IPCの呼び出し（ipc_call）
+    ///
+    /// `caller`のスレッドが`frame`のレジスタに載せたメッセージを、端点`endpoint`
+    /// （キー）を持っているプロセス`servers`に送る。その端点のipc_reply_waitで
+    /// 待っているスレッドがあれば、メッセージをそのレジスタに書き込んで呼び出し元の
+    /// 優先度を貸し、そのTIDを返す（呼び出し元はそこへ直接切り替える）。なければ
+    /// サーバーが受け取るまで待ち行列に入って`None`を返す。どちらの場合も
+    /// 呼び出し元は返信が届くまで`WaitReason::IpcCall`で待つ。
+    pub fn ipc_call(&mut self, caller: u64, endpoint: u64, servers: &[u64], frame: &ProcessContext) -> KernelResult<Option<u64>> {
+        if !self.processes.iter().any(|p| servers.contains(&p.thread_group_id) && p.state != ProcessState::Zombie) {
+            return kerror!(IpcError::ConnectionRefused);
+        }
+        let (caller_tgid, priority) = match self.processes.iter_mut().find(|p| p.id == caller) {
+            Some(process) => {
+                process.state = ProcessState::Waiting(WaitReason::IpcCall(endpoint));
+                (process.thread_group_id, process.effective_priority())
+            }
+            None => return kerror!(ProcessError::NotFound),
+        };
+
+        let serving = ProcessState::Waiting(WaitReason::IpcReplyWait(endpoint));
+        let Some(server) = self.processes.iter_mut()
+            .find(|p| servers.contains(&p.thread_group_id) && p.state == serving)
+        else {
+            self.ipc_callers.entry(endpoint).or_default().push_back(caller);
+            return Ok(None);
+        };
+        let context = unsafe { &mut *(server.context_ptr as *mut ProcessContext) };
+        CallMessage::from_context(frame).write_to(context);
+        context.set_return_value(caller_tgid);
+        server.state = ProcessState::Ready;
+        server.donated_priority = Some(priority);
+        let server_tid = server.id;
+        self.ipc_replies.insert(server_tid, caller);
+        Ok(Some(server_tid))
+    }
+
+    /// 同期IPCの返信と次の呼び出しの受け取り（ipc_reply_wait）
+    ///
+    /// `server`のスレッドが受け取った呼び出しにまだ返信していなければ、`frame`の
+    /// レジスタのメッセージを呼び出し元に書き込んで起こし、借りていた優先度を返す。
+    /// 続けて端点`endpoint`（キー）の待ち行列に入っている呼び出しを受け取り、
+    /// そのメッセージを`frame`に書き込む。呼び出しがなければ
+    /// `WaitReason::IpcReplyWait`で待つ（次のipc_callが直接メッセージを書き込んで起こす）。
+    pub fn ipc_reply_wait(&mut self, server: u64, endpoint: u64, frame: &mut ProcessContext) -> KernelResult<ReplyWait> {
+        match self.processes.iter_mut().find(|p| p.id == server) {
+            Some(process) => process.donated_priority = None,
+            None => return kerror!(ProcessError::NotFound),
+        }
+
+        // 1. 前の呼び出しに返信する（呼び出し元がシグナルで諦めていれば返信先はもうない）
+        let mut replied = None;
+        if let Some(caller) = self.ipc_replies.remove(&server)
+            && let Some(process) = self.processes.iter_mut().find(|p| p.id == caller)
+            && let ProcessState::Waiting(WaitReason::IpcCall(_)) = process.state
+        {
+            let context = unsafe { &mut *(process.context_ptr as *mut ProcessContext) };
+            CallMessage::from_context(frame).write_to(context);
+            context.set_return_value(0);
+            process.state = ProcessState::Ready;
+            replied = Some(caller);
+        }
+
+        // 2. 待ち行列の先頭の呼び出しを受け取る
+        if let Some(caller) = self.pop_ipc_caller(endpoint)
+            && let Some(process) = self.processes.iter().find(|p| p.id == caller)
+        {
+            let context = unsafe { &*(process.context_ptr as *const ProcessContext) };
+            CallMessage::from_context(context).write_to(frame);
+            let (caller_tgid, priority) = (process.thread_group_id, process.effective_priority());
+            self.ipc_replies.insert(server, caller);
+            if let Some(process) = self.processes.iter_mut().find(|p| p.id == server) {
+                process.donated_priority = Some(priority);
+            }
+            return Ok(ReplyWait::Received(caller_tgid));
+        }
+
+        // 3. 次の呼び出しを待つ
+        if let Some(process) = self.processes.iter_mut().find(|p| p.id == server) {
+            process.state = ProcessState::Waiting(WaitReason::IpcReplyWait(endpoint));
+        }
+        Ok(ReplyWait::Blocked(replied))
+    }
+
+    // サーバーがまだ受け取っていない呼び出しを、呼び出した順に取り出す
+    fn pop_ipc_caller(&mut self, endpoint: u64) -> Option<u64> {
+        let queue = self.ipc_callers.get_mut(&endpoint)?;
+        let caller = queue.pop_front();
+        if queue.is_empty() {
+            self.ipc_callers.remove(&endpoint);
+        }
+        caller
+    }
+
+    /// 閉じた端点（キー）をまだ受け取られていない呼び出しで待っているスレッドを、
+    /// ECONNREFUSEDで起こす
+    pub fn fail_ipc_callers(&mut self, endpoint: u64) {
+        for caller in self.ipc_callers.remove(&endpoint).unwrap_or_default() {
+            self.fail_ipc_caller(caller);
+        }
+    }
+
+    // シグナルや終了でipc_callをやめたスレッドを待ち行列と返信先から外し、
+    // 処理していたサーバーに貸していた優先度を取り戻す
+    fn cancel_ipc_call(&mut self, caller: u64) {
+        self.ipc_callers.retain(|_, queue| {
+            queue.retain(|&tid| tid != caller);
+            !queue.is_empty()
+        });
+        let servers: alloc::vec::Vec<u64> = self.ipc_replies.iter()
+            .filter(|&(_, &tid)| tid == caller)
+            .map(|(&server, _)| server)
+            .collect();
+        for server in servers {
+            self.ipc_replies.remove(&server);
+            if let Some(process) = self.processes.iter_mut().find(|p| p.id == server) {
+                process.donated_priority = None;
+            }
+        }
+    }
+
+    // 終了するスレッドの同期IPCを片付ける。返信を待たせていた呼び出し元は
+    // ECONNREFUSEDで起こす
+    fn abort_ipc(&mut self, tid: u64) {
+        self.cancel_ipc_call(tid);
+        if let Some(caller) = self.ipc_replies.remove(&tid) {
+            self.fail_ipc_caller(caller);
+        }
+    }
+
+    fn fail_ipc_caller(&mut self, caller: u64) {
+        if let Some(process) = self.processes.iter_mut().find(|p| p.id == caller)
+            && let ProcessState::Waiting(WaitReason::IpcCall(_)) = process.state
+        {
+            let context = unsafe { &mut *(process.context_ptr as *mut ProcessContext) };
+            context.set_return_value(crate::error::Errno::ECONNREFUSED.as_syscall_return() as u64);
+            process.state = ProcessState::Ready;
+        }
+    }
+
+    /// プロセスにシグナルを送る
+    ///
+    /// シグナルは保留状態になり、プロセスがユーザーモードに戻るときに配送される。
+    /// ブロックされていなければ、待ち状態のプロセスを起こして配送させる。
+    pub fn send_signal(&mut self, pid: u64, sig: u64) -> KernelResult<()> {
+        if !signal::valid_signal(sig) {
+            return kerror!(ProcessError::InvalidState);
+        }
+        let process = match self.processes.iter_mut().find(|p| p.id == pid) {
+            Some(process) => process,
+            None => return kerror!(ProcessError::NotFound),
+        };
+        if process.state == ProcessState::Zombie {
+            return Ok(());
+        }
+        let deliverable = process.signals.raise(sig);
+
+        // 停止中のプロセスはSIGCONTで再開し、SIGKILLでは終了するためだけに動く。
+        // それ以外のシグナルは再開するまで保留のまま
+        if process.state == ProcessState::Stopped {
+            if sig == signal::SIGCONT {
+                process.state = ProcessState::Ready;
+                process.job_status = Some(JobStatus::Continued);
+                let parent_pid = process.parent_id;
+                self.notify_parent(pid, parent_pid, true);
+            } else if sig == signal::SIGKILL {
+                process.state = ProcessState::Ready;
+            }
+            return Ok(());
+        }
+        if !deliverable {
+            return Ok(());
+        }
+
+        if let ProcessState::Waiting(reason) = process.state {
+            // 時間が残っていてもスリープ・フューテックス・同期IPCの待ちは中断され、
+            // EINTRで戻る。それ以外はシステムコールのやり直しに備えてRIPが戻してあり、
+            // ハンドラを実行するときにSA_RESTARTがなければEINTRに変わる（deliver_signals）
+            if let WaitReason::Sleep(_) | WaitReason::Futex(..) | WaitReason::IpcCall(_) | WaitReason::IpcReplyWait(_) = reason {
+                let context = unsafe { &mut *(process.context_ptr as *mut ProcessContext) };
+                context.set_return_value(crate::error::Errno::EINTR.as_syscall_return() as u64);
+            }
+            process.state = ProcessState::Ready;
+            if let WaitReason::Futex(key, _) = reason {
+                self.remove_futex_waiter(key, pid);
+            }
+            if let WaitReason::IpcCall(_) = reason {
+                self.cancel_ipc_call(pid);
+            }
+        }
+        Ok(())
+    }
+
+    /// 実行中のプロセスにタイマー割り込み1回分のCPU時間を課金する
+    ///
+    /// CPU時間の制限を超えていれば、ソフトリミットではSIGXCPU、
+    /// ハードリミットではSIGKILLを送る。
+    pub fn charge_current_tick(&mut self, user_mode: bool) {
+        let tgid = match self.current_process_mut() {
+            Some(process) if process.state == ProcessState::Running => process.thread_group_id,
+            _ => return,
+        };
+        self.charge_leader(tgid, |leader| leader.charge_tick(user_mode));
+    }
+
+    /// 現在のスレッドがシステムコールで使ったCPU時間（ナノ秒）を課金する
+    ///
+    /// システムコール中は割り込みを止めているので、タイマー割り込みでは
+    /// システム時間が課金されない。代わりに入口から出口までの時間を課金する。
+    /// ブロックしたスレッドにも課金する（終了したものは除く）。
+    pub fn charge_current_syscall(&mut self, nanos: u64) {
+        let tgid = match self.current_process_mut() {
+            Some(process) if process.state != ProcessState::Zombie => process.thread_group_id,
+            _ => return,
+        };
+        self.charge_leader(tgid, |leader| leader.charge_system_time(nanos));
+    }
+
+    // CPU時間はスレッドグループで共有し、リーダーに課金する。制限を超えていればシグナルを送る
+    fn charge_leader(&mut self, tgid: u64, charge: impl FnOnce(&mut Process) -> Option<u64>) {
+        let Some(leader) = self.processes.iter_mut().find(|p| p.id == tgid) else {
+            return;
+        };
+        if let Some(sig) = charge(leader)
+            && let Err(e) = self.send_signal(tgid, sig)
+        {
+            crate::error::log_error(&e);
+        }
+    }
+
+    /// プロセスグループのすべてのプロセスにシグナルを送る
+    ///
+    /// 送った数を返す。グループに（終了していない）プロセスがなければエラー。
+    pub fn signal_group(&mut self, pgid: u64, sig: u64) -> KernelResult<usize> {
+        let members: alloc::vec::Vec<u64> = self.processes.iter()
+            .filter(|p| p.process_group_id == pgid && !p.is_thread() && p.state != ProcessState::Zombie)
+            .map(|p| p.id)
+            .collect();
+        if members.is_empty() {
+            return kerror!(ProcessError::NotFound);
+        }
+        for &pid in &members {
+            self.send_signal(pid, sig)?;
+        }
+        Ok(members.len())
+    }
+
+    /// プロセスグループに属する（終了していない）プロセスがあるか
+    pub fn group_exists(&self, pgid: u64, session_id: u64) -> bool {
+        self.processes.iter().any(|p| {
+            p.process_group_id == pgid && p.session_id == session_id && !p.is_thread() && p.state != ProcessState::Zombie
+        })
+    }
+
+    /// 保留中のシグナルを現在のプロセスに配送し、復帰先のコンテキストを返す
+    ///
+    /// ユーザーモードに戻る直前に呼ぶ。ハンドラがあればシグナルフレームを積んで
+    /// `current_context_ptr`をハンドラから始まるよう書き換える。既定の動作で
+    /// 終了する場合は別のプロセスに切り替え、そのプロセスにも配送を続ける。
+    pub fn deliver_signals(&mut self, current_context_ptr: u64) -> u64 {
+        let mut context_ptr = current_context_ptr;
+        loop {
+            let context = unsafe { &mut *(context_ptr as *mut ProcessContext) };
+            if !context.is_user_mode() {
+                return context_ptr;
+            }
+            let process = match self.current_process_mut() {
+                Some(process) => process,
+                None => return context_ptr,
+            };
+            // シグナルで中断されたnanosleepの残り時間を書き戻す（このプロセスの
+            // アドレス空間に切り替わってから書き込む必要がある）
+            if let Some((rem, deadline)) = process.sleep_rem.take() {
+                let remaining = deadline.saturating_sub(crate::timer::get_global_tick()) * crate::timer::NANOS_PER_TICK;
+                let _ = crate::memory::uaccess::put_user(rem, &crate::timer::Timespec::from_nanos(remaining));
+            }
+            let sig = match process.signals.take_deliverable() {
+                Some(sig) => sig,
+                None => return context_ptr,
+            };
+            let action = process.signals.action(sig);
+            let pid = process.id;
+            let tgid = process.thread_group_id;
+
+            let terminate = match action.handler {
+                SIG_IGN => false,
+                SIG_DFL => match signal::default_action(sig) {
+                    DefaultAction::Terminate => true,
+                    DefaultAction::Ignore | DefaultAction::Continue => false,
+                    DefaultAction::Stop => {
+                        // 停止してSIGCONTを待つ。ここで別のプロセスに切り替わる
+                        crate::println!("Signal: PID {} stopped by signal {}", pid, sig);
+                        process.state = ProcessState::Stopped;
+                        process.job_status = Some(JobStatus::Stopped(sig));
+                        let parent_pid = process.parent_id;
+                        self.notify_parent(pid, parent_pid, true);
+                        context_ptr = self.schedule(context_ptr);
+                        continue;
+                    }
+                },
+                _ => {
+                    // ブロックして中断されたシステムコールはSA_RESTARTがなければEINTRで戻る
+                    if core::mem::take(&mut process.syscall_restart) {
+                        signal::interrupt_syscall(context, &action);
+                    }
+                    let blocked = process.signals.blocked;
+                    match signal::setup_frame(context, sig, &action, blocked) {
+                        Ok(()) => {
+                            let mut mask = blocked | action.mask;
+                            if action.flags & SA_NODEFER == 0 {
+                                mask |= signal::sig_bit(sig);
+                            }
+                            process.signals.set_blocked(mask);
+                            if action.flags & SA_RESETHAND != 0 {
+                                let _ = self.set_signal_action(tgid, sig, signal::SigAction::default());
+                            }
+                            return context_ptr;
+                        }
+                        // フレームを積めない（スタックが壊れている）ならSIGSEGVで終了させる
+                        Err(_) if sig == signal::SIGSEGV => true,
+                        Err(_) => {
+                            process.signals.force(signal::SIGSEGV);
+                            false
+                        }
+                    }
+                }
+            };
+
+            if terminate {
+                crate::println!("Signal: PID {} terminated by signal {}", pid, sig);
+                if let Err(e) = self.handle_process_exit(pid, signal::exit_code_for(sig)) {
+                    crate::error::log_error(&e);
+                }
+                context_ptr = self.schedule(context_ptr);
+            }
+        }
+    }
+
+    /// Find the process that is currently executing on this CPU
+    pub fn current_process_mut(&mut self) -> Option<&mut Process> {
+        let current_pid = crate::syscall::get_current_process_id();
+        self.processes.iter_mut().find(|p| p.id == current_pid)
+    }
+
+    /// 現在のプロセスのリーダー（スレッドグループで共有する情報を持つ）
+    ///
+    /// 親子関係・プロセスグループ・リソース制限と使用量はリーダーが持つ。
+    /// シグナルマスクや状態など、スレッドごとの情報は`current_process_mut`で扱う。
+    pub fn current_leader_mut(&mut self) -> Option<&mut Process> {
+        let tgid = crate::syscall::get_current_thread_group_id();
+        self.processes.iter_mut().find(|p| p.id == tgid)
+    }
+
+    // 現在のプロセスのコンテキストを保存し、キューの後ろに回す
+    fn save_current(&mut self, current_context_ptr: u64) {
+        let current_pid = crate::syscall::get_current_process_id();
+        if let Some(pos) = self.processes.iter().position(|p| p.id == current_pid)
+            && let Some(mut prev) = self.processes.remove(pos)
+        {
+            prev.context_ptr = current_context_ptr;
+            prev.fs_base = FsBase::read().as_u64();
+            // Update state if it was running
+            if prev.state == ProcessState::Running {
+                prev.state = ProcessState::Ready;
             }
-            // 現在のコンテキストを返す（アイドルループ）
-            current_context_ptr
+            self.processes.push_back(prev);
+        }
+    }
+
+    pub fn schedule(&mut self, current_context_ptr: u64) -> u64 {
+        let current_pid = crate::syscall::get_current_process_id();
+
+        // 1. 現在のプロセスのコンテキストを保存し、キューの後ろに回す
+        self.save_current(current_context_ptr);
+
+        // 2. 優先度（とエージング）に従って次のプロセスを選び、先頭に移動する
+        if let Some(next_pid) = self.pick_next()
+            && let Some(pos) = self.processes.iter().position(|p| p.id == next_pid)
+            && let Some(mut next_process) = self.processes.remove(pos)
+        {
+            // Set next process as running
+            next_process.state = ProcessState::Running;
+            self.current_priority = next_process.effective_priority();
+            let context_ptr = next_process.context_ptr;
+            switch_to(&next_process);
+
+            // Move next process to front
+            self.processes.push_front(next_process);
+            return context_ptr;
+        }
+
+        // 3. 実行可能なプロセスがない
+        if current_pid == 0 {
+            // カーネル（アイドルループなど）をそのまま続ける
+            return current_context_ptr;
         }
+        // 現在のプロセスはブロックまたは終了したので、アイドルループに切り替える
+        crate::syscall::set_current_process_id(0);
+        crate::syscall::set_current_thread_group_id(0);
+        unsafe {
+            x86_64::registers::control::Cr3::write(crate::memory::kernel_page_table(), x86_64::registers::control::Cr3Flags::empty());
+        }
+        crate::memory::vma::set_current(None);
+        idle_context()
+    }
+
+    /// システムコールから戻るときに`tid`のスレッドへ直接切り替えるよう予約する
+    pub fn switch_on_return(&mut self, tid: u64) {
+        self.direct_switch = Some(tid);
     }
+
+    /// `tid`のスレッドに直接切り替え、そのコンテキストを返す
+    ///
+    /// `schedule`と違って優先度で次を選ばないので、現在のスレッドに残っていた
+    /// タイムスライス（次のタイマー割り込みまで）はそのまま`tid`が使う。
+    /// `tid`が実行できる状態でなければ`schedule`で選び直す。
+    pub fn switch_directly(&mut self, current_context_ptr: u64, tid: u64) -> u64 {
+        let next = self.processes.iter()
+            .position(|p| p.id == tid && matches!(p.state, ProcessState::Ready | ProcessState::Running))
+            .and_then(|pos| self.processes.remove(pos));
+        let Some(mut next_process) = next else {
+            return self.schedule(current_context_ptr);
+        };
+        self.save_current(current_context_ptr);
+
+        next_process.state = ProcessState::Running;
+        self.current_priority = next_process.effective_priority();
+        let context_ptr = next_process.context_ptr;
+        switch_to(&next_process);
+        self.processes.push_front(next_process);
+        context_ptr
+    }
+}
+
+/// システムコールからの復帰時に呼ばれる
+///
+/// ハンドラが現在のプロセスをブロック・終了させた場合（wait4、exitなど）や
+/// CPUを譲った場合（sched_yield）は次のプロセスに切り替え、そのコンテキストを
+/// 返す。ハンドラが`switch_on_return`で切り替え先を予約した場合（同期IPC）は
+/// 優先度で選ばずにそのスレッドへ切り替える。それ以外は`current_context_ptr`を
+/// そのまま返す。どの場合も保留中のシグナルがあれば配送する。
+pub fn reschedule_after_syscall(current_context_ptr: u64) -> u64 {
+    let mut sched = SCHEDULER.lock();
+    let still_running = sched
+        .current_process_mut()
+        .map(|p| p.state == ProcessState::Running)
+        .unwrap_or(false);
+    let context_ptr = if let Some(tid) = sched.direct_switch.take() {
+        sched.switch_directly(current_context_ptr, tid)
+    } else if still_running {
+        current_context_ptr
+    } else {
+        sched.schedule(current_context_ptr)
+    };
+    let context_ptr = sched.deliver_signals(context_ptr);
+    drop(sched);
+    release_exited();
+    context_ptr
+}
+
+/// 例外を起こした現在のプロセスにシグナルを送り、復帰先のコンテキストを返す
+///
+/// 例外ハンドラから呼ばれる（不正なメモリアクセスはSIGSEGV）。ハンドラが
+/// なければプロセスは終了し、次のプロセスに切り替わる。
+pub fn force_signal_current(current_context_ptr: u64, sig: u64) -> u64 {
+    let mut sched = SCHEDULER.lock();
+    if let Some(process) = sched.current_process_mut() {
+        process.signals.force(sig);
+    }
+    let context_ptr = sched.deliver_signals(current_context_ptr);
+    drop(sched);
+    release_exited();
+    context_ptr
+}
+
+/// 終了したプロセスのチャンネルのケイパビリティとメモリハンドルを解放する
+///
+/// CHANNEL_REGISTRY・HANDLE_REGISTRYはSCHEDULERより先にロックする決まりなので、
+/// 終了（`handle_process_exit`）の中では解放できない。SCHEDULERのロックを
+/// 外した直後に呼び、相手側で待っているスレッドをすぐに起こす。ハンドルは
+/// フレームの参照を手放し、PIDを再利用したプロセスが受け取れないようにする。
+pub fn release_exited() {
+    let exited = SCHEDULER.lock().take_exited();
+    for pid in exited {
+        crate::ipc::syscalls::release_channels(pid);
+        crate::ipc::HANDLE_REGISTRY.lock().cleanup_process_handles(pid);
+    }
+}
+
+// CPUの状態を次のプロセス用に切り替える
+fn switch_to(process: &Process) {
+    // CPU_DATAに現在のプロセスIDを設定
+    crate::syscall::set_current_process_id(process.id);
+    crate::syscall::set_current_thread_group_id(process.thread_group_id);
+    // 割り込み・システムコールでこのプロセスのカーネルスタックを使う
+    crate::gdt::set_kernel_stack(x86_64::VirtAddr::new(process.kernel_stack.top()));
+    // スレッドのTLS。GSはカーネルがCPU_DATAに使うので切り替えない
+    FsBase::write(x86_64::VirtAddr::new(process.fs_base));
+    // FPU/SSE/AVXレジスタは最初に使ったときに入れ替える
+    crate::fpu::switch_to(process.id);
+    // CR3レジスタを新しいプロセスのページテーブルに切り替え
+    unsafe {
+        x86_64::registers::control::Cr3::write(process.address_space.page_table_frame(), x86_64::registers::control::Cr3Flags::empty());
+    }
+    crate::memory::vma::set_current(Some(process.address_space.clone()));
 }
 
+const IDLE_STACK_SIZE: usize = 4096;
+
+#[repr(align(16))]
+struct IdleStack([u8; IDLE_STACK_SIZE]);
+
+static mut IDLE_STACK: IdleStack = IdleStack([0; IDLE_STACK_SIZE]);
+
+// 実行可能なプロセスがないときに動くカーネルスレッド
+extern "C" fn idle_loop() -> ! {
+    crate::println!("No processes available - entering idle state");
+    loop {
+        x86_64::instructions::interrupts::enable_and_hlt();
+    }
+}
+
+// アイドルループを最初から実行するコンテキストを作る
+fn idle_context() -> u64 {
+    let stack_top = unsafe { (&raw mut IDLE_STACK.0) as u64 } + IDLE_STACK_SIZE as u64;
+    let context_ptr = (stack_top - core::mem::size_of::<super::ProcessContext>() as u64) as *mut super::ProcessContext;
+    unsafe {
+        *context_ptr = super::ProcessContext::new_kernel(idle_loop as *const () as u64, stack_top);
+    }
+    context_ptr as u64
+}
+
+/// 最初のプロセスに切り替えて実行を始める（戻らない）
+///
+/// カーネルの初期化が終わったら呼ぶ。以降はタイマー割り込みとシステムコールの
+/// 復帰時にプロセスが切り替わる。
+pub fn start() -> ! {
+    let context_ptr = {
+        let mut sched = SCHEDULER.lock();
+        match sched.schedule(0) {
+            0 => idle_context(),
+            context_ptr => context_ptr,
+        }
+    };
+    unsafe { restore_context(context_ptr) }
+}
+
+// 保存されたコンテキストを復元してIRETQで実行を移す
+unsafe fn restore_context(context_ptr: u64) -> ! {
+    unsafe {
+        core::arch::asm!(
+            "mov rsp, {0}",
+            "pop r15",
+            "pop r14",
+            "pop r13",
+            "pop r12",
+            "pop rbp",
+            "pop rbx",
+            "pop r11",
+            "pop r10",
+            "pop r9",
+            "pop r8",
+            "pop rdi",
+            "pop rsi",
+            "pop rdx",
+            "pop rcx",
+            "pop rax",
+            "iretq",
+            in(reg) context_ptr,
+            options(noreturn)
+        );
+    }
+}
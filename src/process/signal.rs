@@ -0,0 +1,358 @@
+//! POSIX風のシグナル
+//!
+//! プロセスごとに保留中（pending）とブロック中（blocked）のシグナル集合、
+//! シグナルごとの動作（sigaction）を持つ。シグナルはユーザーモードへ戻る
+//! 直前（システムコール・割り込みからの復帰時）に配送され、ハンドラが
+//! 登録されていればユーザースタックにシグナルフレームを積んで呼び出す。
+//! ハンドラから戻るときは`sa_restorer`がrt_sigreturnを呼び、フレームに
+//! 保存したレジスタとシグナルマスクを復元する。
+//!
+//! 停止シグナル（SIGSTOP / SIGTSTP / SIGTTIN / SIGTTOU）はプロセスを
+//! `ProcessState::Stopped`にし、SIGCONTは送られた時点で再開させる。
+//! 停止・再開は親にSIGCHLDで通知され、wait4で取得できる。
+
+use crate::error::Errno;
+use crate::memory::uaccess;
+use super::ProcessContext;
+
+// シグナル番号（Linux x86_64と同じ）
+pub const SIGHUP: u64 = 1;
+pub const SIGINT: u64 = 2;
+pub const SIGQUIT: u64 = 3;
+pub const SIGILL: u64 = 4;
+pub const SIGTRAP: u64 = 5;
+pub const SIGABRT: u64 = 6;
+pub const SIGBUS: u64 = 7;
+pub const SIGFPE: u64 = 8;
+pub const SIGKILL: u64 = 9;
+pub const SIGUSR1: u64 = 10;
+pub const SIGSEGV: u64 = 11;
+pub const SIGUSR2: u64 = 12;
+pub const SIGPIPE: u64 = 13;
+pub const SIGALRM: u64 = 14;
+pub const SIGTERM: u64 = 15;
+pub const SIGCHLD: u64 = 17;
+pub const SIGCONT: u64 = 18;
+pub const SIGSTOP: u64 = 19;
+pub const SIGTSTP: u64 = 20;
+pub const SIGTTIN: u64 = 21;
+pub const SIGTTOU: u64 = 22;
+pub const SIGURG: u64 = 23;
+pub const SIGXCPU: u64 = 24;
+pub const SIGWINCH: u64 = 28;
+
+/// シグナルの数（1..=NSIG が有効な番号）
+pub const NSIG: u64 = 64;
+
+// sa_handler の特別な値
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+// sa_flags
+pub const SA_NOCLDSTOP: u64 = 0x0000_0001;
+pub const SA_SIGINFO: u64 = 0x0000_0004;
+pub const SA_RESTORER: u64 = 0x0400_0000;
+pub const SA_RESTART: u64 = 0x1000_0000;
+pub const SA_NODEFER: u64 = 0x4000_0000;
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+// rt_sigprocmask の how
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+/// シグナル集合（ビットN-1がシグナルN）
+pub type SigSet = u64;
+
+/// シグナル番号に対応するビット
+pub const fn sig_bit(sig: u64) -> SigSet {
+    1 << (sig - 1)
+}
+
+/// ブロックも動作の変更もできないシグナル
+pub const UNBLOCKABLE: SigSet = sig_bit(SIGKILL) | sig_bit(SIGSTOP);
+
+/// プロセスを停止させるシグナル
+pub const STOP_SIGNALS: SigSet = sig_bit(SIGSTOP) | sig_bit(SIGTSTP) | sig_bit(SIGTTIN) | sig_bit(SIGTTOU);
+
+/// 有効なシグナル番号か（0は存在確認用で、ここでは無効とする）
+pub fn valid_signal(sig: u64) -> bool {
+    (1..=NSIG).contains(&sig)
+}
+
+/// ハンドラが登録されていないときの動作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    /// 再開は送られた時点で済んでいるので、配送時には何もしない
+    Continue,
+}
+
+/// シグナルの既定の動作
+pub fn default_action(sig: u64) -> DefaultAction {
+    match sig {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// 親にまだ報告していないジョブ制御による状態の変化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    /// 指定したシグナルで停止した
+    Stopped(u64),
+    /// SIGCONTで再開した
+    Continued,
+}
+
+impl JobStatus {
+    /// wait4 が返すステータス（Linuxの WIFSTOPPED / WIFCONTINUED と同じ形）
+    pub fn wait_status(&self) -> i32 {
+        match *self {
+            JobStatus::Stopped(sig) => ((sig as i32) << 8) | 0x7f,
+            JobStatus::Continued => 0xffff,
+        }
+    }
+}
+
+/// シグナルで終了したプロセスの終了コード
+pub fn exit_code_for(sig: u64) -> i32 {
+    -(sig as i32)
+}
+
+/// 終了したプロセスについて wait4 が返すステータス（Linuxの WIFEXITED / WIFSIGNALED と同じ形）
+///
+/// `exit_code`が負ならシグナルで終了した（`exit_code_for`）。そうでなければ
+/// exitに渡した値の下位8ビットが WEXITSTATUS になる。
+pub fn exit_status(exit_code: i32) -> i32 {
+    if exit_code < 0 {
+        -exit_code & 0x7f
+    } else {
+        (exit_code & 0xff) << 8
+    }
+}
+
+/// rt_sigaction でユーザー空間とやり取りする構造体（カーネルのstruct sigaction）
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SigAction {
+    pub handler: u64,
+    pub flags: u64,
+    pub restorer: u64,
+    pub mask: SigSet,
+}
+
+impl SigAction {
+    /// このシグナルを配送したときに捨てるか
+    fn ignores(&self, sig: u64) -> bool {
+        match self.handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(default_action(sig), DefaultAction::Ignore | DefaultAction::Continue),
+            _ => false,
+        }
+    }
+}
+
+/// プロセスごとのシグナルの状態
+#[derive(Debug, Clone)]
+pub struct SignalState {
+    pub pending: SigSet,
+    pub blocked: SigSet,
+    actions: [SigAction; NSIG as usize],
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        Self {
+            pending: 0,
+            blocked: 0,
+            actions: [SigAction::default(); NSIG as usize],
+        }
+    }
+
+    /// fork した子の状態（動作とマスクは引き継ぎ、保留中のシグナルは引き継がない）
+    pub fn fork(&self) -> Self {
+        Self { pending: 0, ..self.clone() }
+    }
+
+    /// execve 後の状態（ハンドラはもう存在しないので既定の動作に戻す。無視はそのまま）
+    pub fn reset_for_exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+
+    pub fn action(&self, sig: u64) -> SigAction {
+        self.actions[(sig - 1) as usize]
+    }
+
+    /// シグナルの動作を変更する（SIGKILL / SIGSTOP は変更できない）
+    pub fn set_action(&mut self, sig: u64, action: SigAction) -> Result<(), Errno> {
+        if !valid_signal(sig) || sig_bit(sig) & UNBLOCKABLE != 0 {
+            return Err(Errno::EINVAL);
+        }
+        self.actions[(sig - 1) as usize] = SigAction {
+            mask: action.mask & !UNBLOCKABLE,
+            ..action
+        };
+        // 無視に変えたシグナルは保留中のものも捨てる
+        if self.actions[(sig - 1) as usize].ignores(sig) {
+            self.pending &= !sig_bit(sig);
+        }
+        Ok(())
+    }
+
+    /// ブロックするシグナルを設定する（SIGKILL / SIGSTOP は常に外す）
+    pub fn set_blocked(&mut self, mask: SigSet) {
+        self.blocked = mask & !UNBLOCKABLE;
+    }
+
+    /// シグナルを保留状態にする
+    ///
+    /// 無視されるシグナルはブロックされていなければその場で捨てる。
+    /// 配送を待つことになったら`true`を返す。
+    pub fn raise(&mut self, sig: u64) -> bool {
+        let bit = sig_bit(sig);
+        // 停止と再開は打ち消し合う
+        if sig == SIGCONT {
+            self.pending &= !STOP_SIGNALS;
+        } else if bit & STOP_SIGNALS != 0 {
+            self.pending &= !sig_bit(SIGCONT);
+        }
+        if self.blocked & bit == 0 && self.action(sig).ignores(sig) {
+            return false;
+        }
+        self.pending |= bit;
+        self.blocked & bit == 0
+    }
+
+    /// 例外で発生したシグナルを必ず配送させる
+    ///
+    /// ブロック・無視されていても、例外を起こした命令をそのまま再実行すると
+    /// 無限に例外が続くので、ブロックを外して既定の動作に戻す。
+    pub fn force(&mut self, sig: u64) {
+        let bit = sig_bit(sig);
+        if self.blocked & bit != 0 || self.action(sig).handler == SIG_IGN {
+            self.blocked &= !bit;
+            self.actions[(sig - 1) as usize] = SigAction::default();
+        }
+        self.pending |= bit;
+    }
+
+    /// 配送できる（ブロックされていない）保留中のシグナルがあるか
+    pub fn has_deliverable(&self) -> bool {
+        self.pending & !self.blocked != 0
+    }
+
+    /// 次に配送するシグナルを取り出す（番号の小さいものから）
+    pub fn take_deliverable(&mut self) -> Option<u64> {
+        let deliverable = self.pending & !self.blocked;
+        if deliverable == 0 {
+            return None;
+        }
+        let sig = deliverable.trailing_zeros() as u64 + 1;
+        self.pending &= !sig_bit(sig);
+        Some(sig)
+    }
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// シグナルハンドラ呼び出し時にユーザースタックに積むフレーム
+///
+/// ハンドラに入った時点のRSPはこのフレームの先頭（戻り先の`sa_restorer`）を指す。
+/// ハンドラが`ret`するとrestorerがrt_sigreturnを呼び、そのときのRSPは
+/// `restorer`の直後になる。
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalFrame {
+    pub restorer: u64,
+    pub signo: u64,
+    /// ハンドラ呼び出し前のシグナルマスク
+    pub blocked: SigSet,
+    /// 割り込まれた時点のレジスタ
+    pub context: ProcessContext,
+}
+
+// x86_64 System V ABIのレッドゾーン（関数がRSPより下に使ってよい領域）
+const RED_ZONE: u64 = 128;
+
+// rt_sigreturn でユーザーが変更してよいRFLAGSのビット（CF PF AF ZF SF TF DF OF AC）
+const USER_RFLAGS_MASK: u64 = 0x4_0DD5;
+const RFLAGS_IF: u64 = 0x200;
+const RFLAGS_TF: u64 = 0x100;
+const RFLAGS_DF: u64 = 0x400;
+
+// ユーザー空間のアドレスの上限（カノニカルアドレスの下半分）
+const USER_ADDR_END: u64 = 0x0000_8000_0000_0000;
+
+/// やり直す予定のシステムコールを中断してハンドラを実行するときの後始末
+///
+/// SA_RESTARTがあればハンドラから戻ったあとにやり直し、なければEINTRで戻す。
+/// `context`は`restart_syscall`でRIPを戻した状態であること。
+pub fn interrupt_syscall(context: &mut ProcessContext, action: &SigAction) {
+    if action.flags & SA_RESTART == 0 {
+        context.cancel_restart(Errno::EINTR.as_syscall_return() as u64);
+    }
+}
+
+/// シグナルフレームを現在のアドレス空間のユーザースタックに積み、
+/// `context` をハンドラの先頭から実行する状態にする
+pub fn setup_frame(context: &mut ProcessContext, sig: u64, action: &SigAction, blocked: SigSet) -> Result<(), Errno> {
+    let size = core::mem::size_of::<SignalFrame>() as u64;
+    // ハンドラの入口では (RSP + 8) が16バイト境界になるようにする
+    let frame_addr = context.rsp
+        .checked_sub(RED_ZONE + size)
+        .ok_or(Errno::EFAULT)?
+        & !0xF;
+    let frame_addr = frame_addr.checked_sub(8).ok_or(Errno::EFAULT)?;
+
+    let frame = SignalFrame {
+        restorer: if action.flags & SA_RESTORER != 0 { action.restorer } else { 0 },
+        signo: sig,
+        blocked,
+        context: *context,
+    };
+    uaccess::put_user(frame_addr, &frame)?;
+
+    // void handler(int signo, siginfo_t *info, void *ucontext)
+    // siginfo と ucontext はまだないのでNULLを渡す
+    context.rip = action.handler;
+    context.rsp = frame_addr;
+    context.rdi = sig;
+    context.rsi = 0;
+    context.rdx = 0;
+    context.rax = 0;
+    context.rflags &= !(RFLAGS_TF | RFLAGS_DF);
+    Ok(())
+}
+
+/// rt_sigreturn: ユーザースタックのシグナルフレームを読み出す
+///
+/// `user_rsp` はrestorerがシステムコールを呼んだ時点のRSP。保存されていた
+/// レジスタは、ユーザーが書き換えていてもカーネルに戻れないよう
+/// セグメントとRFLAGSを補正して返す。
+pub fn restore_frame(user_rsp: u64) -> Result<SignalFrame, Errno> {
+    let frame_addr = user_rsp.checked_sub(8).ok_or(Errno::EFAULT)?;
+    let mut frame: SignalFrame = uaccess::get_user(frame_addr)?;
+
+    let saved = ProcessContext::new_user(frame.context.rip, frame.context.rsp);
+    if saved.rip >= USER_ADDR_END || saved.rsp >= USER_ADDR_END {
+        return Err(Errno::EFAULT);
+    }
+    frame.context.cs = saved.cs;
+    frame.context.ss = saved.ss;
+    frame.context.rflags = (frame.context.rflags & USER_RFLAGS_MASK) | RFLAGS_IF | 0x2;
+    frame.blocked &= !UNBLOCKABLE;
+    Ok(frame)
+}
@@ -0,0 +1,790 @@
+//! プロセス管理系システムコール
+//!
+//! exit / sched_yield / getpid / fork / execve / wait4、スレッド関連
+//! （clone / gettid / thread_exit / thread_join / arch_prctl）、シグナル関連
+//! （kill / rt_sigaction / rt_sigprocmask / rt_sigreturn）、ジョブ制御用の
+//! プロセスグループ・セッション関連（getppid / setpgid / getpgid / setsid / getsid）、
+//! 優先度・リソース制限関連（getpriority / setpriority / getrlimit / setrlimit /
+//! prlimit64 / getrusage）のハンドラと、その登録処理。
+
+use alloc::vec::Vec;
+use x86_64::registers::model_specific::FsBase;
+
+use crate::error::{Errno, KernelResult};
+use crate::syscall::table::{ArgKind, SyscallEntry, SyscallResult, SyscallTable};
+use crate::memory::uaccess;
+use crate::syscall::{self, SyscallArgs};
+use crate::timer::Timeval;
+use super::resource::{self, Rlimit, Rusage};
+use super::scheduler::{Scheduler, WaitTarget, SCHEDULER};
+use super::signal::{self, SigAction, SigSet};
+use super::{exec, Process, ProcessContext, ProcessState, WaitReason};
+
+// wait4 の options
+pub const WNOHANG: u64 = 0x1;
+pub const WUNTRACED: u64 = 0x2;
+pub const WCONTINUED: u64 = 0x8;
+
+// clone の flags（Linux x86_64と同じ値）
+pub const CLONE_VM: u64 = 0x100;
+pub const CLONE_FS: u64 = 0x200;
+pub const CLONE_FILES: u64 = 0x400;
+pub const CLONE_SIGHAND: u64 = 0x800;
+pub const CLONE_THREAD: u64 = 0x10000;
+pub const CLONE_SYSVSEM: u64 = 0x40000;
+pub const CLONE_SETTLS: u64 = 0x80000;
+/// スレッドの作成に必要なフラグ（cloneはスレッドの作成のみサポートする）
+pub const CLONE_THREAD_FLAGS: u64 = CLONE_VM | CLONE_SIGHAND | CLONE_THREAD;
+
+// arch_prctl の code
+pub const ARCH_SET_GS: u64 = 0x1001;
+pub const ARCH_SET_FS: u64 = 0x1002;
+pub const ARCH_GET_FS: u64 = 0x1003;
+pub const ARCH_GET_GS: u64 = 0x1004;
+
+/// プロセス管理系システムコールを登録
+pub fn register_syscalls(table: &mut SyscallTable) -> KernelResult<()> {
+    table.register(SyscallEntry::new(syscall::SYS_EXIT, "exit", sys_exit, &[ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_SCHED_YIELD, "sched_yield", sys_sched_yield, &[]))?;
+    table.register(SyscallEntry::new(syscall::SYS_GETPID, "getpid", sys_getpid, &[]))?;
+    table.register(SyscallEntry::new(syscall::SYS_FORK, "fork", sys_fork, &[]))?;
+    table.register(SyscallEntry::new(syscall::SYS_EXECVE, "execve", sys_execve,
+        &[ArgKind::UserPtr, ArgKind::UserPtr, ArgKind::UserPtr]))?;
+    table.register(SyscallEntry::new(syscall::SYS_WAIT4, "wait4", sys_wait4,
+        &[ArgKind::Value, ArgKind::UserPtr, ArgKind::Flags, ArgKind::UserPtr]))?;
+    table.register(SyscallEntry::new(syscall::SYS_CLONE, "clone", sys_clone,
+        &[ArgKind::Flags, ArgKind::UserPtr, ArgKind::UserPtr, ArgKind::UserPtr, ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_GETTID, "gettid", sys_gettid, &[]))?;
+    table.register(SyscallEntry::new(syscall::SYS_THREAD_EXIT, "thread_exit", sys_thread_exit, &[ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_THREAD_JOIN, "thread_join", sys_thread_join,
+        &[ArgKind::Pid, ArgKind::UserPtr]))?;
+    table.register(SyscallEntry::new(syscall::SYS_ARCH_PRCTL, "arch_prctl", sys_arch_prctl,
+        &[ArgKind::Value, ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_KILL, "kill", sys_kill, &[ArgKind::Value, ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_RT_SIGACTION, "rt_sigaction", sys_rt_sigaction,
+        &[ArgKind::Value, ArgKind::UserPtr, ArgKind::UserPtr, ArgKind::Size]))?;
+    table.register(SyscallEntry::new(syscall::SYS_RT_SIGPROCMASK, "rt_sigprocmask", sys_rt_sigprocmask,
+        &[ArgKind::Value, ArgKind::UserPtr, ArgKind::UserPtr, ArgKind::Size]))?;
+    table.register(SyscallEntry::new(syscall::SYS_RT_SIGRETURN, "rt_sigreturn", sys_rt_sigreturn, &[]))?;
+    table.register(SyscallEntry::new(syscall::SYS_GETPPID, "getppid", sys_getppid, &[]))?;
+    table.register(SyscallEntry::new(syscall::SYS_SETPGID, "setpgid", sys_setpgid, &[ArgKind::Value, ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_GETPGID, "getpgid", sys_getpgid, &[ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_SETSID, "setsid", sys_setsid, &[]))?;
+    table.register(SyscallEntry::new(syscall::SYS_GETSID, "getsid", sys_getsid, &[ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_GETPRIORITY, "getpriority", sys_getpriority,
+        &[ArgKind::Value, ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_SETPRIORITY, "setpriority", sys_setpriority,
+        &[ArgKind::Value, ArgKind::Value, ArgKind::Value]))?;
+    table.register(SyscallEntry::new(syscall::SYS_GETRLIMIT, "getrlimit", sys_getrlimit,
+        &[ArgKind::Value, ArgKind::UserPtr]))?;
+    table.register(SyscallEntry::new(syscall::SYS_SETRLIMIT, "setrlimit", sys_setrlimit,
+        &[ArgKind::Value, ArgKind::UserPtr]))?;
+    table.register(SyscallEntry::new(syscall::SYS_PRLIMIT64, "prlimit64", sys_prlimit64,
+        &[ArgKind::Value, ArgKind::Value, ArgKind::UserPtr, ArgKind::UserPtr]))?;
+    table.register(SyscallEntry::new(syscall::SYS_GETRUSAGE, "getrusage", sys_getrusage,
+        &[ArgKind::Value, ArgKind::UserPtr]))?;
+    Ok(())
+}
+
+// getpid: Return current process ID
+// スレッドからはプロセス（スレッドグループ）のIDを返す
+fn sys_getpid(_args: &SyscallArgs) -> SyscallResult {
+    let current_pid = syscall::get_current_thread_group_id();
+    crate::println!("Syscall: getpid from PID {}", current_pid);
+    Ok(current_pid)
+}
+
+// fork: Create child process
+// 子プロセスは親のユーザー空間のコピーを持ち、fork()から0を返して再開する
+// スレッドから呼んだ場合もプロセス（リーダー）の子になる
+fn sys_fork(args: &SyscallArgs) -> SyscallResult {
+    let current_pid = syscall::get_current_thread_group_id();
+    crate::println!("Syscall: fork from PID {}", current_pid);
+
+    let mut sched = SCHEDULER.lock();
+    let parent_process = match sched.processes.iter().find(|p| p.id == current_pid) {
+        Some(process) => process,
+        None => return Err(Errno::ESRCH), // Error: no current process
+    };
+
+    // Check resource limits before forking
+    if !parent_process.check_resource_limits() {
+        crate::println!("Fork failed: Parent process exceeded resource limits");
+        return Err(Errno::EAGAIN);
+    }
+
+    // Check if parent can create more children
+    if parent_process.stats.children_count >= parent_process.resource_limits.max_processes {
+        crate::println!("Fork failed: Parent process exceeded child limit");
+        return Err(Errno::EAGAIN);
+    }
+
+    // 親のレジスタはシステムコール入口でカーネルスタックに保存されている
+    let parent_context = unsafe { &*(args.context_ptr as *const ProcessContext) };
+    let child = match parent_process.fork(parent_context) {
+        Ok(child) => child,
+        Err(e) => {
+            crate::println!("Fork failed: {}", e);
+            return Err(Errno::ENOMEM);
+        }
+    };
+    let child_pid = child.id;
+
+    // Register parent-child relationship
+    if let Err(e) = sched.register_child(current_pid, child_pid) {
+        crate::println!("Fork failed: Could not register child");
+        return Err(e.into());
+    }
+    sched.add_process(child);
+
+    drop(sched);
+    // チャンネルのケイパビリティはファイルディスクリプタと同じく子に引き継ぐ
+    crate::ipc::syscalls::inherit_channels(current_pid, child_pid);
+
+    crate::println!("Fork: Parent {} created child {}", current_pid, child_pid);
+    Ok(child_pid) // Parent returns child PID
+}
+
+// execve: Replace the current process image
+// Arguments: RDI=path, RSI=argv, RDX=envp
+// 成功した場合は戻らず、新しいイメージのエントリポイントから再開する
+fn sys_execve(args: &SyscallArgs) -> SyscallResult {
+    let current_pid = syscall::get_current_thread_group_id();
+    let path = exec::copy_path_from_user(args.arg1)?;
+    crate::println!("Syscall: execve \"{}\" from PID {}", path, current_pid);
+
+    // 古いアドレス空間を解放する前に引数をすべてカーネルへコピーしておく
+    let mut total = 0usize;
+    let argv = exec::copy_string_array(args.arg2, &mut total)?;
+    let envp = exec::copy_string_array(args.arg3, &mut total)?;
+
+    let context = unsafe { &mut *(args.context_ptr as *mut ProcessContext) };
+    exec::execve(syscall::get_current_process_id(), context, &path, &argv, &envp)?;
+
+    // 新しいイメージはRAX=0で開始する
+    Ok(0)
+}
+
+// wait4: Wait for child process to exit
+// Arguments: RDI=pid, RSI=status_ptr, RDX=options, R10=ru_ptr
+// pidは-1でどの子でも、0で自分のプロセスグループの子、-1未満で-pidのグループの子
+// 子はプロセスに属するので、どのスレッドからでも待てる
+fn sys_wait4(args: &SyscallArgs) -> SyscallResult {
+    let current_pid = syscall::get_current_thread_group_id();
+    let target_pid = args.arg1;
+    let status_ptr = args.arg2;
+    let options = args.arg3;
+
+    // セキュリティ：status_ptrの検証
+    // 子プロセスを回収してから書き込みに失敗しないよう、先に検証する
+    if status_ptr != 0 && let Err(errno) = uaccess::access_ok(status_ptr, core::mem::size_of::<i32>(), true) {
+        crate::println!("SECURITY: Invalid status pointer in wait4: {:#x}", status_ptr);
+        return Err(errno);
+    }
+
+    // セキュリティ：optionsの検証
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED) != 0 {
+        crate::println!("SECURITY: Unsupported wait4 options: {}", options);
+        return Err(Errno::EINVAL);
+    }
+
+    crate::println!("Syscall: wait4 from PID {}, waiting for {}", current_pid, target_pid as i64);
+
+    let mut sched = SCHEDULER.lock();
+    let target = match target_pid as i64 {
+        -1 => WaitTarget::Any,
+        0 => WaitTarget::Group(sched.current_leader_mut().ok_or(Errno::ESRCH)?.process_group_id),
+        pid if pid < 0 => WaitTarget::Group(pid.unsigned_abs()),
+        pid => WaitTarget::Pid(pid as u64),
+    };
+
+    // Use enhanced scheduler to find zombie children
+    let target_child = sched.find_zombie_children(current_pid, target).first().copied().unwrap_or(0);
+
+    if target_child != 0 {
+        // Reap the zombie child
+        match sched.reap_zombie_child(current_pid, target_child) {
+            Ok(exit_code) => {
+                // Write exit code to status pointer if provided
+                drop(sched);
+                if status_ptr != 0 {
+                    uaccess::put_user(status_ptr, &signal::exit_status(exit_code))?;
+                }
+
+                crate::println!("Wait4: PID {} reaped child {} with exit code {}", current_pid, target_child, exit_code);
+                Ok(target_child)
+            }
+            Err(e) => {
+                crate::println!("Wait4: PID {} failed to reap child {}", current_pid, target_child);
+                Err(e.into())
+            }
+        }
+    } else if let Some((child_pid, status)) =
+        sched.take_job_status(current_pid, target, options & WUNTRACED != 0, options & WCONTINUED != 0)
+    {
+        // 停止・再開した子（WUNTRACED / WCONTINUED）。回収はしない
+        if status_ptr != 0 {
+            uaccess::put_user(status_ptr, &status)?;
+        }
+        Ok(child_pid)
+    } else {
+        // 待つべき子プロセスがいなければブロックしない
+        let has_child = sched.processes.iter().any(|p| {
+            p.parent_id == current_pid && !p.is_thread() && target.matches(p)
+        });
+        if !has_child {
+            return Err(Errno::ECHILD);
+        }
+        if options & WNOHANG != 0 {
+            return Ok(0);
+        }
+
+        // No zombie children available - block until a child exits.
+        // 子の終了で起こされたらwait4をやり直して回収する
+        match sched.current_process_mut() {
+            Some(current_process) => {
+                current_process.state = ProcessState::Waiting(WaitReason::Child(target.wait_pid()));
+                crate::println!("Wait4: PID {} blocking for child {}", current_pid, target_pid as i64);
+                Err(Errno::ERESTARTSYS)
+            }
+            None => Err(Errno::ESRCH),
+        }
+    }
+}
+
+// sched_yield: Yield CPU to another process
+fn sys_sched_yield(_args: &SyscallArgs) -> SyscallResult {
+    let current_pid = syscall::get_current_process_id();
+    crate::println!("Syscall: sched_yield from PID {}", current_pid);
+
+    // Set current process state to Ready; the switch happens on syscall return
+    let mut sched = SCHEDULER.lock();
+    if let Some(process) = sched.current_process_mut() {
+        process.state = ProcessState::Ready;
+    }
+
+    Ok(0) // Success
+}
+
+// sys_exit: プロセス終了
+// Arguments: RDI=exit_code
+// スレッドから呼んだ場合もプロセス全体（すべてのスレッド）が終了する
+fn sys_exit(args: &SyscallArgs) -> SyscallResult {
+    let current_pid = syscall::get_current_process_id();
+    let exit_code = args.arg1 as i32;
+
+    // セキュリティ：終了コードの検証
+    if !(-255..=255).contains(&exit_code) {
+        crate::println!("SECURITY: Invalid exit code: {}", exit_code);
+        return Err(Errno::EINVAL);
+    }
+
+    crate::println!("Syscall: sys_exit from PID {} with code {}", current_pid, exit_code);
+
+    let mut sched = SCHEDULER.lock();
+
+    // Use enhanced scheduler to handle process exit
+    // 親に見えるのは下位8ビットだけ（負の終了コードはシグナルでの終了を表す）
+    if let Err(e) = sched.handle_process_exit(current_pid, exit_code & 0xff) {
+        crate::println!("Exit: Failed to handle exit for PID {}", current_pid);
+        return Err(e.into());
+    }
+
+    // Don't remove from scheduler immediately - let parent reap it
+    // The zombie is switched away from on syscall return
+    Ok(0) // 成功を示す戻り値
+}
+
+// clone: Create a thread
+// Arguments: RDI=flags, RSI=stack, RDX=parent_tid, R10=child_tid, R8=tls
+// 新しいスレッドは呼び出し元と同じ位置から、`stack`をスタックにしてRAX=0で
+// 再開する。CLONE_SETTLSなら`tls`をFS_BASEにする。呼び出し元には新しい
+// スレッドのTIDを返す。
+fn sys_clone(args: &SyscallArgs) -> SyscallResult {
+    let (flags, user_stack, tls) = (args.arg1, args.arg2, args.arg5);
+    // fork相当（CLONE_THREADなし）はforkを使う。共有しないものを指定するフラグも無効
+    if flags & CLONE_THREAD_FLAGS != CLONE_THREAD_FLAGS
+        || flags & !(CLONE_THREAD_FLAGS | CLONE_FS | CLONE_FILES | CLONE_SYSVSEM | CLONE_SETTLS) != 0
+    {
+        return Err(Errno::EINVAL);
+    }
+    if flags & CLONE_SETTLS != 0 && tls >= uaccess::USER_SPACE_END {
+        return Err(Errno::EPERM);
+    }
+    // スタックを共有すると呼び出し元のスタックを壊すので、必ず指定させる
+    if user_stack == 0 || user_stack >= uaccess::USER_SPACE_END {
+        return Err(Errno::EINVAL);
+    }
+    let tgid = syscall::get_current_thread_group_id();
+
+    let mut sched = SCHEDULER.lock();
+    // RLIMIT_NPROCと同じく、スレッドの数も制限する
+    let max_threads = sched.current_leader_mut().ok_or(Errno::ESRCH)?.resource_limits.max_processes;
+    if sched.thread_count(tgid) >= max_threads as usize {
+        return Err(Errno::EAGAIN);
+    }
+
+    let context = unsafe { &*(args.context_ptr as *const ProcessContext) };
+    let mut thread = sched.current_process_mut().ok_or(Errno::ESRCH)?
+        .spawn_thread(context, user_stack)
+        .map_err(|_| Errno::ENOMEM)?;
+    if flags & CLONE_SETTLS != 0 {
+        thread.fs_base = tls;
+    }
+    let tid = thread.id;
+    // スレッドは親子関係に加えない（wait4ではなくthread_joinで回収する）
+    sched.processes.push_back(thread);
+
+    crate::println!("Clone: PID {} created thread {}", tgid, tid);
+    Ok(tid)
+}
+
+// gettid: Return the current thread ID
+fn sys_gettid(_args: &SyscallArgs) -> SyscallResult {
+    Ok(syscall::get_current_process_id())
+}
+
+// arch_prctl: Set or get architecture-specific thread state
+// Arguments: RDI=code, RSI=addr
+// TLS用のFS_BASEのみサポートする。GS_BASEはカーネルがCPU_DATAに使っている
+fn sys_arch_prctl(args: &SyscallArgs) -> SyscallResult {
+    let (code, addr) = (args.arg1, args.arg2);
+    match code {
+        ARCH_SET_FS => {
+            // 正規のユーザーアドレスでなければWRMSRが#GPを起こす
+            if addr >= uaccess::USER_SPACE_END {
+                return Err(Errno::EPERM);
+            }
+            let mut sched = SCHEDULER.lock();
+            let process = sched.current_process_mut().ok_or(Errno::ESRCH)?;
+            process.fs_base = addr;
+            FsBase::write(x86_64::VirtAddr::new(addr));
+            Ok(0)
+        }
+        ARCH_GET_FS => {
+            let fs_base = SCHEDULER.lock().current_process_mut().ok_or(Errno::ESRCH)?.fs_base;
+            uaccess::put_user(addr, &fs_base)?;
+            Ok(0)
+        }
+        ARCH_SET_GS | ARCH_GET_GS => Err(Errno::EOPNOTSUPP),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+// thread_exit: Terminate the calling thread
+// Arguments: RDI=exit_code
+// 最後のスレッドならプロセスとして終了する。終了したスレッドはthread_joinで回収する
+fn sys_thread_exit(args: &SyscallArgs) -> SyscallResult {
+    let current_tid = syscall::get_current_process_id();
+    let tgid = syscall::get_current_thread_group_id();
+    let exit_code = args.arg1 as i32;
+    if !(-255..=255).contains(&exit_code) {
+        return Err(Errno::EINVAL);
+    }
+
+    let mut sched = SCHEDULER.lock();
+    if sched.thread_count(tgid) <= 1 {
+        sched.handle_process_exit(current_tid, exit_code & 0xff)?;
+        return Ok(0);
+    }
+    // メインスレッドは親に回収されるまでプロセスを代表するので、
+    // ほかのスレッドより先には終了できない（exitでプロセスごと終了する）
+    if current_tid == tgid {
+        return Err(Errno::EBUSY);
+    }
+    sched.handle_thread_exit(current_tid, exit_code)?;
+    Ok(0)
+}
+
+// thread_join: Wait for a thread to exit and reap it
+// Arguments: RDI=tid, RSI=status_ptr
+// 同じプロセスのスレッド（メインスレッド以外）のみ待てる
+fn sys_thread_join(args: &SyscallArgs) -> SyscallResult {
+    let current_tid = syscall::get_current_process_id();
+    let tgid = syscall::get_current_thread_group_id();
+    let (tid, status_ptr) = (args.arg1, args.arg2);
+    if tid == current_tid {
+        return Err(Errno::EINVAL);
+    }
+    // 回収してから書き込みに失敗しないよう、先に検証する
+    if status_ptr != 0 {
+        uaccess::access_ok(status_ptr, core::mem::size_of::<i32>(), true)?;
+    }
+
+    let mut sched = SCHEDULER.lock();
+    if let Ok(exit_code) = sched.reap_thread(tgid, tid) {
+        if status_ptr != 0 {
+            uaccess::put_user(status_ptr, &exit_code)?;
+        }
+        return Ok(0);
+    }
+    if !sched.processes.iter().any(|p| p.id == tid && p.thread_group_id == tgid && p.is_thread()) {
+        return Err(Errno::ESRCH);
+    }
+
+    // 終了で起こされたらthread_joinをやり直して回収する
+    let current = sched.current_process_mut().ok_or(Errno::ESRCH)?;
+    current.state = ProcessState::Waiting(WaitReason::Thread(tid));
+    Err(Errno::ERESTARTSYS)
+}
+
+// kill: Send a signal to a process or a process group
+// Arguments: RDI=pid, RSI=sig (0 checks that the target exists)
+// pid > 0 はそのプロセス、0 は自分のプロセスグループ、-1 は自分とシステムの
+// プロセス以外のすべてのプロセス、-1 未満は |pid| のプロセスグループ宛て。
+// 特権がなければ送れるのは自分と自分の子だけ（setpriorityと同じ）
+fn sys_kill(args: &SyscallArgs) -> SyscallResult {
+    let pid = args.arg1 as i64;
+    let sig = args.arg2;
+    if sig != 0 && !signal::valid_signal(sig) {
+        return Err(Errno::EINVAL);
+    }
+    let current_tgid = syscall::get_current_thread_group_id();
+
+    let mut sched = SCHEDULER.lock();
+    let leader = sched.current_leader_mut().ok_or(Errno::ESRCH)?;
+    let (privileged, own_pgid) = (leader.privileged, leader.process_group_id);
+
+    let candidates: Vec<&Process> = sched.processes.iter()
+        .filter(|p| match pid {
+            1.. => p.id == pid as u64,
+            -1 => p.thread_group_id != current_tgid && !is_system_process(p) && !p.is_thread() && p.state != ProcessState::Zombie,
+            _ => {
+                let pgid = if pid == 0 { own_pgid } else { pid.unsigned_abs() };
+                p.process_group_id == pgid && !p.is_thread() && p.state != ProcessState::Zombie
+            }
+        })
+        .collect();
+    if candidates.is_empty() {
+        return Err(Errno::ESRCH);
+    }
+
+    // 送れるのは権限のあるプロセスだけ。1つもなければEPERM
+    let targets: Vec<u64> = candidates.iter()
+        .filter(|p| may_modify(current_tgid, privileged, p))
+        .map(|p| p.id)
+        .collect();
+    if targets.is_empty() {
+        return Err(Errno::EPERM);
+    }
+    if sig != 0 {
+        for target in targets {
+            sched.send_signal(target, sig)?;
+        }
+    }
+    Ok(0)
+}
+
+// kill(-1)の対象にしないプロセス：PID 1とカーネルが起動したプロセス
+// （カーネルが特権を与えるのは起動時のプロセスだけなので、特権で見分ける）
+fn is_system_process(process: &Process) -> bool {
+    process.id == 1 || process.privileged
+}
+
+// rt_sigaction: Examine and change a signal action
+// Arguments: RDI=sig, RSI=act, RDX=oact, R10=sigsetsize
+fn sys_rt_sigaction(args: &SyscallArgs) -> SyscallResult {
+    let (sig, act_ptr, oact_ptr) = (args.arg1, args.arg2, args.arg3);
+    if args.arg4 != core::mem::size_of::<SigSet>() as u64 || !signal::valid_signal(sig) {
+        return Err(Errno::EINVAL);
+    }
+    // ユーザーメモリへのアクセスはスケジューラのロックの外で行う
+    let new_action = match act_ptr {
+        0 => None,
+        ptr => Some(uaccess::get_user::<SigAction>(ptr)?),
+    };
+
+    // 動作はスレッドグループで共有する（どのスレッドから変えてもプロセス全体に効く）
+    let tgid = syscall::get_current_thread_group_id();
+    let old_action = {
+        let mut sched = SCHEDULER.lock();
+        let old_action = sched.current_leader_mut().ok_or(Errno::ESRCH)?.signals.action(sig);
+        if let Some(action) = new_action {
+            sched.set_signal_action(tgid, sig, action)?;
+        }
+        old_action
+    };
+
+    if oact_ptr != 0 {
+        uaccess::put_user(oact_ptr, &old_action)?;
+    }
+    Ok(0)
+}
+
+// rt_sigprocmask: Examine and change blocked signals
+// Arguments: RDI=how, RSI=set, RDX=oldset, R10=sigsetsize
+fn sys_rt_sigprocmask(args: &SyscallArgs) -> SyscallResult {
+    let (how, set_ptr, oldset_ptr) = (args.arg1, args.arg2, args.arg3);
+    if args.arg4 != core::mem::size_of::<SigSet>() as u64 {
+        return Err(Errno::EINVAL);
+    }
+    let set = match set_ptr {
+        0 => None,
+        ptr => Some(uaccess::get_user::<SigSet>(ptr)?),
+    };
+
+    let old_blocked = {
+        let mut sched = SCHEDULER.lock();
+        let process = sched.current_process_mut().ok_or(Errno::ESRCH)?;
+        let old_blocked = process.signals.blocked;
+        if let Some(set) = set {
+            let blocked = match how {
+                signal::SIG_BLOCK => old_blocked | set,
+                signal::SIG_UNBLOCK => old_blocked & !set,
+                signal::SIG_SETMASK => set,
+                _ => return Err(Errno::EINVAL),
+            };
+            process.signals.set_blocked(blocked);
+        }
+        old_blocked
+    };
+
+    if oldset_ptr != 0 {
+        uaccess::put_user(oldset_ptr, &old_blocked)?;
+    }
+    // ブロックを外したシグナルはシステムコールからの復帰時に配送される
+    Ok(0)
+}
+
+// rt_sigreturn: Return from a signal handler
+// シグナルフレームに保存したレジスタとシグナルマスクを復元する。
+// 割り込まれた時点のRAX・RIPをそのまま返すため、EJUSTRETURNで
+// 戻り値の書き込みとやり直しの判定を飛ばす（RAXは任意の値でありうる）。
+fn sys_rt_sigreturn(args: &SyscallArgs) -> SyscallResult {
+    let context = unsafe { &mut *(args.context_ptr as *mut ProcessContext) };
+    let frame = match signal::restore_frame(context.stack_pointer()) {
+        Ok(frame) => frame,
+        Err(_) => {
+            // フレームが壊れていたら戻る先がないので終了させる
+            let mut sched = SCHEDULER.lock();
+            if let Some(process) = sched.current_process_mut() {
+                process.signals.force(signal::SIGSEGV);
+            }
+            return Err(Errno::EFAULT);
+        }
+    };
+
+    let mut sched = SCHEDULER.lock();
+    let process = sched.current_process_mut().ok_or(Errno::ESRCH)?;
+    process.signals.set_blocked(frame.blocked);
+    *context = frame.context;
+    Err(Errno::EJUSTRETURN)
+}
+
+// getppid: Return the parent process ID
+fn sys_getppid(_args: &SyscallArgs) -> SyscallResult {
+    let mut sched = SCHEDULER.lock();
+    let process = sched.current_leader_mut().ok_or(Errno::ESRCH)?;
+    Ok(process.parent_id)
+}
+
+// setpgid: Set the process group of a process
+// Arguments: RDI=pid (0 = self), RSI=pgid (0 = same as pid)
+// 対象は自分か自分の子で、同じセッション内のグループにしか移れない
+fn sys_setpgid(args: &SyscallArgs) -> SyscallResult {
+    let current_pid = syscall::get_current_thread_group_id();
+    if (args.arg1 as i64) < 0 || (args.arg2 as i64) < 0 {
+        return Err(Errno::EINVAL);
+    }
+    let pid = if args.arg1 == 0 { current_pid } else { args.arg1 };
+    let pgid = if args.arg2 == 0 { pid } else { args.arg2 };
+
+    let mut sched = SCHEDULER.lock();
+    let session_id = sched.current_leader_mut().ok_or(Errno::ESRCH)?.session_id;
+    // 既存のグループに移る場合は、同じセッションにそのグループがなければならない
+    if pgid != pid && !sched.group_exists(pgid, session_id) {
+        return Err(Errno::EPERM);
+    }
+
+    let target = sched.processes.iter_mut()
+        .find(|p| p.id == pid && !p.is_thread() && (p.id == current_pid || p.parent_id == current_pid))
+        .ok_or(Errno::ESRCH)?;
+    if target.session_id != session_id || target.is_session_leader() {
+        return Err(Errno::EPERM);
+    }
+    if pgid == pid {
+        target.create_process_group()?;
+    } else {
+        target.join_process_group(pgid)?;
+    }
+    Ok(0)
+}
+
+// getpgid: Return the process group of a process
+// Arguments: RDI=pid (0 = self)
+fn sys_getpgid(args: &SyscallArgs) -> SyscallResult {
+    let pid = if args.arg1 == 0 { syscall::get_current_thread_group_id() } else { args.arg1 };
+    let sched = SCHEDULER.lock();
+    let process = sched.processes.iter().find(|p| p.id == pid).ok_or(Errno::ESRCH)?;
+    Ok(process.process_group_id)
+}
+
+// setsid: Create a new session
+// 呼び出し元は新しいセッションと新しいプロセスグループのリーダーになる
+fn sys_setsid(_args: &SyscallArgs) -> SyscallResult {
+    let current_pid = syscall::get_current_thread_group_id();
+    let mut sched = SCHEDULER.lock();
+    // 自分のPIDをIDとするグループが残っていると、そのグループがセッションをまたぐ
+    if sched.processes.iter().any(|p| p.process_group_id == current_pid && p.state != ProcessState::Zombie) {
+        return Err(Errno::EPERM);
+    }
+    let process = sched.current_leader_mut().ok_or(Errno::ESRCH)?;
+    let sid = process.create_session()?;
+    Ok(sid)
+}
+
+// getsid: Return the session ID of a process
+// Arguments: RDI=pid (0 = self)
+fn sys_getsid(args: &SyscallArgs) -> SyscallResult {
+    let pid = if args.arg1 == 0 { syscall::get_current_thread_group_id() } else { args.arg1 };
+    let sched = SCHEDULER.lock();
+    let process = sched.processes.iter().find(|p| p.id == pid).ok_or(Errno::ESRCH)?;
+    Ok(process.session_id)
+}
+
+// 特権がなければ、変更できるのは自分（のスレッド）と自分の子だけ
+// `current_tgid`と`privileged`は呼び出し元のスレッドグループのリーダーのもの
+fn may_modify(current_tgid: u64, privileged: bool, target: &Process) -> bool {
+    privileged || target.thread_group_id == current_tgid || target.parent_id == current_tgid
+}
+
+// getpriority / setpriority の対象となるプロセスのPID
+fn priority_targets(sched: &mut Scheduler, which: u64, who: u64) -> Result<Vec<u64>, Errno> {
+    // who=0は呼び出したプロセス（スレッドではなくスレッドグループ）
+    let current_pid = syscall::get_current_thread_group_id();
+    let targets: Vec<u64> = match which {
+        resource::PRIO_PROCESS => {
+            let pid = if who == 0 { current_pid } else { who };
+            sched.processes.iter().filter(|p| p.id == pid).map(|p| p.id).collect()
+        }
+        resource::PRIO_PGRP => {
+            let pgid = match who {
+                0 => sched.current_leader_mut().ok_or(Errno::ESRCH)?.process_group_id,
+                pgid => pgid,
+            };
+            sched.processes.iter()
+                .filter(|p| p.process_group_id == pgid && p.state != ProcessState::Zombie)
+                .map(|p| p.id)
+                .collect()
+        }
+        // ユーザーの概念がまだないので PRIO_USER も無効
+        _ => return Err(Errno::EINVAL),
+    };
+    if targets.is_empty() {
+        return Err(Errno::ESRCH);
+    }
+    Ok(targets)
+}
+
+// getpriority: Get the scheduling priority
+// Arguments: RDI=which, RSI=who
+// Linuxのシステムコールと同じく、負の値を避けるため 20 - nice（1..=40）を返す
+fn sys_getpriority(args: &SyscallArgs) -> SyscallResult {
+    let mut sched = SCHEDULER.lock();
+    let targets = priority_targets(&mut sched, args.arg1, args.arg2)?;
+    let best = sched.processes.iter()
+        .filter(|p| targets.contains(&p.id))
+        .map(|p| p.priority)
+        .min()
+        .ok_or(Errno::ESRCH)?;
+    Ok((20 - resource::priority_to_nice(best)) as u64)
+}
+
+// setpriority: Set the scheduling priority
+// Arguments: RDI=which, RSI=who, RDX=nice
+// 優先度を上げる（niceを下げる）には特権が必要
+fn sys_setpriority(args: &SyscallArgs) -> SyscallResult {
+    let current_tgid = syscall::get_current_thread_group_id();
+    let priority = resource::nice_to_priority(args.arg3 as i64);
+
+    let mut sched = SCHEDULER.lock();
+    let privileged = sched.current_leader_mut().ok_or(Errno::ESRCH)?.privileged;
+    let targets = priority_targets(&mut sched, args.arg1, args.arg2)?;
+
+    // 一部だけ変更されることがないよう、先にすべて検査する
+    for process in sched.processes.iter().filter(|p| targets.contains(&p.id)) {
+        if !may_modify(current_tgid, privileged, process) {
+            return Err(Errno::EPERM);
+        }
+        resource::check_priority_change(process.priority, priority, privileged)?;
+    }
+    for process in sched.processes.iter_mut().filter(|p| targets.contains(&p.id)) {
+        process.set_priority(priority)?;
+    }
+    Ok(0)
+}
+
+// getrlimit / setrlimit / prlimit64 の共通処理
+// `pid`が0なら自分。`new_limit`があれば変更し、変更前の値を返す
+fn do_prlimit(pid: u64, res: u64, new_limit: Option<Rlimit>) -> Result<Rlimit, Errno> {
+    let current_pid = syscall::get_current_thread_group_id();
+    let pid = if pid == 0 { current_pid } else { pid };
+
+    let mut sched = SCHEDULER.lock();
+    let privileged = sched.current_leader_mut().ok_or(Errno::ESRCH)?.privileged;
+    let process = sched.processes.iter_mut().find(|p| p.id == pid).ok_or(Errno::ESRCH)?;
+    if pid != current_pid && !may_modify(current_pid, privileged, process) {
+        return Err(Errno::EPERM);
+    }
+
+    let old = resource::get_rlimit(&process.resource_limits, &process.hard_limits, res)?;
+    if let Some(new_limit) = new_limit {
+        resource::apply_rlimit(&mut process.resource_limits, &mut process.hard_limits, res, &new_limit, privileged)?;
+    }
+    Ok(old)
+}
+
+// getrlimit: Get resource limits
+// Arguments: RDI=resource, RSI=rlim
+fn sys_getrlimit(args: &SyscallArgs) -> SyscallResult {
+    let old = do_prlimit(0, args.arg1, None)?;
+    uaccess::put_user(args.arg2, &old)?;
+    Ok(0)
+}
+
+// setrlimit: Set resource limits
+// Arguments: RDI=resource, RSI=rlim
+fn sys_setrlimit(args: &SyscallArgs) -> SyscallResult {
+    let new_limit: Rlimit = uaccess::get_user(args.arg2)?;
+    do_prlimit(0, args.arg1, Some(new_limit))?;
+    Ok(0)
+}
+
+// prlimit64: Get and set resource limits of a process
+// Arguments: RDI=pid (0 = self), RSI=resource, RDX=new_limit, R10=old_limit
+fn sys_prlimit64(args: &SyscallArgs) -> SyscallResult {
+    let new_limit = match args.arg3 {
+        0 => None,
+        ptr => Some(uaccess::get_user::<Rlimit>(ptr)?),
+    };
+    let old = do_prlimit(args.arg1, args.arg2, new_limit)?;
+    if args.arg4 != 0 {
+        uaccess::put_user(args.arg4, &old)?;
+    }
+    Ok(0)
+}
+
+// getrusage: Get resource usage
+// Arguments: RDI=who, RSI=usage
+fn sys_getrusage(args: &SyscallArgs) -> SyscallResult {
+    let usage = {
+        let mut sched = SCHEDULER.lock();
+        let process = sched.current_leader_mut().ok_or(Errno::ESRCH)?;
+        match args.arg1 as i64 {
+            resource::RUSAGE_SELF => Rusage {
+                ru_utime: Timeval::from_millis(process.stats.user_time),
+                ru_stime: Timeval::from_millis(process.stats.system_time),
+                ru_maxrss: (process.stats.memory_used / 1024) as i64,
+                ..Rusage::default()
+            },
+            resource::RUSAGE_CHILDREN => Rusage {
+                ru_utime: Timeval::from_millis(process.stats.children_user_time),
+                ru_stime: Timeval::from_millis(process.stats.children_system_time),
+                ..Rusage::default()
+            },
+            _ => return Err(Errno::EINVAL),
+        }
+    };
+    uaccess::put_user(args.arg2, &usage)?;
+    Ok(0)
+}
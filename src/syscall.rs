@@ -5,7 +5,77 @@ use crate::gdt;
 use core::arch::naked_asm;
 use core::sync::atomic::{AtomicU64, Ordering};
 
-// セキュリティ：安全なシステムコール引数解析
+pub mod table;
+
+use table::{ArgKind, SyscallEntry, SyscallResult, SyscallTable};
+use crate::error::{Errno, KernelResult};
+use crate::memory::uaccess;
+use crate::process::ProcessContext;
+
+// システムコール番号
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_CREATE_CHANNEL: u64 = 2;
+pub const SYS_SEND_MESSAGE: u64 = 3;
+pub const SYS_RECEIVE_MESSAGE: u64 = 4;
+pub const SYS_CREATE_MEMORY_HANDLE: u64 = 5;
+pub const SYS_TRANSFER_MEMORY: u64 = 6;
+pub const SYS_RECEIVE_MEMORY_HANDLE: u64 = 7;
+pub const SYS_MMAP: u64 = 9;
+pub const SYS_MPROTECT: u64 = 10;
+pub const SYS_MUNMAP: u64 = 11;
+pub const SYS_BRK: u64 = 12;
+pub const SYS_RT_SIGACTION: u64 = 13;
+pub const SYS_RT_SIGPROCMASK: u64 = 14;
+pub const SYS_RT_SIGRETURN: u64 = 15;
+pub const SYS_SCHED_YIELD: u64 = 24;
+pub const SYS_NANOSLEEP: u64 = 35;
+pub const SYS_GETPID: u64 = 39;
+pub const SYS_CLONE: u64 = 56;
+pub const SYS_FORK: u64 = 57;
+pub const SYS_EXECVE: u64 = 59;
+/// Linuxのexitと同じく、呼び出したスレッドだけを終了する（SYS_EXITはプロセス全体）
+pub const SYS_THREAD_EXIT: u64 = 60;
+pub const SYS_WAIT4: u64 = 61;
+pub const SYS_KILL: u64 = 62;
+pub const SYS_GETRLIMIT: u64 = 97;
+pub const SYS_GETRUSAGE: u64 = 98;
+pub const SYS_SETPGID: u64 = 109;
+pub const SYS_GETPPID: u64 = 110;
+pub const SYS_SETSID: u64 = 112;
+pub const SYS_GETPGID: u64 = 121;
+pub const SYS_GETSID: u64 = 124;
+pub const SYS_GETPRIORITY: u64 = 140;
+pub const SYS_SETPRIORITY: u64 = 141;
+pub const SYS_ARCH_PRCTL: u64 = 158;
+pub const SYS_SETRLIMIT: u64 = 160;
+pub const SYS_GETTID: u64 = 186;
+pub const SYS_FUTEX: u64 = 202;
+pub const SYS_CLOCK_GETTIME: u64 = 228;
+pub const SYS_PRLIMIT64: u64 = 302;
+/// スレッドの終了を待って回収する（ruix独自）
+pub const SYS_THREAD_JOIN: u64 = 500;
+/// 同期IPCの呼び出し（返信まで待つ、ruix独自）
+pub const SYS_IPC_CALL: u64 = 501;
+/// 同期IPCの返信と次の呼び出しの受け取り（ruix独自）
+pub const SYS_IPC_REPLY_WAIT: u64 = 502;
+/// チャンネルの端点のケイパビリティを閉じる（ruix独自）
+pub const SYS_CLOSE_CHANNEL: u64 = 503;
+/// 名前サービスにサービスを登録する（ruix独自）
+pub const SYS_REGISTER_SERVICE: u64 = 504;
+/// 名前サービスでサービスを検索する（ruix独自）
+pub const SYS_LOOKUP_SERVICE: u64 = 505;
+
+/// システムコールの入口で積むレジスタフレーム
+///
+/// SYSCALL命令（`asm_syscall_handler`）と`int 0x80`
+/// （`interrupts::syscall_interrupt_handler`）のどちらも、カーネルスタックの先頭に
+/// 汎用レジスタすべてとIRETQ用フレームを`ProcessContext`と同じ並びで積む。
+/// そのためハンドラ中でブロックしても、このフレームをそのまま保存して別の
+/// プロセスに切り替えられる。
+pub type SyscallFrame = ProcessContext;
+
+/// システムコールハンドラに渡す引数
 #[derive(Debug)]
 pub struct SyscallArgs {
     pub syscall_number: u64,
@@ -15,41 +85,29 @@ pub struct SyscallArgs {
     pub arg4: u64,
     pub arg5: u64,
     pub arg6: u64,
+    /// 呼び出し元のレジスタを保存した`SyscallFrame`へのポインタ（カーネルスタック上）
+    pub context_ptr: u64,
 }
 
 impl SyscallArgs {
-    // 安全な引数解析関数
-    pub fn safe_parse(stack_ptr: u64) -> Result<Self, SyscallError> {
-        // スタックポインタの検証
-        if !validate_stack_pointer(stack_ptr) {
-            return Err(SyscallError::InvalidStackPointer);
-        }
-
-        // システムコール番号と引数を安全に取得
-        let syscall_number = safe_read_register(stack_ptr, -1)?; // RAX
-        let arg1 = safe_read_register(stack_ptr, 2)?;  // RDI
-        let arg2 = safe_read_register(stack_ptr, 4)?;  // RSI
-        let arg3 = safe_read_register(stack_ptr, 5)?;  // RDX
-        let arg4 = safe_read_register(stack_ptr, 6)?;  // R10
-        let arg5 = safe_read_register(stack_ptr, 7)?;  // R8
-        let arg6 = safe_read_register(stack_ptr, 8)?;  // R9
-
-        // システムコール番号の検証
-        if !validate_syscall_number(syscall_number) {
-            return Err(SyscallError::InvalidSyscallNumber(syscall_number));
-        }
-
-        crate::println!("SECURITY: Syscall args parsed safely for syscall {}", syscall_number);
-
-        Ok(SyscallArgs {
-            syscall_number,
+    /// フレームから番号と引数を取り出す
+    pub fn from_frame(frame: &SyscallFrame) -> Self {
+        let [arg1, arg2, arg3, arg4, arg5, arg6] = frame.syscall_args();
+        SyscallArgs {
+            syscall_number: frame.syscall_number(),
             arg1,
             arg2,
             arg3,
             arg4,
             arg5,
             arg6,
-        })
+            context_ptr: frame as *const SyscallFrame as u64,
+        }
+    }
+
+    /// 引数を配列として取得（テーブル駆動の検証・トレース用）
+    pub fn as_array(&self) -> [u64; 6] {
+        [self.arg1, self.arg2, self.arg3, self.arg4, self.arg5, self.arg6]
     }
 }
 
@@ -59,61 +117,45 @@ pub enum SyscallError {
     InvalidStackPointer,
     InvalidSyscallNumber(u64),
     InvalidPointer(u64),
-    AccessViolation(u64),
     BoundsExceeded,
 }
 
-// スタックポインタを検証
-fn validate_stack_pointer(stack_ptr: u64) -> bool {
-    // スタックポインタが合理的な範囲にあることを確認
-    // カーネルスタックは通常0xFFFF_8000_0000_0000以降
-    if stack_ptr < 0xFFFF_8000_0000_0000 || stack_ptr > 0xFFFF_FFFF_FFFF_FFFF {
-        crate::println!("SECURITY: Invalid stack pointer: {:#x}", stack_ptr);
+impl From<SyscallError> for Errno {
+    fn from(err: SyscallError) -> Self {
+        match err {
+            SyscallError::InvalidStackPointer => Errno::EFAULT,
+            SyscallError::InvalidSyscallNumber(_) => Errno::ENOSYS,
+            SyscallError::InvalidPointer(_) => Errno::EFAULT,
+            SyscallError::BoundsExceeded => Errno::EFAULT,
+        }
+    }
+}
+
+// フレームのアドレスを検証
+fn validate_frame_pointer(frame_ptr: u64) -> bool {
+    // 入口のアセンブリはフレームを現在のカーネルスタックの最上部に積む
+    let expected = gdt::kernel_stack_top().as_u64() - core::mem::size_of::<SyscallFrame>() as u64;
+    if frame_ptr != expected {
+        crate::println!("SECURITY: Invalid syscall frame: {:#x} (expected {:#x})", frame_ptr, expected);
         return false;
     }
     
     // 16バイト境界にアライメントされていることを確認
-    if stack_ptr % 16 != 0 {
-        crate::println!("SECURITY: Stack pointer not aligned: {:#x}", stack_ptr);
+    if !frame_ptr.is_multiple_of(16) {
+        crate::println!("SECURITY: Syscall frame not aligned: {:#x}", frame_ptr);
         return false;
     }
     
     true
 }
 
-// システムコール番号を検証
-fn validate_syscall_number(syscall_number: u64) -> bool {
-    // サポートされているシステムコール番号の範囲チェック
-    match syscall_number {
-        0 | 1 | 2 | 3 | 4 | 5 | 6 | 7 | 24 | 39 | 57 | 61 => {
-            crate::println!("SECURITY: Valid syscall number: {}", syscall_number);
-            true
-        }
-        _ => {
-            crate::println!("SECURITY: Invalid syscall number: {}", syscall_number);
-            false
-        }
-    }
-}
-
-// 安全なレジスタ読み取り
-fn safe_read_register(stack_ptr: u64, offset: isize) -> Result<u64, SyscallError> {
-    // メモリアクセスの境界チェック
-    let addr = stack_ptr as isize + (offset * 8) as isize;
-    if addr < 0 || addr > 0x7FFF_FFFF_FFFF_FFFF {
-        return Err(SyscallError::InvalidPointer(addr as u64));
-    }
-
-    // 安全なメモリ読み取り
-    let ptr = unsafe { (stack_ptr as *const u64).offset(offset) };
-    let value = unsafe { *ptr };
-    
-    // 値の合理性チェック
-    if value == 0xDEAD_BEEF_DEAD_BEEF {
-        return Err(SyscallError::AccessViolation(value));
+// フレームから引数を取り出して検証する
+fn parse_frame(frame_ptr: u64) -> Result<SyscallArgs, SyscallError> {
+    if !validate_frame_pointer(frame_ptr) {
+        return Err(SyscallError::InvalidStackPointer);
     }
-    
-    Ok(value)
+    // 登録されていない番号はdispatchが-ENOSYSを返す
+    Ok(SyscallArgs::from_frame(unsafe { &*(frame_ptr as *const SyscallFrame) }))
 }
 
 // ポインタ引数を安全に検証
@@ -155,6 +197,8 @@ pub struct CpuData {
     pub current_process_id: u64,
     // TSSへのポインタ（将来的な割り込み処理用） (offset 24)
     pub tss_ptr: u64,
+    // 現在実行中のスレッドが属するプロセス（スレッドグループ）のID (offset 32)
+    pub current_thread_group_id: u64,
 }
 
 // 起動時はゼロで初期化。
@@ -164,6 +208,7 @@ pub static mut CPU_DATA: CpuData = CpuData {
     kernel_stack_top: 0,
     current_process_id: 0,
     tss_ptr: 0,
+    current_thread_group_id: 0,
 };
 
 /// Thread-safe function to get current process ID
@@ -178,6 +223,19 @@ pub fn set_current_process_id(pid: u64) {
     unsafe { CPU_DATA.current_process_id = pid }
 }
 
+/// 現在のプロセスID（スレッドならそのスレッドグループのID）
+///
+/// `get_current_process_id`はスケジューラが切り替える単位（スレッド）のID。
+/// ユーザーから見えるPIDや、プロセス全体で共有するIPCハンドルにはこちらを使う。
+pub fn get_current_thread_group_id() -> u64 {
+    unsafe { CPU_DATA.current_thread_group_id }
+}
+
+/// 現在のスレッドグループIDを設定
+pub fn set_current_thread_group_id(tgid: u64) {
+    unsafe { CPU_DATA.current_thread_group_id = tgid }
+}
+
 pub fn init() {
     use x86_64::registers::model_specific::Efer;
     
@@ -218,10 +276,12 @@ pub fn init() {
         // 割り込みフラグ(IF)をクリアして、ハンドラ実行中の割り込みを禁止する
         SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG);
     }
+    // システムコールテーブル（table::init）はヒープを使うので、ヒープの初期化後に構築する
 }
 
 // システムコールのエントリポイント（アセンブリ）
-// 保存すべきレジスタをスタックに積み、Rustのハンドラを呼び出す
+// カーネルスタック上にSyscallFrameを積み、Rustのハンドラを呼び出す。
+// ハンドラは復帰先のフレームを返すので、プロセスの切り替えもここで行える。
 #[unsafe(naked)]
 unsafe extern "C" fn asm_syscall_handler() {
     naked_asm!(
@@ -230,482 +290,141 @@ unsafe extern "C" fn asm_syscall_handler() {
         "mov gs:[0], rsp",      // [gs:0] へのユーザーRSP退避
         "mov rsp, gs:[8]",      // [gs:8] からカーネルスタックをロード
 
-        // コンテキスト保存
+        // IRETQ用フレーム（割り込みでCPUが積むものと同じ形）
+        "push 0x1b",            // SS（ユーザーデータセグメント）
+        "push qword ptr gs:[0]", // RSP
         "push r11",             // RFLAGS
+        "push 0x23",            // CS（ユーザーコードセグメント）
         "push rcx",             // 復帰用RIP
-        
-        // スタックアライメント調整 (16byte境界)
-        "sub rsp, 8",
-        
-        "mov rdi, rsp",         // 第1引数に現在のスタックポインタ
-        "call {rust_handler}",  // Rustハンドラを呼び出し、RAXに結果が返る
-        // 結果はすでにRAXにある
-
-        "add rsp, 8",           // 調整を戻す
+        "swapgs",
 
-        "pop rcx",
+        // 汎用レジスタ
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+
+        "mov rdi, rsp",         // 第1引数にSyscallFrame
+        "call {rust_handler}",  // 復帰先のコンテキストがRAXに返る
+        "mov rsp, rax",
+
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
         "pop r11",
-        
-        "mov rsp, gs:[0]",      // ユーザーRSP復元
-        "swapgs",
-        "sysretq",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+
+        // 別のプロセスに切り替わる場合もあるので、sysretqではなくiretqで戻る
+        "iretq",
         rust_handler = sym rust_syscall_handler,
     );
 }
 
 // Rust側のシステムコール処理ロジック
-pub extern "C" fn rust_syscall_handler(stack_ptr: u64) -> u64 {
+//
+// `frame_ptr`はカーネルスタック上のSyscallFrame（SYSCALLとint 0x80で共通）。
+// 戻り値をそのRAXに書き込み、復帰先のフレーム（ハンドラ中でブロックして
+// プロセスが切り替わった場合は次のプロセスのもの）を返す。
+pub extern "C" fn rust_syscall_handler(frame_ptr: u64) -> u64 {
     // 現在のプロセスIDを取得（デバッグ用）
     let current_pid = unsafe { CPU_DATA.current_process_id };
-    
-    // セキュリティ：安全な引数解析を使用
-    let args = match SyscallArgs::safe_parse(stack_ptr) {
-        Ok(args) => args,
+    let entry_tsc = crate::timer::read_tsc();
+
+    let result = match parse_frame(frame_ptr) {
+        Ok(args) => table::dispatch(&args),
         Err(err) => {
-            crate::println!("SECURITY ERROR: Syscall argument parsing failed: {:?}", err);
-            return -1i64 as u64; // エラーコードを返す
+            crate::println!("SECURITY ERROR: Syscall argument parsing failed for PID {}: {:?}", current_pid, err);
+            Errno::from(err).as_syscall_return() // -errnoを返す
         }
     };
-    
-    crate::println!("SECURITY: Safe syscall processing for PID {}, syscall {}", current_pid, args.syscall_number);
-    
-    let result = match args.syscall_number {
-        39 => {
-            // getpid: Return current process ID
-            crate::println!("Syscall: getpid from PID {}", current_pid);
-            current_pid as i64
-        }
-        57 => {
-            // fork: Create child process
-            crate::println!("Syscall: fork from PID {}", current_pid);
-            
-            use crate::process::scheduler::SCHEDULER;
-            
-            let mut sched = SCHEDULER.lock();
-            if let Some(parent_process) = sched.processes.front() {
-                if parent_process.id == current_pid {
-                    // Check resource limits before forking
-                    if !parent_process.check_resource_limits() {
-                        crate::println!("Fork failed: Parent process exceeded resource limits");
-                        return -1i64 as u64;
-                    }
-                    
-                    // Check if parent can create more children
-                    if parent_process.stats.children_count >= parent_process.resource_limits.max_processes {
-                        crate::println!("Fork failed: Parent process exceeded child limit");
-                        return -1i64 as u64;
-                    }
-                    
-                    // Safe PID allocation
-                    let child_pid = crate::process::allocate_pid();
-                    
-                    // Register parent-child relationship
-                    if let Err(_) = sched.register_child(current_pid, child_pid) {
-                        crate::println!("Fork failed: Could not register child");
-                        return -1i64 as u64;
-                    }
-                    
-                    crate::println!("Fork: Parent {} created child {}", current_pid, child_pid);
-                    child_pid as i64 // Parent returns child PID
-                } else {
-                    -1i64 // Error: process not found
-                }
-            } else {
-                -1i64 // Error: no current process
-            }
-        }
-        61 => {
-            // wait4: Wait for child process to exit
-            // Arguments: RDI=pid, RSI=status_ptr, RDX=options, R10=ru_ptr
-            let target_pid = args.arg1;
-            let status_ptr = args.arg2;
-            let options = args.arg3;
-            
-            // セキュリティ：引数の検証
-            if target_pid != -1i64 as u64 && (target_pid < 1000 || target_pid > 10000) {
-                crate::println!("SECURITY: Invalid target PID for wait4: {}", target_pid);
-                return -1i64 as u64;
-            }
-            
-            // セキュリティ：status_ptrの検証
-            if status_ptr != 0 {
-                if let Err(err) = validate_user_pointer(status_ptr, 4) { // i32 = 4 bytes
-                    crate::println!("SECURITY: Invalid status pointer in wait4: {:?}", err);
-                    return -1i64 as u64;
-                }
-            }
-            
-            // セキュリティ：optionsの検証（現在は0のみサポート）
-            if options != 0 {
-                crate::println!("SECURITY: Unsupported wait4 options: {}", options);
-                return -1i64 as u64;
-            }
-            
-            crate::println!("Syscall: wait4 from PID {}, waiting for {}", current_pid, target_pid);
-            
-            use crate::process::scheduler::SCHEDULER;
-            use crate::process::{ProcessState, WaitReason};
-            
-            let mut sched = SCHEDULER.lock();
-            
-            // Use enhanced scheduler to find zombie children
-            let zombie_children = sched.find_zombie_children(current_pid);
-            let target_child = if target_pid == -1i64 as u64 {
-                // Any child
-                zombie_children.first().copied().unwrap_or(0)
-            } else {
-                // Specific child
-                zombie_children.iter().find(|&&pid| pid == target_pid).copied().unwrap_or(0)
-            };
-            
-            if target_child != 0 {
-                // Reap the zombie child
-                match sched.reap_zombie_child(current_pid, target_child) {
-                    Ok(exit_code) => {
-                        // Write exit code to status pointer if provided
-                        if status_ptr != 0 {
-                            unsafe {
-                                *(status_ptr as *mut i32) = exit_code;
-                            }
-                        }
-                        
-                        crate::println!("Wait4: PID {} reaped child {} with exit code {}", current_pid, target_child, exit_code);
-                        target_child as i64
-                    }
-                    Err(_) => {
-                        crate::println!("Wait4: PID {} failed to reap child {}", current_pid, target_child);
-                        -1i64
-                    }
-                }
-            } else {
-                // No zombie children available - properly block current process
-                if let Some(current_process) = sched.processes.front_mut() {
-                    if current_process.id == current_pid {
-                        current_process.state = ProcessState::Waiting(WaitReason::Child(target_pid));
-                        crate::println!("Wait4: PID {} blocking for child {}", current_pid, target_pid);
-                        
-                        // スケジューラーでブロックされたプロセスを最後に移動
-                        sched.processes.rotate_left(1);
-                        
-                        // 次のラウンドで再スケジュール
-                        if let Some(next_process) = sched.processes.front() {
-                            unsafe {
-                                crate::syscall::CPU_DATA.current_process_id = next_process.id;
-                            }
-                            // CR3レジスタを新しいプロセスのページテーブルに切り替え
-                            unsafe {
-                                x86_64::registers::control::Cr3::write(next_process.page_table_frame, x86_64::registers::control::Cr3Flags::empty());
-                            }
-                            return next_process.context_ptr;
-                        }
-                    }
-                }
-                
-                // 緊急時のフォールバック
-                crate::println!("Wait4: PID {} - blocking implementation failed", current_pid);
-                -1i64 // Block (would normally resume when child exits)
-            }
-        }
-        24 => {
-            // sched_yield: Yield CPU to another process
-            crate::println!("Syscall: sched_yield from PID {}", current_pid);
-            
-            // Set current process state to Ready and trigger scheduling
-            use crate::process::scheduler::SCHEDULER;
-            use crate::process::ProcessState;
-            
-            let mut sched = SCHEDULER.lock();
-            if let Some(process) = sched.processes.front_mut() {
-                if process.id == current_pid {
-                    process.state = ProcessState::Ready;
-                }
-            }
-            
-            0i64 // Success
-        }
-        0 => {
-            // sys_exit: プロセス終了
-            // Arguments: RDI=exit_code
-            let exit_code = args.arg1 as i32;
-            
-            // セキュリティ：終了コードの検証
-            if exit_code < -255 || exit_code > 255 {
-                crate::println!("SECURITY: Invalid exit code: {}", exit_code);
-                return -1i64 as u64;
-            }
-            
-            crate::println!("Syscall: sys_exit from PID {} with code {}", current_pid, exit_code);
-            
-            use crate::process::scheduler::SCHEDULER;
-            
-            let mut sched = SCHEDULER.lock();
-            
-            // Use enhanced scheduler to handle process exit
-            if let Err(_) = sched.handle_process_exit(current_pid, exit_code) {
-                crate::println!("Exit: Failed to handle exit for PID {}", current_pid);
-                return -1i64 as u64;
-            }
-            
-            // Don't remove from scheduler immediately - let parent reap it
-            // Current process ID will be reset by scheduler on next context switch
-            unsafe { crate::syscall::CPU_DATA.current_process_id = 0; }
-            
-            0 // 成功を示す戻り値
-        }
-        1 => {
-            // sys_write: デバッグ出力
-            // 引数: RDI=fd, RSI=buf*, RDX=count
-            let fd = args.arg1;
-            let buf = args.arg2;
-            let count = args.arg3;
-            
-            // セキュリティ：引数の検証
-            if fd > 2 { // stdin(0), stdout(1), stderr(2) のみ許可
-                crate::println!("SECURITY: Invalid file descriptor: {}", fd);
-                return -1i64 as u64;
-            }
-            
-            if count > 4096 { // 4KB制限
-                crate::println!("SECURITY: Write count too large: {}", count);
-                return -1i64 as u64;
-            }
-            
-            // セキュリティ：ユーザーポインタの検証
-            if let Err(err) = validate_user_pointer(buf, count as usize) {
-                crate::println!("SECURITY: Invalid user pointer in write: {:?}", err);
-                return -1i64 as u64;
-            }
-            
-            if fd == 1 { // stdout
-                // 簡単な文字列出力（バッファから文字列を取得）
-                let slice = unsafe { core::slice::from_raw_parts(buf as *const u8, count as usize) };
-                if let Ok(s) = core::str::from_utf8(slice) {
-                    crate::print!("{}", s);
-                    count as i64 // 書き込んだバイト数を返す
-                } else {
-                    -1i64 // エラー
-                }
-            } else {
-                -1i64 // 無効なファイルディスクリプタ
-            }
-        }
-        2 => {
-            // sys_create_channel: IPCチャネルの作成
-            // 引数: RDI=target_pid
-            let target_pid = args.arg1;
-            
-            // セキュリティ：target_pidの検証
-            if target_pid == 0 || target_pid > 10000 {
-                crate::println!("SECURITY: Invalid target PID for create_channel: {}", target_pid);
-                return -1i64 as u64;
-            }
-            
-            crate::println!("IPC: Process {} creating channel with {}", current_pid, target_pid);
-            match crate::ipc::syscalls::create_channel(target_pid) {
-                Ok(channel_id) => {
-                    crate::println!("IPC: Channel {} created successfully", channel_id);
-                    channel_id as i64
-                }
-                Err(_) => {
-                    crate::println!("IPC: Channel creation failed");
-                    -1i64 // エラー
-                }
-            }
-        }
-        3 => {
-            // sys_send_message: メッセージ送信
-            // 引数: RDI=channel_id, RSI=msg_type, RDX=data_ptr, R10=data_len
-            let channel_id = args.arg1;
-            let msg_type = args.arg2 as u32;
-            let data_ptr = args.arg3;
-            let data_len = args.arg4;
-            
-            // セキュリティ：引数の検証
-            if channel_id == 0 || channel_id > 1000 {
-                crate::println!("SECURITY: Invalid channel ID for send_message: {}", channel_id);
-                return -1i64 as u64;
-            }
-            
-            if data_len > 256 { // IPCメッセージの最大サイズ
-                crate::println!("SECURITY: Message too large: {}", data_len);
-                return -1i64 as u64;
-            }
-            
-            // セキュリティ：データポインタの検証
-            if data_ptr != 0 {
-                if let Err(err) = validate_user_pointer(data_ptr, data_len as usize) {
-                    crate::println!("SECURITY: Invalid data pointer in send_message: {:?}", err);
-                    return -1i64 as u64;
-                }
-            }
-            
-            let data_slice = unsafe { core::slice::from_raw_parts(data_ptr as *const u8, data_len as usize) };
-            
-            crate::println!("IPC: Process {} sending message to channel {}, type {}, len {}", current_pid, channel_id, msg_type, data_len);
-            match crate::ipc::syscalls::send_message(channel_id, msg_type, data_slice) {
-                Ok(_) => {
-                    crate::println!("IPC: Message sent successfully");
-                    0i64 // 成功
-                }
-                Err(_) => {
-                    crate::println!("IPC: Message send failed");
-                    -1i64 // エラー
-                }
-            }
-        }
-        4 => {
-            // sys_receive_message: メッセージ受信
-            // 引数: RDI=channel_id, RSI=buffer_ptr, RDX=buffer_size
-            // 戻り値: 受信したメッセージのサイズ、または-1（エラー）、または-2（メッセージなし）
-            let channel_id = args.arg1;
-            let buffer_ptr = args.arg2;
-            let buffer_size = args.arg3;
-            
-            // セキュリティ：引数の検証
-            if channel_id == 0 || channel_id > 1000 {
-                crate::println!("SECURITY: Invalid channel ID for receive_message: {}", channel_id);
-                return -1i64 as u64;
-            }
-            
-            if buffer_size > 256 { // IPCメッセージの最大サイズ
-                crate::println!("SECURITY: Buffer too large: {}", buffer_size);
-                return -1i64 as u64;
-            }
-            
-            // セキュリティ：バッファポインタの検証
-            if buffer_ptr != 0 {
-                if let Err(err) = validate_user_pointer(buffer_ptr, buffer_size as usize) {
-                    crate::println!("SECURITY: Invalid buffer pointer in receive_message: {:?}", err);
-                    return -1i64 as u64;
-                }
-            }
-            
-            crate::println!("IPC: Process {} receiving message from channel {}", current_pid, channel_id);
-            match crate::ipc::syscalls::receive_message(channel_id) {
-                Ok(Some(message)) => {
-                    // メッセージを受信したらバッファにコピー
-                    let copy_len = core::cmp::min(message.data_len, buffer_size as usize);
-                    unsafe {
-                        core::ptr::copy_nonoverlapping(
-                            message.data.as_ptr(),
-                            buffer_ptr as *mut u8,
-                            copy_len
-                        );
-                    }
-                    crate::println!("IPC: Message received, len {}", copy_len);
-                    copy_len as i64 // コピーしたバイト数を返す
-                }
-                Ok(None) => {
-                    crate::println!("IPC: No message available");
-                    -2i64 // メッセージなし
-                }
-                Err(_) => {
-                    crate::println!("IPC: Message receive failed");
-                    -1i64 // エラー
-                }
-            }
-        }
-        5 => {
-            // sys_create_memory_handle: メモリハンドル作成
-            // 引数: RDI=start_addr, RSI=size, RDX=rights, R10=mode
-            let start_addr = x86_64::VirtAddr::new(args.arg1);
-            let size = args.arg2 as usize;
-            let rights = match args.arg3 {
-                0 => crate::ipc::AccessRights::ReadOnly,
-                1 => crate::ipc::AccessRights::ReadWrite,
-                2 => crate::ipc::AccessRights::Execute,
-                _ => {
-                    crate::println!("SECURITY: Invalid access rights: {}", args.arg3);
-                    return -1i64 as u64;
-                }
-            };
-            let mode = match args.arg4 {
-                0 => crate::ipc::TransferMode::Ownership,
-                1 => crate::ipc::TransferMode::Shared,
-                2 => crate::ipc::TransferMode::Exclusive,
-                _ => {
-                    crate::println!("SECURITY: Invalid transfer mode: {}", args.arg4);
-                    return -1i64 as u64;
-                }
-            };
-            
-            // セキュリティ：アドレス範囲の検証
-            if let Err(err) = validate_user_pointer(args.arg1, size) {
-                crate::println!("SECURITY: Invalid memory range in create_memory_handle: {:?}", err);
-                return -1i64 as u64;
-            }
-            
-            crate::println!("MEMORY IPC: Process {} creating handle for addr {:#x}, size {}", current_pid, start_addr, size);
-            match crate::ipc::syscalls::create_memory_handle(start_addr, size, rights, mode) {
-                Ok(handle_id) => {
-                    crate::println!("MEMORY IPC: Handle {} created successfully", handle_id);
-                    handle_id as i64
-                }
-                Err(_) => {
-                    crate::println!("MEMORY IPC: Handle creation failed");
-                    -1i64 // エラー
-                }
-            }
-        }
-        6 => {
-            // sys_transfer_memory: メモリハンドル転送
-            // 引数: RDI=handle_id, RSI=target_pid
-            let handle_id = args.arg1;
-            let target_pid = args.arg2;
-            
-            // セキュリティ：引数の検証
-            if handle_id == 0 {
-                crate::println!("SECURITY: Invalid handle ID for transfer_memory: {}", handle_id);
-                return -1i64 as u64;
-            }
-            
-            if target_pid == 0 || target_pid > 10000 {
-                crate::println!("SECURITY: Invalid target PID for transfer_memory: {}", target_pid);
-                return -1i64 as u64;
-            }
-            
-            crate::println!("MEMORY IPC: Process {} transferring handle {} to PID {}", current_pid, handle_id, target_pid);
-            match crate::ipc::syscalls::transfer_memory(handle_id, target_pid) {
-                Ok(()) => {
-                    crate::println!("MEMORY IPC: Handle {} transferred successfully", handle_id);
-                    0i64 // 成功
-                }
-                Err(_) => {
-                    crate::println!("MEMORY IPC: Handle {} transfer failed", handle_id);
-                    -1i64 // エラー
-                }
-            }
-        }
-        7 => {
-            // sys_receive_memory_handle: メモリハンドル受信
-            // 引数: RDI=handle_id
-            let handle_id = args.arg1;
-            
-            // セキュリティ：引数の検証
-            if handle_id == 0 {
-                crate::println!("SECURITY: Invalid handle ID for receive_memory_handle: {}", handle_id);
-                return -1i64 as u64;
-            }
-            
-            crate::println!("MEMORY IPC: Process {} receiving handle {}", current_pid, handle_id);
-            match crate::ipc::syscalls::receive_memory_handle(handle_id) {
-                Ok(range) => {
-                    crate::println!("MEMORY IPC: Handle {} received successfully, range {:#x}-{:#x}", 
-                        handle_id, range.start_addr, range.start_addr + range.size);
-                    0i64 // 成功
-                }
-                Err(_) => {
-                    crate::println!("MEMORY IPC: Handle {} receive failed", handle_id);
-                    -1i64 // エラー
-                }
-            }
+
+    let frame = unsafe { &mut *(frame_ptr as *mut SyscallFrame) };
+    let restarting = result == Errno::ERESTARTSYS.as_syscall_return();
+    if result == Errno::EJUSTRETURN.as_syscall_return() {
+        // rt_sigreturnがフレームを丸ごと復元した
+    } else if restarting {
+        // ブロックしたシステムコールは、起こされたときに最初からやり直す
+        frame.restart_syscall();
+    } else {
+        // 結果をu64として返す（負の値は符号拡張される）
+        frame.set_return_value(result as u64);
+    }
+
+    // 割り込みを止めているあいだはティックで課金されないので、ここで課金する
+    let elapsed = crate::timer::tsc_to_nanos(crate::timer::read_tsc().wrapping_sub(entry_tsc));
+    {
+        let mut sched = crate::process::scheduler::SCHEDULER.lock();
+        sched.charge_current_syscall(elapsed);
+        // ハンドラを実行するシグナルで起こされたら、SA_RESTARTがなければEINTRにする
+        if let Some(process) = sched.current_process_mut() {
+            process.syscall_restart = restarting;
         }
-        _ => {
-            crate::println!("Unknown syscall {} from PID {}", args.syscall_number, current_pid);
-            -1i64 // エラー
+    }
+
+    crate::process::scheduler::reschedule_after_syscall(frame_ptr)
+}
+
+/// このモジュールが提供するシステムコールを登録
+pub fn register_syscalls(table: &mut SyscallTable) -> KernelResult<()> {
+    table.register(SyscallEntry::new(SYS_WRITE, "write", sys_write,
+        &[ArgKind::Fd, ArgKind::UserPtr, ArgKind::Size]))?;
+    Ok(())
+}
+
+// sys_write: デバッグ出力
+// 引数: RDI=fd, RSI=buf*, RDX=count
+fn sys_write(args: &SyscallArgs) -> SyscallResult {
+    let fd = args.arg1;
+    let buf = args.arg2;
+    let count = args.arg3;
+
+    // セキュリティ：引数の検証
+    if fd > 2 { // stdin(0), stdout(1), stderr(2) のみ許可
+        crate::println!("SECURITY: Invalid file descriptor: {}", fd);
+        return Err(Errno::EBADF);
+    }
+
+    if count > 4096 { // 4KB制限
+        crate::println!("SECURITY: Write count too large: {}", count);
+        return Err(Errno::EINVAL);
+    }
+
+    if fd == 1 { // stdout
+        // セキュリティ：ページテーブルを確認しながらカーネル側にコピー
+        let mut data = alloc::vec![0u8; count as usize];
+        if let Err(errno) = uaccess::copy_from_user(&mut data, buf) {
+            crate::println!("SECURITY: Invalid user pointer in write: {:#x}", buf);
+            return Err(errno);
         }
-    };
 
-    // 結果をu64として返す（負の値は符号拡張される）
-    result as u64
-}
\ No newline at end of file
+        if let Ok(s) = core::str::from_utf8(&data) {
+            crate::print!("{}", s);
+            Ok(count) // 書き込んだバイト数を返す
+        } else {
+            Err(Errno::EINVAL) // UTF-8でない
+        }
+    } else {
+        Err(Errno::EBADF) // stdoutのみ書き込み可能
+    }
+}
@@ -0,0 +1,301 @@
+//! システムコールテーブル
+//!
+//! 各サブシステムは自分のシステムコールを`SyscallEntry`として登録します。
+//! 番号の検証、引数の検証、呼び出し統計、トレースはすべてこのテーブルを
+//! 基準に行われるため、ディスパッチャ側に番号のリストを重複して持つ必要はありません。
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use super::SyscallArgs;
+use crate::error::{Errno, KernelResult, SyscallError};
+use crate::kerror;
+use crate::memory::uaccess;
+
+/// システムコールハンドラの戻り値
+///
+/// `Ok`の値はそのままユーザーのRAXに、`Err`は`-errno`としてRAXに返される。
+pub type SyscallResult = Result<u64, Errno>;
+
+/// システムコールハンドラの型
+pub type SyscallHandler = fn(&SyscallArgs) -> SyscallResult;
+
+/// PIDとして受け付ける最大値
+pub const MAX_PID: u64 = 10000;
+/// プロセスが持てるチャンネルの端点のケイパビリティの数
+pub const MAX_CAPABILITIES: u64 = 256;
+
+/// システムコール引数の種類
+///
+/// ディスパッチャはハンドラを呼ぶ前に、この種類に応じた共通の検証を行う。
+/// 呼び出し固有の検証（サイズ上限など）はハンドラ側で行う。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// 検証しない任意の整数値
+    Value,
+    /// プロセスID (1..=MAX_PID)
+    Pid,
+    /// プロセスID、または-1（任意のプロセス）
+    PidOrAny,
+    /// ファイルディスクリプタ
+    Fd,
+    /// ユーザー空間へのポインタ（NULL許可、実際のアクセス可否はコピー時に検証）
+    UserPtr,
+    /// バイト数
+    Size,
+    /// フラグ・列挙値
+    Flags,
+    /// チャンネルの端点のケイパビリティ（スロット番号、0..MAX_CAPABILITIES）
+    Capability,
+    /// メモリハンドルID (0以外)
+    HandleId,
+}
+
+impl ArgKind {
+    /// 引数の値がこの種類として妥当か検証する
+    pub fn validate(self, value: u64) -> bool {
+        match self {
+            ArgKind::Value | ArgKind::Fd | ArgKind::Size | ArgKind::Flags => true,
+            ArgKind::Pid => value != 0 && value <= MAX_PID,
+            ArgKind::PidOrAny => value == u64::MAX || (value != 0 && value <= MAX_PID),
+            ArgKind::UserPtr => value == 0 || uaccess::check_user_range(value, 0).is_ok(),
+            ArgKind::Capability => value < MAX_CAPABILITIES,
+            ArgKind::HandleId => value != 0,
+        }
+    }
+
+    /// 検証に失敗したときに返すエラー番号
+    pub fn errno(self) -> Errno {
+        match self {
+            ArgKind::Pid | ArgKind::PidOrAny => Errno::ESRCH,
+            ArgKind::Fd | ArgKind::Capability | ArgKind::HandleId => Errno::EBADF,
+            ArgKind::UserPtr => Errno::EFAULT,
+            ArgKind::Value | ArgKind::Size | ArgKind::Flags => Errno::EINVAL,
+        }
+    }
+}
+
+/// システムコールごとの呼び出し統計
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyscallStats {
+    /// 呼び出し回数
+    pub calls: u64,
+    /// エラーを返した回数（引数検証の失敗を含む）
+    pub errors: u64,
+}
+
+/// システムコールテーブルの1エントリ
+#[derive(Debug, Clone)]
+pub struct SyscallEntry {
+    /// システムコール番号
+    pub number: u64,
+    /// トレース表示用の名前
+    pub name: &'static str,
+    /// ハンドラ関数
+    pub handler: SyscallHandler,
+    /// 引数の個数
+    pub arg_count: usize,
+    /// 各引数の種類（先頭から`arg_count`個）
+    pub arg_kinds: &'static [ArgKind],
+    /// 呼び出し統計
+    pub stats: SyscallStats,
+}
+
+impl SyscallEntry {
+    pub fn new(number: u64, name: &'static str, handler: SyscallHandler, arg_kinds: &'static [ArgKind]) -> Self {
+        Self {
+            number,
+            name,
+            handler,
+            arg_count: arg_kinds.len(),
+            arg_kinds,
+            stats: SyscallStats::default(),
+        }
+    }
+
+    /// 引数をこのエントリの種類に従って検証し、失敗した引数の位置を返す
+    fn validate_args(&self, args: &SyscallArgs) -> Result<(), usize> {
+        let values = args.as_array();
+        for (index, kind) in self.arg_kinds.iter().enumerate().take(self.arg_count) {
+            if !kind.validate(values[index]) {
+                return Err(index);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 登録済みシステムコールの一覧
+pub struct SyscallTable {
+    entries: BTreeMap<u64, SyscallEntry>,
+}
+
+impl SyscallTable {
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// システムコールを登録する。同じ番号が既に登録されている場合はエラー
+    pub fn register(&mut self, entry: SyscallEntry) -> KernelResult<()> {
+        if entry.arg_count > 6 || entry.arg_count != entry.arg_kinds.len() {
+            return kerror!(SyscallError::InvalidArgs);
+        }
+        if self.entries.contains_key(&entry.number) {
+            return kerror!(SyscallError::AlreadyRegistered);
+        }
+        self.entries.insert(entry.number, entry);
+        Ok(())
+    }
+
+    /// 番号でエントリを取得
+    pub fn get(&self, number: u64) -> Option<&SyscallEntry> {
+        self.entries.get(&number)
+    }
+
+    /// 番号が登録されているか
+    pub fn contains(&self, number: u64) -> bool {
+        self.entries.contains_key(&number)
+    }
+
+    /// 全エントリを番号順に取得
+    pub fn entries(&self) -> impl Iterator<Item = &SyscallEntry> {
+        self.entries.values()
+    }
+}
+
+impl Default for SyscallTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+lazy_static! {
+    pub static ref SYSCALL_TABLE: Mutex<SyscallTable> = Mutex::new(SyscallTable::new());
+}
+
+static TABLE_INIT: AtomicBool = AtomicBool::new(false);
+static TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 各サブシステムのシステムコールを登録する（一度だけ実行される）
+///
+/// テーブルはヒープに確保するので、`allocator::init_heap`の後に呼ぶ。
+pub fn init() {
+    if TABLE_INIT.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        return; // Already initialized
+    }
+
+    let mut table = SYSCALL_TABLE.lock();
+    let results = [
+        super::register_syscalls(&mut table),
+        crate::process::syscalls::register_syscalls(&mut table),
+        crate::process::futex::register_syscalls(&mut table),
+        crate::memory::syscalls::register_syscalls(&mut table),
+        crate::ipc::syscalls::register_syscalls(&mut table),
+        crate::timer::syscalls::register_syscalls(&mut table),
+    ];
+    for result in results.iter() {
+        if let Err(e) = result {
+            crate::error::log_error(e);
+        }
+    }
+
+    crate::println!("Syscall table initialized with {} entries", table.entries.len());
+}
+
+/// 追加のシステムコールを登録する
+pub fn register_syscall(entry: SyscallEntry) -> KernelResult<()> {
+    SYSCALL_TABLE.lock().register(entry)
+}
+
+/// システムコール番号が登録済みか検証
+pub fn is_registered(number: u64) -> bool {
+    SYSCALL_TABLE.lock().contains(number)
+}
+
+/// トレースの有効・無効を切り替える
+pub fn set_trace(enabled: bool) {
+    TRACE_ENABLED.store(enabled, Ordering::Release);
+}
+
+/// トレースが有効か
+pub fn trace_enabled() -> bool {
+    TRACE_ENABLED.load(Ordering::Acquire)
+}
+
+/// 全システムコールの統計を取得 (番号, 名前, 統計)
+pub fn syscall_stats() -> Vec<(u64, &'static str, SyscallStats)> {
+    SYSCALL_TABLE.lock()
+        .entries()
+        .map(|e| (e.number, e.name, e.stats))
+        .collect()
+}
+
+/// テーブルを引いてシステムコールを実行し、RAXに返す値を得る
+pub fn dispatch(args: &SyscallArgs) -> i64 {
+    let current_pid = super::get_current_process_id();
+
+    // テーブルのロックはハンドラ呼び出し前に解放する
+    let (name, handler) = {
+        let mut table = SYSCALL_TABLE.lock();
+        let entry = match table.entries.get_mut(&args.syscall_number) {
+            Some(entry) => entry,
+            None => {
+                crate::println!("Unknown syscall {} from PID {}", args.syscall_number, current_pid);
+                return Errno::ENOSYS.as_syscall_return();
+            }
+        };
+        entry.stats.calls += 1;
+
+        if let Err(index) = entry.validate_args(args) {
+            entry.stats.errors += 1;
+            crate::println!("SECURITY: Invalid argument {} ({:?}) for {}: {:#x}",
+                index + 1, entry.arg_kinds[index], entry.name, args.as_array()[index]);
+            return entry.arg_kinds[index].errno().as_syscall_return();
+        }
+
+        if trace_enabled() {
+            trace_call(entry, args, current_pid);
+        }
+
+        (entry.name, entry.handler)
+    };
+
+    let result = handler(args);
+
+    if result.is_err() && let Some(entry) = SYSCALL_TABLE.lock().entries.get_mut(&args.syscall_number) {
+        entry.stats.errors += 1;
+    }
+    if trace_enabled() {
+        match result {
+            Ok(value) => crate::println!("TRACE: PID {} {} = {}", current_pid, name, value),
+            Err(errno) => crate::println!("TRACE: PID {} {} = -{}", current_pid, name, errno),
+        }
+    }
+
+    match result {
+        Ok(value) => value as i64,
+        Err(errno) => errno.as_syscall_return(),
+    }
+}
+
+// 引数の種類に合わせてトレースを出力する
+fn trace_call(entry: &SyscallEntry, args: &SyscallArgs, pid: u64) {
+    let values = args.as_array();
+    crate::print!("TRACE: PID {} {}(", pid, entry.name);
+    for (index, kind) in entry.arg_kinds.iter().enumerate() {
+        if index > 0 {
+            crate::print!(", ");
+        }
+        match kind {
+            ArgKind::UserPtr | ArgKind::Flags => crate::print!("{:#x}", values[index]),
+            ArgKind::PidOrAny if values[index] == u64::MAX => crate::print!("-1"),
+            _ => crate::print!("{}", values[index]),
+        }
+    }
+    crate::println!(")");
+}
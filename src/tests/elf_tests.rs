@@ -0,0 +1,228 @@
+//! ELF Loader Tests
+//!
+//! This module tests ELF64 header validation, loading into a new address space
+//! and the initial stack built for execve.
+
+use crate::testing::{TestResult, TestError};
+use crate::process::elf::{self, ElfImage};
+use crate::process::exec;
+use crate::memory::paging;
+use crate::memory::vma::VmaKind;
+use crate::error::{Errno, KernelError, ProcessError};
+use alloc::string::ToString;
+use alloc::format;
+use alloc::vec::Vec;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+const LOAD_ADDRESS: u64 = 0x400000;
+const CODE: [u8; 4] = [0x90, 0x90, 0xEB, 0xFE]; // nop; nop; jmp $
+/// Real user program built by build.rs from src/tests/syscall_test.rs
+const SYSCALL_TEST_IMAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/syscall_test"));
+
+/// Build an ELF executable with a single loadable, executable PT_LOAD segment
+///
+/// The loader tests patch its headers to produce malformed images.
+pub fn build_executable(load_address: u64, code: &[u8]) -> Vec<u8> {
+    const ELF_HEADER_SIZE: usize = 64;
+    let code_offset = (ELF_HEADER_SIZE + elf::PROGRAM_HEADER_SIZE) as u64;
+    let mut image = Vec::with_capacity(code_offset as usize + code.len());
+
+    // ELF header
+    image.extend_from_slice(&[0x7F, b'E', b'L', b'F']);
+    image.extend_from_slice(&[2, 1, 1, 0]);                            // ELFCLASS64, ELFDATA2LSB, EV_CURRENT
+    image.extend_from_slice(&[0u8; 8]);
+    image.extend_from_slice(&2u16.to_le_bytes());                     // e_type = ET_EXEC
+    image.extend_from_slice(&0x3Eu16.to_le_bytes());                  // e_machine = EM_X86_64
+    image.extend_from_slice(&1u32.to_le_bytes());                     // e_version
+    image.extend_from_slice(&(load_address + code_offset).to_le_bytes()); // e_entry
+    image.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // e_phoff
+    image.extend_from_slice(&0u64.to_le_bytes());                     // e_shoff
+    image.extend_from_slice(&0u32.to_le_bytes());                     // e_flags
+    image.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes()); // e_ehsize
+    image.extend_from_slice(&(elf::PROGRAM_HEADER_SIZE as u16).to_le_bytes()); // e_phentsize
+    image.extend_from_slice(&1u16.to_le_bytes());                     // e_phnum
+    image.extend_from_slice(&[0u8; 6]);                               // e_shentsize, e_shnum, e_shstrndx
+
+    // Program header: load the whole file at `load_address`
+    let file_size = code_offset + code.len() as u64;
+    image.extend_from_slice(&elf::PT_LOAD.to_le_bytes());
+    image.extend_from_slice(&(elf::PF_R | elf::PF_X).to_le_bytes());
+    image.extend_from_slice(&0u64.to_le_bytes());                     // p_offset
+    image.extend_from_slice(&load_address.to_le_bytes());             // p_vaddr
+    image.extend_from_slice(&load_address.to_le_bytes());             // p_paddr
+    image.extend_from_slice(&file_size.to_le_bytes());                // p_filesz
+    image.extend_from_slice(&file_size.to_le_bytes());                // p_memsz
+    image.extend_from_slice(&4096u64.to_le_bytes());                  // p_align
+
+    image.extend_from_slice(code);
+    image
+}
+
+/// Test parsing a well-formed executable
+pub fn test_parse_valid_image() -> TestResult {
+    let image = build_executable(LOAD_ADDRESS, &CODE);
+    let parsed = ElfImage::parse(&image)?;
+
+    crate::assert_eq!(parsed.segments().len(), 1);
+    crate::assert_eq!(parsed.segments()[0].vaddr, LOAD_ADDRESS);
+    crate::assert_true!(parsed.entry() > LOAD_ADDRESS);
+    crate::assert_eq!(parsed.image_end(), LOAD_ADDRESS + 0x1000);
+
+    Ok(())
+}
+
+/// Test that the compiled user program is accepted and loaded W^X
+pub fn test_load_compiled_program() -> TestResult {
+    let parsed = ElfImage::parse(SYSCALL_TEST_IMAGE)?;
+    crate::assert_true!(parsed.segments().len() > 1);
+    for segment in parsed.segments() {
+        crate::assert_true!(segment.vaddr >= LOAD_ADDRESS);
+        crate::assert_false!(segment.flags & elf::PF_W != 0 && segment.flags & elf::PF_X != 0);
+    }
+
+    let loaded = elf::load(SYSCALL_TEST_IMAGE)?;
+    let l4 = loaded.address_space.page_table_frame();
+    let entry_page = Page::containing_address(VirtAddr::new(loaded.entry));
+    crate::assert_true!(paging::user_page_flags(l4, entry_page)
+        .is_some_and(|f| !f.contains(PageTableFlags::NO_EXECUTE) && !f.contains(PageTableFlags::WRITABLE)));
+    crate::assert_true!(loaded.phdr != 0);
+
+    Ok(())
+}
+
+/// Test that malformed headers are rejected with ENOEXEC
+pub fn test_reject_malformed_images() -> TestResult {
+    let image = build_executable(LOAD_ADDRESS, &CODE);
+    let invalid = KernelError::Process(ProcessError::InvalidExecutable);
+
+    // Truncated header
+    crate::assert_eq!(ElfImage::parse(&image[..32]).err(), Some(invalid.clone()));
+
+    // Bad magic
+    let mut bad = image.clone();
+    bad[0] = 0;
+    crate::assert_eq!(ElfImage::parse(&bad).err(), Some(invalid.clone()));
+
+    // Wrong machine (EM_386)
+    let mut bad = image.clone();
+    bad[18] = 3;
+    crate::assert_eq!(ElfImage::parse(&bad).err(), Some(invalid.clone()));
+
+    // Segment extends past the end of the file (p_filesz)
+    let mut bad = image.clone();
+    bad[64 + 32..64 + 40].copy_from_slice(&0x10000u64.to_le_bytes());
+    bad[64 + 40..64 + 48].copy_from_slice(&0x10000u64.to_le_bytes());
+    crate::assert_eq!(ElfImage::parse(&bad).err(), Some(invalid.clone()));
+
+    // Entry point outside every executable segment
+    let mut bad = image.clone();
+    bad[24..32].copy_from_slice(&0x900000u64.to_le_bytes());
+    crate::assert_eq!(ElfImage::parse(&bad).err(), Some(invalid.clone()));
+
+    // Segments may not reach into kernel space
+    let mut bad = image.clone();
+    bad[64 + 16..64 + 24].copy_from_slice(&0xFFFF_8000_0000_0000u64.to_le_bytes());
+    crate::assert_eq!(ElfImage::parse(&bad).err(), Some(invalid.clone()));
+
+    crate::assert_eq!(Errno::from(invalid), Errno::ENOEXEC);
+
+    Ok(())
+}
+
+/// Test that segments and the user stack are mapped with the right permissions
+pub fn test_load_maps_segments() -> TestResult {
+    let image = build_executable(LOAD_ADDRESS, &CODE);
+    let loaded = elf::load(&image)?;
+    let l4 = loaded.address_space.page_table_frame();
+
+    // Code page: present, user, read-only, executable, and holds the file contents
+    let code_page = Page::containing_address(VirtAddr::new(LOAD_ADDRESS));
+    let flags = match paging::user_page_flags(l4, code_page) {
+        Some(flags) => flags,
+        None => return Err(TestError::AssertionFailed("code page not mapped".to_string())),
+    };
+    crate::assert_false!(flags.contains(PageTableFlags::WRITABLE));
+    crate::assert_false!(flags.contains(PageTableFlags::NO_EXECUTE));
+
+    let frame = paging::user_page_frame(l4, code_page)
+        .ok_or_else(|| TestError::AssertionFailed("code page not mapped".into()))?;
+    let entry_offset = (loaded.entry - LOAD_ADDRESS) as usize;
+    let loaded_code = unsafe { core::slice::from_raw_parts(paging::frame_ptr(frame).add(entry_offset), CODE.len()) };
+    crate::assert_eq!(loaded_code, &CODE[..]);
+
+    // Stack: writable and not executable
+    let stack_page = Page::containing_address(VirtAddr::new(loaded.stack_top - 1));
+    let flags = paging::user_page_flags(l4, stack_page);
+    crate::assert_true!(flags.is_some_and(|f| f.contains(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)));
+
+    // The image and the whole stack reservation are recorded as VMAs
+    let space = &loaded.address_space;
+    crate::assert_eq!(space.find_vma(LOAD_ADDRESS).map(|vma| vma.kind), Some(VmaKind::Image));
+    crate::assert_eq!(space.find_vma(elf::USER_STACK_LOWEST).map(|vma| vma.kind), Some(VmaKind::Stack));
+    crate::assert_true!(space.find_vma(elf::USER_STACK_LOWEST - 1).is_none());
+
+    // The heap starts right after the image
+    crate::assert_eq!(space.program_break().start, loaded.image_end);
+
+    Ok(())
+}
+
+// Read a u64 from user memory in another address space
+fn read_user_u64(l4: x86_64::structures::paging::PhysFrame, addr: u64) -> Option<u64> {
+    let frame = paging::user_page_frame(l4, Page::containing_address(VirtAddr::new(addr)))?;
+    let ptr = unsafe { paging::frame_ptr(frame).add((addr % 4096) as usize) as *const u64 };
+    Some(unsafe { ptr.read_unaligned() })
+}
+
+/// Test the System V initial stack built for execve
+pub fn test_initial_stack_layout() -> TestResult {
+    let image = build_executable(LOAD_ADDRESS, &CODE);
+    let argv = [b"/bin/test\0".to_vec(), b"-v\0".to_vec()];
+    let envp = [b"HOME=/\0".to_vec()];
+    let (loaded, sp) = exec::prepare_image(&image, &argv, &envp)?;
+    let l4 = loaded.address_space.page_table_frame();
+    let word = |index: u64| read_user_u64(l4, sp + index * 8).unwrap_or(u64::MAX);
+
+    crate::assert_eq!(sp % 16, 0);
+    crate::assert_true!(sp < loaded.stack_top);
+
+    // argc, argv[0..2], NULL, envp[0], NULL
+    crate::assert_eq!(word(0), 2);
+    crate::assert_eq!(word(3), 0);
+    crate::assert_eq!(word(5), 0);
+    let arg0 = word(1);
+    let arg0_frame = paging::user_page_frame(l4, Page::containing_address(VirtAddr::new(arg0)))
+        .ok_or_else(|| TestError::AssertionFailed("argv[0] not mapped".into()))?;
+    let arg0_bytes = unsafe { core::slice::from_raw_parts(paging::frame_ptr(arg0_frame).add((arg0 % 4096) as usize), argv[0].len()) };
+    crate::assert_eq!(arg0_bytes, &argv[0][..]);
+
+    // Auxiliary vector follows envp and ends with AT_NULL
+    let mut index = 6;
+    let mut entry = None;
+    while word(index) != exec::AT_NULL {
+        if word(index) == exec::AT_ENTRY {
+            entry = Some(word(index + 1));
+        }
+        index += 2;
+        if index > 64 {
+            break;
+        }
+    }
+    crate::assert_eq!(word(index), exec::AT_NULL);
+    crate::assert_eq!(entry, Some(loaded.entry));
+
+    Ok(())
+}
+
+/// Test the program registry used by execve
+pub fn test_program_registry() -> TestResult {
+    let image = build_executable(LOAD_ADDRESS, &CODE);
+    exec::register_program("/test/registry", image.clone());
+
+    let found = exec::find_program("/test/registry");
+    crate::assert_true!(found.is_some_and(|found| *found == image));
+    crate::assert_true!(exec::find_program("/test/missing").is_none());
+
+    Ok(())
+}
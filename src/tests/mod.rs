@@ -12,7 +12,9 @@ use alloc::format;
 use alloc::vec;
 use x86_64::VirtAddr;
 
+pub mod elf_tests;
 pub mod ipc_tests;
+pub mod syscall_tests;
 
 /// Create all test suites
 pub fn create_all_test_suites() -> Vec<TestSuite> {
@@ -22,6 +24,8 @@ pub fn create_all_test_suites() -> Vec<TestSuite> {
         create_error_tests(),
         create_integration_tests(),
         create_ipc_tests(),
+        create_syscall_tests(),
+        create_elf_tests(),
     ]
 }
 
@@ -32,6 +36,11 @@ fn create_memory_tests() -> TestSuite {
         .add_test(TestCase::new("allocation_with_flags", "Test allocation with different flags", TestCategory::Unit, test_allocation_with_flags))
         .add_test(TestCase::new("memory_statistics", "Test memory statistics tracking", TestCategory::Unit, test_memory_statistics))
         .add_test(TestCase::new("page_mapping", "Test page mapping and unmapping", TestCategory::Integration, test_page_mapping))
+        .add_test(TestCase::new("user_copy_rejects_bad_pointers", "Test copy_from_user/copy_to_user return EFAULT", TestCategory::Unit, test_user_copy_rejects_bad_pointers))
+        .add_test(TestCase::new("address_space_clone", "Test fork-style address space duplication", TestCategory::Integration, test_address_space_clone))
+        .add_test(TestCase::new("frame_ref_counts", "Test per-frame reference counts for COW sharing", TestCategory::Unit, test_frame_ref_counts))
+        .add_test(TestCase::new("anonymous_mappings", "Test VMAs, demand paging and mmap/mprotect/munmap", TestCategory::Integration, test_anonymous_mappings))
+        .add_test(TestCase::new("program_break", "Test brk heap growth and shrinking", TestCategory::Integration, test_program_break))
 }
 
 /// CPU management tests
@@ -40,6 +49,7 @@ fn create_cpu_tests() -> TestSuite {
         .add_test(TestCase::new("cpu_data_access", "Test per-CPU data access", TestCategory::Unit, test_cpu_data_access))
         .add_test(TestCase::new("cpu_statistics", "Test CPU statistics tracking", TestCategory::Unit, test_cpu_statistics))
         .add_test(TestCase::new("interrupt_handling", "Test interrupt context handling", TestCategory::Integration, test_interrupt_handling))
+        .add_test(TestCase::new("fpu_enabled", "Test FPU/SSE setup for lazy state switching", TestCategory::Unit, test_fpu_enabled))
 }
 
 /// Error handling tests
@@ -134,6 +144,212 @@ fn test_page_mapping() -> TestResult {
     Ok(())
 }
 
+fn test_user_copy_rejects_bad_pointers() -> TestResult {
+    use crate::memory::uaccess;
+    use crate::error::Errno;
+
+    static KERNEL_DATA: [u8; 16] = [0xAA; 16];
+    let mut buf = [0u8; 16];
+
+    // NULL, unmapped user memory, kernel memory and non-user addresses must all fail
+    crate::assert_eq!(uaccess::copy_from_user(&mut buf, 0), Err(Errno::EFAULT));
+    crate::assert_eq!(uaccess::copy_from_user(&mut buf, 0x7000_0000), Err(Errno::EFAULT));
+    crate::assert_eq!(uaccess::copy_from_user(&mut buf, KERNEL_DATA.as_ptr() as u64), Err(Errno::EFAULT));
+    crate::assert_eq!(uaccess::copy_to_user(0xFFFF_8000_0000_0000, &buf), Err(Errno::EFAULT));
+    crate::assert_eq!(uaccess::copy_to_user(u64::MAX - 4, &buf), Err(Errno::EFAULT));
+    crate::assert_eq!(uaccess::strncpy_from_user(&mut buf, 0x7000_0000), Err(Errno::EFAULT));
+
+    // Zero-length copies never touch user memory
+    crate::assert_eq!(uaccess::copy_to_user(0, &[]), Ok(()));
+
+    Ok(())
+}
+
+fn test_address_space_clone() -> TestResult {
+    use crate::memory::{self, paging};
+    use crate::memory::mmap::{MAP_PRIVATE, PROT_READ, PROT_WRITE};
+    use crate::memory::vma::{Access, AddressSpace};
+    use x86_64::structures::paging::{Page, PageTableFlags};
+
+    // Build a private address space with one touched user page; cloning the
+    // live CR3 table would mark the running kernel's user pages COW.
+    let space = AddressSpace::new()?;
+    let addr = space.map_anonymous(0, 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE)
+        .map_err(|e| TestError::AssertionFailed(format!("mmap failed: {:?}", e)))?;
+    crate::assert_true!(space.handle_fault(VirtAddr::new(addr), Access::Write));
+    let page = Page::containing_address(VirtAddr::new(addr));
+    let frame = match paging::user_page_frame(space.page_table_frame(), page) {
+        Some(frame) => frame,
+        None => return Err(TestError::AssertionFailed("page not mapped".to_string())),
+    };
+
+    let source = space.page_table_frame();
+    let clone = paging::clone_address_space(source, &[])?;
+    crate::assert_true!(clone != source);
+
+    let src = unsafe { paging::table_at(source.start_address()) };
+    let dst = unsafe { paging::table_at(clone.start_address()) };
+    for (src_entry, dst_entry) in src.iter().zip(dst.iter()) {
+        crate::assert_eq!(src_entry.flags(), dst_entry.flags());
+        if src_entry.flags().contains(PageTableFlags::USER_ACCESSIBLE) {
+            // User-bearing tables are private to the clone
+            crate::assert_true!(src_entry.addr() != dst_entry.addr());
+        } else {
+            // Kernel-only mappings are shared
+            crate::assert_eq!(src_entry.addr(), dst_entry.addr());
+        }
+    }
+
+    // The user page is shared copy-on-write by both tables
+    crate::assert_eq!(paging::user_page_frame(clone, page), Some(frame));
+    crate::assert_eq!(memory::frame_ref_count(frame), 2);
+    for l4 in [source, clone] {
+        crate::assert_true!(paging::user_page_flags(l4, page)
+            .is_some_and(|f| f.contains(paging::COW) && !f.contains(PageTableFlags::WRITABLE)));
+    }
+
+    paging::free_address_space(clone);
+    crate::assert_eq!(memory::frame_ref_count(frame), 1);
+
+    // Memory received through a handle stays writable and shared after fork
+    let shared_frame = match memory::allocate_frame() {
+        Some(frame) => frame,
+        None => return Err(TestError::ResourceUnavailable("no frames available".to_string())),
+    };
+    let shared_addr = addr + 0x10_0000;
+    crate::assert_ok!(space.map_frames(shared_addr, &[shared_frame], PROT_READ | PROT_WRITE));
+    crate::assert_eq!(memory::frame_ref_count(shared_frame), 2);
+    let child = space.fork()?;
+    let shared_page = Page::containing_address(VirtAddr::new(shared_addr));
+    crate::assert_eq!(paging::user_page_frame(child.page_table_frame(), shared_page), Some(shared_frame));
+    crate::assert_eq!(memory::frame_ref_count(shared_frame), 3);
+    for l4 in [source, child.page_table_frame()] {
+        crate::assert_true!(paging::user_page_flags(l4, shared_page)
+            .is_some_and(|f| f.contains(PageTableFlags::WRITABLE) && !f.contains(paging::COW)));
+    }
+    drop(child);
+    crate::assert_eq!(memory::release_frame(shared_frame), 1);
+
+    // Each process gets its own kernel stack with the context at the top
+    let stack = crate::process::KernelStack::new();
+    crate::assert_eq!(stack.top() % 16, 0);
+    crate::assert_eq!(stack.top() - stack.initial_context_ptr(), core::mem::size_of::<crate::process::ProcessContext>() as u64);
+
+    // Running out of heap for kernel stacks is an error, not a panic
+    let mut stacks = Vec::with_capacity(crate::allocator::HEAP_SIZE / crate::process::KERNEL_STACK_SIZE + 1);
+    let exhausted = loop {
+        if stacks.len() == stacks.capacity() {
+            break None;
+        }
+        match crate::process::KernelStack::try_new() {
+            Ok(stack) => stacks.push(stack),
+            Err(e) => break Some(e),
+        }
+    };
+    drop(stacks);
+    crate::assert_true!(matches!(exhausted, Some(crate::error::KernelError::Memory(crate::error::AllocError::OutOfMemory))));
+    crate::assert_ok!(crate::process::KernelStack::try_new());
+
+    Ok(())
+}
+
+fn test_frame_ref_counts() -> TestResult {
+    use crate::memory;
+
+    let frame = match memory::allocate_frame() {
+        Some(frame) => frame,
+        None => return Err(TestError::ResourceUnavailable("no frames available".to_string())),
+    };
+    crate::assert_eq!(memory::frame_ref_count(frame), 1);
+
+    // Sharing between parent and child
+    crate::assert_eq!(memory::share_frame(frame), 2);
+    crate::assert_eq!(memory::share_frame(frame), 3);
+    crate::assert_eq!(memory::frame_ref_count(frame), 3);
+
+    // Dropping references; the last one frees the frame
+    crate::assert_eq!(memory::release_frame(frame), 2);
+    crate::assert_eq!(memory::release_frame(frame), 1);
+    crate::assert_eq!(memory::frame_ref_count(frame), 1);
+    crate::assert_eq!(memory::release_frame(frame), 0);
+
+    Ok(())
+}
+
+fn test_anonymous_mappings() -> TestResult {
+    use crate::memory::mmap::{self, MAP_FIXED, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+    use crate::memory::paging;
+    use crate::memory::vma::{Access, AddressSpace};
+    use x86_64::structures::paging::{Page, PageTableFlags};
+
+    let space = AddressSpace::new()?;
+    let l4 = space.page_table_frame();
+    let flags_at = |addr: u64| paging::user_page_flags(l4, Page::containing_address(VirtAddr::new(addr)));
+
+    // Three pages at the default base; nothing is allocated until first touch
+    let start = space.map_anonymous(0, 3 * 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE)
+        .map_err(|e| TestError::AssertionFailed(format!("mmap failed: {:?}", e)))?;
+    crate::assert_eq!(start, mmap::MMAP_BASE);
+    crate::assert_eq!(space.total_size(), 3 * 4096);
+    crate::assert_true!(flags_at(start + 4096).is_none());
+
+    // Demand-zero fault inside the VMA, rejected outside it
+    crate::assert_true!(space.handle_fault(VirtAddr::new(start + 4096 + 8), Access::Write));
+    crate::assert_true!(flags_at(start + 4096).is_some_and(|f| f.contains(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE)));
+    crate::assert_false!(space.handle_fault(VirtAddr::new(start + 3 * 4096), Access::Read));
+    crate::assert_false!(space.handle_fault(VirtAddr::new(start), Access::Execute));
+
+    // Fixed mappings below the minimum address are rejected
+    crate::assert_err!(space.map_anonymous(0x1000, 4096, PROT_READ, MAP_PRIVATE | MAP_FIXED));
+
+    // mprotect splits the VMA and updates pages that are already present
+    crate::assert_ok!(space.protect(start + 4096, 4096, PROT_READ));
+    crate::assert_eq!(space.vmas().len(), 3);
+    crate::assert_true!(flags_at(start + 4096).is_some_and(|f| !f.contains(PageTableFlags::WRITABLE)));
+    crate::assert_false!(space.handle_fault(VirtAddr::new(start + 4096), Access::Write));
+    crate::assert_eq!(space.protect(start, 8 * 4096, PROT_READ), Err(crate::error::Errno::ENOMEM));
+
+    // munmap of the first page leaves the rest in place
+    crate::assert_eq!(space.unmap(start, 4096), 4096);
+    crate::assert_true!(space.find_vma(start).is_none());
+    crate::assert_true!(space.find_vma(start + 4096).is_some());
+    crate::assert_eq!(space.unmap(start, 16 * 4096), 2 * 4096);
+    crate::assert_eq!(space.total_size(), 0);
+    crate::assert_true!(flags_at(start + 4096).is_none());
+
+    Ok(())
+}
+
+fn test_program_break() -> TestResult {
+    use crate::error::Errno;
+    use crate::memory::mmap::{MAP_FIXED, MAP_PRIVATE, PROT_READ};
+    use crate::memory::vma::{Access, AddressSpace, VmaKind};
+
+    const HEAP_START: u64 = 0x600000;
+    let space = AddressSpace::new()?;
+    space.init_program_break(HEAP_START);
+    crate::assert_eq!(space.program_break().current, HEAP_START);
+    crate::assert_true!(space.find_vma(HEAP_START).is_none());
+
+    // Growing rounds the heap VMA up to whole pages
+    crate::assert_eq!(space.set_program_break(HEAP_START + 5000), Ok(HEAP_START + 5000));
+    crate::assert_eq!(space.find_vma(HEAP_START + 4096).map(|vma| (vma.kind, vma.end)), Some((VmaKind::Heap, HEAP_START + 2 * 4096)));
+    crate::assert_true!(space.handle_fault(VirtAddr::new(HEAP_START + 4096), Access::Write));
+
+    // Shrinking releases the pages above the new break
+    crate::assert_eq!(space.set_program_break(HEAP_START + 10), Ok(HEAP_START + 10));
+    crate::assert_true!(space.find_vma(HEAP_START + 4096).is_none());
+    crate::assert_eq!(space.total_size(), 4096);
+
+    // The break cannot move below its start or into another mapping
+    crate::assert_eq!(space.set_program_break(HEAP_START - 1), Err(Errno::ENOMEM));
+    crate::assert_ok!(space.map_anonymous(HEAP_START + 4 * 4096, 4096, PROT_READ, MAP_PRIVATE | MAP_FIXED));
+    crate::assert_eq!(space.set_program_break(HEAP_START + 8 * 4096), Err(Errno::ENOMEM));
+    crate::assert_eq!(space.program_break().current, HEAP_START + 10);
+
+    Ok(())
+}
+
 // ===== CPU Tests =====
 
 fn test_cpu_data_access() -> TestResult {
@@ -153,6 +369,31 @@ fn test_cpu_data_access() -> TestResult {
     Ok(())
 }
 
+fn test_fpu_enabled() -> TestResult {
+    use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+    // FPU/SSE instructions run natively and trap through CR0.TS for lazy switching
+    crate::assert_false!(Cr0::read().contains(Cr0Flags::EMULATE_COPROCESSOR));
+    crate::assert_true!(Cr0::read().contains(Cr0Flags::MONITOR_COPROCESSOR));
+    crate::assert_true!(Cr4::read().contains(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    crate::assert_true!(crate::fpu::area_size() >= crate::fpu::FXSAVE_AREA_SIZE);
+
+    // New threads start from the FNINIT defaults
+    let mut state = crate::fpu::ExtendedState::new();
+    crate::assert_eq!(state.control_word(), 0x037f);
+    crate::assert_eq!(state.mxcsr(), 0x1f80);
+
+    // fork/clone get their own copy of the saved area (no thread has TID u64::MAX, so nothing is saved from the live registers)
+    state.set_mxcsr(0x7f80);
+    let copy = state.duplicate(u64::MAX);
+    crate::assert_eq!(copy.mxcsr(), 0x7f80);
+    crate::assert_eq!(copy.control_word(), 0x037f);
+    state.set_mxcsr(0x1f80);
+    crate::assert_eq!(copy.mxcsr(), 0x7f80);
+
+    Ok(())
+}
+
 fn test_cpu_statistics() -> TestResult {
     // Test performance monitoring
     let initial_stats = cpu::PERF_MONITOR.get_stats();
@@ -386,5 +627,46 @@ fn create_ipc_tests() -> TestSuite {
         .add_test(TestCase::new("ipc_boot_sequence", "Test IPC boot sequence", TestCategory::Integration, crate::tests::ipc_tests::test_ipc_boot_sequence))
         .add_test(TestCase::new("ipc_cleanup", "Test IPC cleanup functionality", TestCategory::Integration, crate::tests::ipc_tests::test_ipc_cleanup))
         .add_test(TestCase::new("page_table_ops", "Test page table operations", TestCategory::Integration, crate::tests::ipc_tests::test_page_table_ops))
+        .add_test(TestCase::new("memory_handle_exit", "Test memory handle cleanup on exit", TestCategory::Unit, crate::tests::ipc_tests::test_memory_handle_exit))
+        .add_test(TestCase::new("memory_handle_range", "Test memory handle range validation", TestCategory::Unit, crate::tests::ipc_tests::test_memory_handle_range))
+        .add_test(TestCase::new("blocking_wakeups", "Test blocking send/receive wakeups", TestCategory::Unit, crate::tests::ipc_tests::test_blocking_wakeups))
+        .add_test(TestCase::new("call_reply", "Test synchronous call/reply", TestCategory::Unit, crate::tests::ipc_tests::test_call_reply))
+        .add_test(TestCase::new("endpoint_capabilities", "Test channel endpoint capabilities", TestCategory::Unit, crate::tests::ipc_tests::test_endpoint_capabilities))
+        .add_test(TestCase::new("name_service", "Test IPC name service", TestCategory::Unit, crate::tests::ipc_tests::test_name_service))
         .add_test(TestCase::new("all_ipc_tests", "Run all IPC tests", TestCategory::System, crate::tests::ipc_tests::run_all_ipc_tests))
 }
+
+/// Syscall table tests
+fn create_syscall_tests() -> TestSuite {
+    TestSuite::new("Syscall Table", "Tests for syscall registration and dispatch", TestCategory::Unit)
+        .add_test(TestCase::new("table_registration", "Test syscall registration", TestCategory::Unit, crate::tests::syscall_tests::test_table_registration))
+        .add_test(TestCase::new("syscall_frame_args", "Test syscall arguments come from the frame's named registers", TestCategory::Unit, crate::tests::syscall_tests::test_syscall_frame_args))
+        .add_test(TestCase::new("arg_kind_validation", "Test syscall argument validation", TestCategory::Unit, crate::tests::syscall_tests::test_arg_kind_validation))
+        .add_test(TestCase::new("errno_mapping", "Test errno mapping for syscall failures", TestCategory::Unit, crate::tests::syscall_tests::test_errno_mapping))
+        .add_test(TestCase::new("timespec_conversion", "Test timespec conversion and tick rounding", TestCategory::Unit, crate::tests::syscall_tests::test_timespec_conversion))
+        .add_test(TestCase::new("sleep_interruption", "Test signals interrupt nanosleep and keep the remainder target", TestCategory::Unit, crate::tests::syscall_tests::test_sleep_interruption))
+        .add_test(TestCase::new("signal_state", "Test signal masks, actions and delivery order", TestCategory::Unit, crate::tests::syscall_tests::test_signal_state))
+        .add_test(TestCase::new("syscall_restart", "Test SA_RESTART and EINTR for interrupted syscalls", TestCategory::Unit, crate::tests::syscall_tests::test_syscall_restart))
+        .add_test(TestCase::new("wait_process_group", "Test wait4 selection by process group", TestCategory::Unit, crate::tests::syscall_tests::test_wait_process_group))
+        .add_test(TestCase::new("job_control_signals", "Test stop/continue signals and wait status", TestCategory::Unit, crate::tests::syscall_tests::test_job_control_signals))
+        .add_test(TestCase::new("resource_limits", "Test nice conversion and soft/hard resource limits", TestCategory::Unit, crate::tests::syscall_tests::test_resource_limits))
+        .add_test(TestCase::new("priority_aging", "Test aging prevents starvation of low-priority processes", TestCategory::Unit, crate::tests::syscall_tests::test_priority_aging))
+        .add_test(TestCase::new("unprivileged_process", "Test forked children cannot raise limits or priority", TestCategory::Unit, crate::tests::syscall_tests::test_unprivileged_process))
+        .add_test(TestCase::new("cpu_limit_signals", "Test CPU-time soft and hard limit signals", TestCategory::Unit, crate::tests::syscall_tests::test_cpu_limit_signals))
+        .add_test(TestCase::new("futex_key_validation", "Test futex address alignment and range checks", TestCategory::Unit, crate::tests::syscall_tests::test_futex_key_validation))
+        .add_test(TestCase::new("futex_wait_wake", "Test futex FIFO wake order, counts, timeouts and signals", TestCategory::Unit, crate::tests::syscall_tests::test_futex_wait_wake))
+        .add_test(TestCase::new("spawn_thread", "Test threads share the address space with their own stack", TestCategory::Integration, crate::tests::syscall_tests::test_spawn_thread))
+        .add_test(TestCase::new("thread_group_exec", "Test signal actions are per process and execve leaves one thread", TestCategory::Integration, crate::tests::syscall_tests::test_thread_group_exec))
+        .add_test(TestCase::new("builtin_syscalls", "Test built-in syscalls are registered", TestCategory::Integration, crate::tests::syscall_tests::test_builtin_syscalls_registered))
+}
+
+/// ELF loader tests
+fn create_elf_tests() -> TestSuite {
+    TestSuite::new("ELF Loader", "Tests for ELF64 parsing and program loading", TestCategory::Unit)
+        .add_test(TestCase::new("parse_valid_image", "Test parsing a valid executable", TestCategory::Unit, crate::tests::elf_tests::test_parse_valid_image))
+        .add_test(TestCase::new("load_compiled_program", "Test loading the user program built by build.rs", TestCategory::Integration, crate::tests::elf_tests::test_load_compiled_program))
+        .add_test(TestCase::new("reject_malformed_images", "Test malformed executables are rejected", TestCategory::Unit, crate::tests::elf_tests::test_reject_malformed_images))
+        .add_test(TestCase::new("load_maps_segments", "Test segments and stack are mapped with correct permissions", TestCategory::Integration, crate::tests::elf_tests::test_load_maps_segments))
+        .add_test(TestCase::new("initial_stack_layout", "Test argc/argv/envp/auxv layout of the initial stack", TestCategory::Integration, crate::tests::elf_tests::test_initial_stack_layout))
+        .add_test(TestCase::new("program_registry", "Test program lookup by path", TestCategory::Unit, crate::tests::elf_tests::test_program_registry))
+}
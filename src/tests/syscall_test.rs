@@ -1,20 +1,25 @@
-#!/usr/bin/env rust
+//! Boot-time user program exercising getpid, fork, wait4 and exit
+//!
+//! This is not part of the kernel crate: `build.rs` compiles it as a
+//! freestanding static ELF executable and the kernel embeds the image with
+//! `include_bytes!` and launches it through the ELF loader.
 
 #![no_std]
 #![no_main]
-#![feature(asm)]
 
-use core::panic::PanicInfo;
 use core::arch::asm;
+use core::panic::PanicInfo;
 
 // Syscall numbers
+const SYS_EXIT: u64 = 0;
+const SYS_WRITE: u64 = 1;
 const SYS_GETPID: u64 = 39;
 const SYS_FORK: u64 = 57;
 const SYS_WAIT4: u64 = 61;
-const SYS_EXIT: u64 = 0;
-const SYS_WRITE: u64 = 1;
 
-#[no_mangle]
+const STDOUT: u64 = 1;
+
+#[unsafe(no_mangle)]
 pub extern "C" fn _start() -> ! {
     test_syscalls();
     exit(0);
@@ -22,159 +27,102 @@ pub extern "C" fn _start() -> ! {
 
 fn test_syscalls() {
     // Test getpid
-    let pid = getpid();
     print_string("My PID: ");
-    print_int(pid);
-    print_char('\n');
-    
+    print_int(getpid());
+    print_string("\n");
+
     // Test fork
     let child_pid = fork();
     if child_pid == 0 {
         // Child process
         print_string("Child process, PID: ");
         print_int(getpid());
-        print_char('\n');
+        print_string("\n");
         exit(42);
+    } else if child_pid < 0 {
+        print_string("fork failed\n");
     } else {
         // Parent process
         print_string("Parent process, child PID: ");
         print_int(child_pid);
-        print_char('\n');
-        
+        print_string("\n");
+
         // Test wait4
         let mut status = 0i32;
-        let waited_pid = wait4(-1i64 as u64, &mut status);
-        
+        let waited_pid = wait4(-1, &mut status);
+
         print_string("Waited for PID: ");
         print_int(waited_pid);
         print_string(", exit status: ");
-        print_int(status as u64);
-        print_char('\n');
+        print_int(((status >> 8) & 0xff) as i64); // WEXITSTATUS
+        print_string("\n");
     }
 }
 
-// Syscall wrappers
-fn getpid() -> i64 {
-    let mut result: i64;
+// Syscall wrappers (rcx and r11 are clobbered by the syscall instruction)
+unsafe fn syscall3(number: u64, arg1: u64, arg2: u64, arg3: u64) -> i64 {
+    let result: i64;
     unsafe {
         asm!(
-            "mov rax, {}",
             "syscall",
-            "mov {}, rax",
-            out(reg) result,
-            const SYS_GETPID,
+            inlateout("rax") number as i64 => result,
+            in("rdi") arg1,
+            in("rsi") arg2,
+            in("rdx") arg3,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
         );
     }
     result
 }
 
+fn getpid() -> i64 {
+    unsafe { syscall3(SYS_GETPID, 0, 0, 0) }
+}
+
 fn fork() -> i64 {
-    let mut result: i64;
-    unsafe {
-        asm!(
-            "mov rax, {}",
-            "syscall",
-            "mov {}, rax",
-            out(reg) result,
-            const SYS_FORK,
-        );
-    }
-    result
+    unsafe { syscall3(SYS_FORK, 0, 0, 0) }
 }
 
-fn wait4(pid: u64, status_ptr: *mut i32) -> i64 {
-    let mut result: i64;
-    unsafe {
-        asm!(
-            "mov rax, {}",
-            "mov rdi, {}",
-            "mov rsi, {}",
-            "syscall",
-            "mov {}, rax",
-            out(reg) result,
-            in(reg) pid,
-            in(reg) status_ptr,
-            const SYS_WAIT4,
-        );
-    }
-    result
+fn wait4(pid: i64, status: &mut i32) -> i64 {
+    unsafe { syscall3(SYS_WAIT4, pid as u64, status as *mut i32 as u64, 0) }
+}
+
+fn write(bytes: &[u8]) -> i64 {
+    unsafe { syscall3(SYS_WRITE, STDOUT, bytes.as_ptr() as u64, bytes.len() as u64) }
 }
 
 fn exit(code: i32) -> ! {
-    unsafe {
-        asm!(
-            "mov rax, {}",
-            "mov rdi, {}",
-            "syscall",
-            const SYS_EXIT,
-            in(reg) code,
-        );
-    }
+    unsafe { syscall3(SYS_EXIT, code as u64, 0, 0) };
     loop {}
 }
 
 // Simple output functions
 fn print_string(s: &str) {
-    for byte in s.bytes() {
-        print_char(byte as char);
-    }
+    write(s.as_bytes());
 }
 
-fn print_int(mut n: u64) {
-    if n == 0 {
-        print_char('0');
-        return;
-    }
-    
+fn print_int(n: i64) {
     let mut buf = [0u8; 20];
-    let mut i = 19;
-    
-    while n > 0 {
-        buf[i] = ((n % 10) as u8) + b'0';
-        n /= 10;
+    let mut i = buf.len();
+    let mut value = n.unsigned_abs();
+    loop {
         i -= 1;
+        buf[i] = (value % 10) as u8 + b'0';
+        value /= 10;
+        if value == 0 {
+            break;
+        }
     }
-    
-    for j in (i + 1)..20 {
-        print_char(buf[j] as char);
-    }
-}
-
-fn print_char(c: char) {
-    let mut result: i64;
-    unsafe {
-        asm!(
-            "mov rax, {}",
-            "mov rdi, {}",
-            "mov rsi, {}",
-            "mov rdx, {}",
-            "syscall",
-            "mov {}, rax",
-            out(reg) result,
-            const SYS_WRITE,
-            in(reg) 1i64,      // stdout
-            in(reg) &c as *const _ as u64,
-            in(reg) 1u64,       // count
-        );
+    if n < 0 {
+        print_string("-");
     }
+    write(&buf[i..]);
 }
 
 #[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
-    print_string("PANIC: ");
-    if let Some(location) = info.location() {
-        print_string("file ");
-        let file_bytes = location.file().as_bytes();
-        for &byte in file_bytes {
-            print_char(*byte as char);
-        }
-        print_string(":");
-        print_int(location.line() as u64);
-        print_string(" ");
-    }
-    if let Some(message) = info.message() {
-        print_string(message);
-    }
-    print_char('\n');
-    loop {}
+fn panic(_info: &PanicInfo) -> ! {
+    print_string("PANIC in user program\n");
+    exit(101);
 }
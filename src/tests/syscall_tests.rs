@@ -0,0 +1,564 @@
+//! Syscall Table Tests
+//!
+//! This module tests syscall registration and table-driven argument validation.
+
+use crate::testing::{TestResult, TestError};
+use crate::syscall::{self, SyscallArgs};
+use crate::syscall::table::{self, ArgKind, SyscallEntry, SyscallResult, SyscallTable};
+use crate::error::{Errno, IpcError, KernelError, ProcessError};
+use crate::timer::{self, Timespec};
+use crate::process::{Process, ProcessContext, ProcessState, ResourceLimits};
+use crate::process::scheduler::{Scheduler, WaitTarget};
+use crate::process::futex;
+use crate::process::resource::{self, Rlimit};
+use crate::process::signal::{self, DefaultAction, JobStatus, SigAction, SignalState};
+use alloc::string::ToString;
+use alloc::format;
+
+fn test_handler(_args: &SyscallArgs) -> SyscallResult {
+    Ok(0)
+}
+
+/// Test that duplicate registrations are rejected
+pub fn test_table_registration() -> TestResult {
+    let mut table = SyscallTable::new();
+
+    crate::assert_ok!(table.register(SyscallEntry::new(100, "test", test_handler, &[ArgKind::Value])));
+    crate::assert_true!(table.contains(100));
+    crate::assert_eq!(table.get(100).map(|e| e.arg_count), Some(1));
+
+    // Same number must not be registered twice
+    crate::assert_err!(table.register(SyscallEntry::new(100, "test_dup", test_handler, &[])));
+    crate::assert_eq!(table.entries().count(), 1);
+
+    Ok(())
+}
+
+/// Test that arguments are read from the named registers of the frame
+pub fn test_syscall_frame_args() -> TestResult {
+    // Fill each slot with its index, in the order the entry stubs push them
+    let mut slots = [0u64; 20];
+    for (i, slot) in slots.iter_mut().enumerate() {
+        *slot = i as u64;
+    }
+    let base = slots.as_mut_ptr();
+    let frame = unsafe { &mut *(base as *mut syscall::SyscallFrame) };
+
+    let args = SyscallArgs::from_frame(frame);
+    crate::assert_eq!(args.syscall_number, 14); // RAX
+    crate::assert_eq!(args.as_array(), [10, 11, 12, 7, 9, 8]); // RDI, RSI, RDX, R10, R8, R9
+    crate::assert_eq!(args.context_ptr, base as u64);
+    crate::assert_eq!(frame.instruction_pointer(), 15);
+    crate::assert_eq!(frame.stack_pointer(), 18);
+
+    // The return value lands in RAX and a restart rewinds RIP by one instruction
+    frame.set_return_value(Errno::EINVAL.as_syscall_return() as u64);
+    frame.restart_syscall();
+    crate::assert_eq!(slots[14] as i64, Errno::EINVAL.as_syscall_return());
+    crate::assert_eq!(slots[15], 13);
+
+    Ok(())
+}
+
+/// Test argument kind validation
+pub fn test_arg_kind_validation() -> TestResult {
+    crate::assert_true!(ArgKind::Pid.validate(1));
+    crate::assert_false!(ArgKind::Pid.validate(0));
+    crate::assert_false!(ArgKind::Pid.validate(table::MAX_PID + 1));
+
+    crate::assert_true!(ArgKind::PidOrAny.validate(u64::MAX));
+    crate::assert_false!(ArgKind::PidOrAny.validate(0));
+
+    crate::assert_true!(ArgKind::Capability.validate(0));
+    crate::assert_false!(ArgKind::Capability.validate(table::MAX_CAPABILITIES));
+
+    crate::assert_true!(ArgKind::UserPtr.validate(0));
+    crate::assert_true!(ArgKind::UserPtr.validate(0x400000));
+    crate::assert_false!(ArgKind::UserPtr.validate(0xFFFF_8000_0000_0000));
+
+    Ok(())
+}
+
+/// Test that the built-in syscalls are registered in the global table
+pub fn test_builtin_syscalls_registered() -> TestResult {
+    let builtin = [
+        syscall::SYS_EXIT,
+        syscall::SYS_WRITE,
+        syscall::SYS_CREATE_CHANNEL,
+        syscall::SYS_SEND_MESSAGE,
+        syscall::SYS_RECEIVE_MESSAGE,
+        syscall::SYS_CREATE_MEMORY_HANDLE,
+        syscall::SYS_TRANSFER_MEMORY,
+        syscall::SYS_RECEIVE_MEMORY_HANDLE,
+        syscall::SYS_SCHED_YIELD,
+        syscall::SYS_GETPID,
+        syscall::SYS_FORK,
+        syscall::SYS_WAIT4,
+        syscall::SYS_NANOSLEEP,
+        syscall::SYS_CLOCK_GETTIME,
+        syscall::SYS_KILL,
+        syscall::SYS_RT_SIGACTION,
+        syscall::SYS_RT_SIGPROCMASK,
+        syscall::SYS_RT_SIGRETURN,
+        syscall::SYS_GETPPID,
+        syscall::SYS_SETPGID,
+        syscall::SYS_GETPGID,
+        syscall::SYS_SETSID,
+        syscall::SYS_GETSID,
+        syscall::SYS_GETPRIORITY,
+        syscall::SYS_SETPRIORITY,
+        syscall::SYS_GETRLIMIT,
+        syscall::SYS_SETRLIMIT,
+        syscall::SYS_FUTEX,
+        syscall::SYS_CLONE,
+        syscall::SYS_GETTID,
+        syscall::SYS_THREAD_EXIT,
+        syscall::SYS_THREAD_JOIN,
+        syscall::SYS_ARCH_PRCTL,
+        syscall::SYS_PRLIMIT64,
+        syscall::SYS_GETRUSAGE,
+    ];
+
+    for number in builtin.iter() {
+        if !table::is_registered(*number) {
+            return Err(TestError::AssertionFailed(format!("syscall {} is not registered", number)));
+        }
+    }
+    crate::assert_false!(table::is_registered(0xFFFF));
+
+    // Unregistered numbers reach dispatch and fail with ENOSYS
+    let mut slots = [0u64; 20];
+    slots[14] = 0xFFFF; // RAX
+    let args = SyscallArgs::from_frame(unsafe { &*(slots.as_ptr() as *const syscall::SyscallFrame) });
+    crate::assert_eq!(table::dispatch(&args), Errno::ENOSYS.as_syscall_return());
+
+    let stats = table::syscall_stats();
+    crate::assert_true!(stats.len() >= builtin.len());
+    if stats.iter().any(|(_, name, _)| name.is_empty()) {
+        return Err(TestError::AssertionFailed("syscall without name".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Test that kernel errors map to distinct Linux errno values
+pub fn test_errno_mapping() -> TestResult {
+    crate::assert_eq!(Errno::from(IpcError::ChannelFull), Errno::EAGAIN);
+    crate::assert_eq!(Errno::from(IpcError::AccessDenied), Errno::EACCES);
+    crate::assert_eq!(Errno::from(IpcError::MessageTooLarge), Errno::EMSGSIZE);
+    crate::assert_eq!(Errno::from(KernelError::Process(ProcessError::NotFound)), Errno::ESRCH);
+    crate::assert_eq!(Errno::from(syscall::SyscallError::InvalidPointer(0)), Errno::EFAULT);
+
+    crate::assert_eq!(Errno::EINVAL.as_syscall_return(), -22);
+    crate::assert_eq!(ArgKind::UserPtr.errno(), Errno::EFAULT);
+
+    Ok(())
+}
+
+/// Test timespec conversion used by nanosleep / clock_gettime
+pub fn test_timespec_conversion() -> TestResult {
+    let ts = Timespec::from_nanos(1_500_000_000);
+    crate::assert_eq!(ts, Timespec { tv_sec: 1, tv_nsec: 500_000_000 });
+    crate::assert_eq!(ts.to_nanos(), Some(1_500_000_000));
+
+    // Negative or out-of-range fields are rejected
+    crate::assert_eq!(Timespec { tv_sec: -1, tv_nsec: 0 }.to_nanos(), None);
+    crate::assert_eq!(Timespec { tv_sec: 0, tv_nsec: 1_000_000_000 }.to_nanos(), None);
+    crate::assert_eq!(Timespec { tv_sec: i64::MAX, tv_nsec: 0 }.to_nanos(), None);
+
+    // Sleeps round up to whole ticks
+    crate::assert_eq!(timer::nanos_to_ticks(1), 1);
+    crate::assert_eq!(timer::nanos_to_ticks(timer::NANOS_PER_TICK), 1);
+    crate::assert_eq!(timer::nanos_to_ticks(timer::NANOS_PER_TICK + 1), 2);
+
+    Ok(())
+}
+
+/// Test that only a sleep interrupted by a signal reports its remaining time
+pub fn test_sleep_interruption() -> TestResult {
+    let mut sched = Scheduler::new();
+    let (expired, interrupted) = (crate::process::allocate_pid(), crate::process::allocate_pid());
+    sched.processes.push_back(Process::new(expired, 0x400000, 0x7000_0000));
+    sched.processes.push_back(Process::new(interrupted, 0x400000, 0x7000_0000));
+
+    sched.sleep_until(expired, 10, 0x7000_1000)?;
+    sched.sleep_until(interrupted, 1000, 0x7000_2000)?;
+
+    // A sleep that runs to its deadline has nothing to write back
+    sched.wake_sleepers(10);
+    crate::assert_eq!(sched.processes[0].state, ProcessState::Ready);
+    crate::assert_eq!(sched.processes[0].sleep_rem, None);
+
+    // A signal wakes the sleeper early with EINTR and keeps the rem target
+    sched.send_signal(interrupted, signal::SIGUSR1)?;
+    let process = &sched.processes[1];
+    let context = unsafe { &*(process.context_ptr as *const ProcessContext) };
+    crate::assert_eq!(process.state, ProcessState::Ready);
+    crate::assert_eq!(context.syscall_number(), Errno::EINTR.as_syscall_return() as u64);
+    crate::assert_eq!(process.sleep_rem, Some((0x7000_2000, 1000)));
+
+    Ok(())
+}
+
+/// Test signal masks, actions and delivery order
+pub fn test_signal_state() -> TestResult {
+    let mut state = SignalState::new();
+
+    // Default-ignored signals are discarded, others stay pending
+    crate::assert_false!(state.raise(signal::SIGCHLD));
+    crate::assert_true!(state.raise(signal::SIGTERM));
+    crate::assert_true!(state.raise(signal::SIGUSR1));
+    crate::assert_eq!(state.take_deliverable(), Some(signal::SIGUSR1));
+    crate::assert_eq!(state.take_deliverable(), Some(signal::SIGTERM));
+    crate::assert_eq!(state.take_deliverable(), None);
+
+    // Blocked signals stay pending until unblocked; SIGKILL cannot be blocked
+    state.set_blocked(signal::sig_bit(signal::SIGUSR2) | signal::sig_bit(signal::SIGKILL));
+    crate::assert_eq!(state.blocked, signal::sig_bit(signal::SIGUSR2));
+    crate::assert_false!(state.raise(signal::SIGUSR2));
+    crate::assert_eq!(state.take_deliverable(), None);
+    state.set_blocked(0);
+    crate::assert_eq!(state.take_deliverable(), Some(signal::SIGUSR2));
+
+    // SIGKILL's action cannot be changed
+    let handler = SigAction { handler: 0x401000, ..SigAction::default() };
+    crate::assert_err!(state.set_action(signal::SIGKILL, handler));
+    crate::assert_ok!(state.set_action(signal::SIGSEGV, handler));
+
+    // Forced signals are delivered even when blocked, and exec resets handlers
+    state.set_blocked(signal::sig_bit(signal::SIGSEGV));
+    state.force(signal::SIGSEGV);
+    crate::assert_eq!(state.take_deliverable(), Some(signal::SIGSEGV));
+    crate::assert_ok!(state.set_action(signal::SIGINT, handler));
+    state.reset_for_exec();
+    crate::assert_eq!(state.action(signal::SIGINT), SigAction::default());
+
+    Ok(())
+}
+
+/// Test that a handler interrupts a blocked syscall with EINTR unless SA_RESTART is set
+pub fn test_syscall_restart() -> TestResult {
+    let mut frame = ProcessContext::new_user(0x401002, 0x7000_0000);
+    frame.set_return_value(61); // wait4 blocked and asked for a restart
+    frame.restart_syscall();
+
+    let restart = SigAction { handler: 0x402000, flags: signal::SA_RESTART, ..SigAction::default() };
+    signal::interrupt_syscall(&mut frame, &restart);
+    crate::assert_eq!(frame.instruction_pointer(), 0x401000);
+    crate::assert_eq!(frame.syscall_number(), 61);
+
+    let plain = SigAction { handler: 0x402000, ..SigAction::default() };
+    signal::interrupt_syscall(&mut frame, &plain);
+    crate::assert_eq!(frame.instruction_pointer(), 0x401002);
+    crate::assert_eq!(frame.syscall_number() as i64, Errno::EINTR.as_syscall_return());
+
+    Ok(())
+}
+
+/// Test stop/continue signal semantics used for job control
+pub fn test_job_control_signals() -> TestResult {
+    crate::assert_eq!(signal::default_action(signal::SIGTSTP), DefaultAction::Stop);
+    crate::assert_eq!(signal::default_action(signal::SIGCONT), DefaultAction::Continue);
+
+    // SIGCONT discards pending stop signals and is not delivered by default
+    let mut state = SignalState::new();
+    crate::assert_true!(state.raise(signal::SIGTSTP));
+    crate::assert_false!(state.raise(signal::SIGCONT));
+    crate::assert_eq!(state.take_deliverable(), None);
+
+    // A stop signal discards a pending (handled) SIGCONT
+    let handler = SigAction { handler: 0x401000, ..SigAction::default() };
+    crate::assert_ok!(state.set_action(signal::SIGCONT, handler));
+    crate::assert_true!(state.raise(signal::SIGCONT));
+    crate::assert_true!(state.raise(signal::SIGSTOP));
+    crate::assert_eq!(state.take_deliverable(), Some(signal::SIGSTOP));
+    crate::assert_eq!(state.take_deliverable(), None);
+
+    // wait4 status encoding matches WIFSTOPPED / WIFCONTINUED
+    crate::assert_eq!(JobStatus::Stopped(signal::SIGTSTP).wait_status(), 0x147f);
+    crate::assert_eq!(JobStatus::Continued.wait_status(), 0xffff);
+
+    // Exit and signal termination decode with WIFEXITED / WEXITSTATUS / WIFSIGNALED / WTERMSIG
+    let exited = |status: i32| status & 0x7f == 0;
+    let signaled = |status: i32| ((status & 0x7f) + 1) as i8 >> 1 > 0;
+    let exited_status = signal::exit_status(3);
+    crate::assert_true!(exited(exited_status) && !signaled(exited_status));
+    crate::assert_eq!((exited_status >> 8) & 0xff, 3);
+    crate::assert_eq!((signal::exit_status(255) >> 8) & 0xff, 255); // exit(-1)
+    let killed_status = signal::exit_status(signal::exit_code_for(signal::SIGKILL));
+    crate::assert_true!(signaled(killed_status) && !exited(killed_status));
+    crate::assert_eq!(killed_status & 0x7f, signal::SIGKILL as i32);
+    crate::assert_false!(signaled(JobStatus::Stopped(signal::SIGTSTP).wait_status()));
+
+    Ok(())
+}
+
+/// Test that wait4 can select children by process group
+pub fn test_wait_process_group() -> TestResult {
+    let mut sched = Scheduler::new();
+    let parent = crate::process::allocate_pid();
+    let job = crate::process::allocate_pid();
+    let other = crate::process::allocate_pid();
+    sched.processes.push_back(Process::new(parent, 0x400000, 0x7000_0000));
+    for (pid, pgid) in [(job, job), (other, parent)] {
+        let mut child = Process::new(pid, 0x400000, 0x7000_0000);
+        child.parent_id = parent;
+        child.process_group_id = pgid;
+        child.state = ProcessState::Zombie;
+        sched.processes.push_back(child);
+    }
+
+    crate::assert_eq!(sched.find_zombie_children(parent, WaitTarget::Group(job)), alloc::vec![job]);
+    crate::assert_eq!(sched.find_zombie_children(parent, WaitTarget::Group(parent)), alloc::vec![other]);
+    crate::assert_eq!(sched.find_zombie_children(parent, WaitTarget::Pid(other)), alloc::vec![other]);
+    crate::assert_eq!(sched.find_zombie_children(parent, WaitTarget::Any).len(), 2);
+
+    // Stopped members of the job are reported through the same selector
+    sched.processes[1].state = ProcessState::Stopped;
+    sched.processes[1].job_status = Some(JobStatus::Stopped(signal::SIGTSTP));
+    crate::assert_eq!(sched.take_job_status(parent, WaitTarget::Group(parent), true, false), None);
+    crate::assert_eq!(sched.take_job_status(parent, WaitTarget::Group(job), true, false),
+        Some((job, JobStatus::Stopped(signal::SIGTSTP).wait_status())));
+
+    // Group waits are woken by any child's state change
+    crate::assert_eq!(WaitTarget::Group(job).wait_pid(), u64::MAX);
+    crate::assert_eq!(WaitTarget::Pid(job).wait_pid(), job);
+
+    Ok(())
+}
+
+/// Test nice conversion and soft/hard resource limit rules
+pub fn test_resource_limits() -> TestResult {
+    crate::assert_eq!(resource::nice_to_priority(0), crate::process::DEFAULT_PRIORITY);
+    crate::assert_eq!(resource::nice_to_priority(19), 29);
+    crate::assert_eq!(resource::nice_to_priority(-20), 0);
+    crate::assert_eq!(resource::priority_to_nice(resource::nice_to_priority(5)), 5);
+
+    let mut soft = ResourceLimits::default();
+    let mut hard = ResourceLimits::default();
+    let limit = resource::get_rlimit(&soft, &hard, resource::RLIMIT_AS)
+        .map_err(|e| TestError::AssertionFailed(format!("getrlimit failed: {}", e)))?;
+    crate::assert_eq!(limit.rlim_cur, soft.max_memory);
+
+    // Lowering is always allowed
+    let lower = Rlimit { rlim_cur: 1024 * 1024, rlim_max: 2 * 1024 * 1024 };
+    crate::assert_ok!(resource::apply_rlimit(&mut soft, &mut hard, resource::RLIMIT_AS, &lower, false));
+    crate::assert_eq!(soft.max_memory, 1024 * 1024);
+    crate::assert_eq!(hard.max_memory, 2 * 1024 * 1024);
+
+    // Raising the hard limit needs privilege; soft above hard is invalid
+    let raise = Rlimit { rlim_cur: 1024 * 1024, rlim_max: 4 * 1024 * 1024 };
+    crate::assert_eq!(resource::apply_rlimit(&mut soft, &mut hard, resource::RLIMIT_AS, &raise, false), Err(Errno::EPERM));
+    crate::assert_ok!(resource::apply_rlimit(&mut soft, &mut hard, resource::RLIMIT_AS, &raise, true));
+    let inverted = Rlimit { rlim_cur: 2, rlim_max: 1 };
+    crate::assert_eq!(resource::apply_rlimit(&mut soft, &mut hard, resource::RLIMIT_AS, &inverted, true), Err(Errno::EINVAL));
+
+    // CPU time is unlimited by default and exchanged in seconds
+    let cpu = resource::get_rlimit(&soft, &hard, resource::RLIMIT_CPU)
+        .map_err(|e| TestError::AssertionFailed(format!("getrlimit failed: {}", e)))?;
+    crate::assert_eq!(cpu.rlim_cur, resource::RLIM_INFINITY);
+    let cpu_limit = Rlimit { rlim_cur: 3, rlim_max: 5 };
+    crate::assert_ok!(resource::apply_rlimit(&mut soft, &mut hard, resource::RLIMIT_CPU, &cpu_limit, false));
+    crate::assert_eq!(soft.max_cpu_time, 3000);
+
+    Ok(())
+}
+
+/// Test that aging lets a low-priority process run despite a CPU-bound high-priority one
+pub fn test_priority_aging() -> TestResult {
+    let mut sched = Scheduler::new();
+    let (low, high) = (crate::process::allocate_pid(), crate::process::allocate_pid());
+    let mut low_process = Process::new(low, 0x400000, 0x7000_0000);
+    low_process.priority = 20;
+    let mut high_process = Process::new(high, 0x400000, 0x7000_0000);
+    high_process.priority = 0;
+    sched.processes.push_back(low_process);
+    sched.processes.push_back(high_process);
+
+    // The high-priority process wins until the other has waited out the difference
+    for _ in 0..20 {
+        crate::assert_eq!(sched.pick_next(), Some(high));
+    }
+    crate::assert_eq!(sched.processes[0].wait_age, 20);
+    crate::assert_eq!(sched.pick_next(), Some(low));
+    crate::assert_eq!(sched.processes[0].wait_age, 0);
+    crate::assert_eq!(sched.pick_next(), Some(high));
+
+    Ok(())
+}
+
+/// Test that privilege is not inherited, so a forked child cannot raise its limits or priority
+pub fn test_unprivileged_process() -> TestResult {
+    let image = super::elf_tests::build_executable(0x400000, &[0xEB, 0xFE]);
+    let mut parent = Process::from_elf(crate::process::allocate_pid(), &image)?;
+    crate::assert_false!(parent.privileged);
+
+    // Only the kernel grants privilege (to the boot process); fork drops it
+    parent.privileged = true;
+    let mut child = parent.fork(&ProcessContext::new_user(0x400000, 0x7000_0000))?;
+    crate::assert_false!(child.privileged);
+
+    let raise = Rlimit { rlim_cur: 1024 * 1024, rlim_max: resource::RLIM_INFINITY };
+    crate::assert_eq!(resource::apply_rlimit(&mut child.resource_limits, &mut child.hard_limits, resource::RLIMIT_AS, &raise, child.privileged), Err(Errno::EPERM));
+    crate::assert_ok!(resource::apply_rlimit(&mut parent.resource_limits, &mut parent.hard_limits, resource::RLIMIT_AS, &raise, parent.privileged));
+
+    // Lowering the nice value (raising the priority) is refused, raising it is not
+    let higher = resource::nice_to_priority(-5);
+    crate::assert_eq!(resource::check_priority_change(child.priority, higher, child.privileged), Err(Errno::EACCES));
+    crate::assert_ok!(resource::check_priority_change(child.priority, resource::nice_to_priority(5), child.privileged));
+    crate::assert_ok!(resource::check_priority_change(parent.priority, higher, parent.privileged));
+
+    Ok(())
+}
+
+/// Test CPU-time limit notifications (SIGXCPU at the soft limit, SIGKILL at the hard limit)
+pub fn test_cpu_limit_signals() -> TestResult {
+    let (soft, hard) = (3000, 5000);
+    crate::assert_eq!(resource::cpu_limit_signal(2800, 2900, soft, hard), None);
+    crate::assert_eq!(resource::cpu_limit_signal(2900, 3000, soft, hard), Some(signal::SIGXCPU));
+    // Repeated once per second while between the limits
+    crate::assert_eq!(resource::cpu_limit_signal(3000, 3100, soft, hard), None);
+    crate::assert_eq!(resource::cpu_limit_signal(3900, 4000, soft, hard), Some(signal::SIGXCPU));
+    crate::assert_eq!(resource::cpu_limit_signal(4900, 5000, soft, hard), Some(signal::SIGKILL));
+
+    // No CPU limit is enforced unless one is set with setrlimit
+    crate::assert_eq!(ResourceLimits::default().max_cpu_time, u64::MAX);
+
+    // System time from syscalls accrues in nanoseconds and carries sub-millisecond remainders
+    let mut process = Process::new(crate::process::allocate_pid(), 0x400000, 0x7000_0000);
+    crate::assert_eq!(process.charge_system_time(600_000), None);
+    crate::assert_eq!(process.stats.system_time, 0);
+    crate::assert_eq!(process.charge_system_time(600_000), None);
+    crate::assert_eq!(process.stats.system_time, 1);
+    crate::assert_eq!(process.stats.system_nanos_carry, 200_000);
+    crate::assert_eq!(process.stats.cpu_time_used, 1);
+    crate::assert_eq!(process.stats.user_time, 0);
+
+    Ok(())
+}
+
+/// Test futex address validation (keys are only computed for aligned user words)
+pub fn test_futex_key_validation() -> TestResult {
+    crate::assert_eq!(futex::futex_key(0x1002), Err(Errno::EINVAL));
+    crate::assert_eq!(futex::futex_key(0), Err(Errno::EFAULT));
+    crate::assert_eq!(futex::futex_key(0xffff_8000_0000_0000), Err(Errno::EFAULT));
+
+    Ok(())
+}
+
+// Value the process will see in RAX when it returns to user space
+fn saved_return_value(process: &Process) -> i64 {
+    let context = unsafe { &*(process.context_ptr as *const ProcessContext) };
+    context.syscall_number() as i64
+}
+
+/// Test futex wait queues: FIFO wake order, wake counts, timeouts and signals
+pub fn test_futex_wait_wake() -> TestResult {
+    const KEY: u64 = 0x12_3000;
+    let mut sched = Scheduler::new();
+    let mut pids = [0u64; 4];
+    for pid in pids.iter_mut() {
+        *pid = crate::process::allocate_pid();
+        sched.processes.push_back(Process::new(*pid, 0x400000, 0x7000_0000));
+    }
+    let [first, interrupted, last, timed] = pids;
+    let state_of = |sched: &Scheduler, pid: u64| sched.processes.iter().find(|p| p.id == pid).map(|p| p.state);
+    let waiting = Some(ProcessState::Waiting(crate::process::WaitReason::Futex(KEY, None)));
+
+    for pid in [first, interrupted, last] {
+        sched.futex_wait(pid, KEY, None)?;
+    }
+    sched.futex_wait(timed, KEY, Some(50))?;
+
+    // A signal ends the wait with EINTR and drops the waiter from the queue
+    sched.send_signal(interrupted, signal::SIGUSR1)?;
+    crate::assert_eq!(state_of(&sched, interrupted), Some(ProcessState::Ready));
+    crate::assert_eq!(saved_return_value(&sched.processes[1]), Errno::EINTR.as_syscall_return());
+
+    // Waiters are woken in the order they started waiting, at most `count` of them
+    crate::assert_eq!(sched.futex_wake(KEY, 1), 1);
+    crate::assert_eq!(state_of(&sched, first), Some(ProcessState::Ready));
+    crate::assert_eq!(state_of(&sched, last), waiting);
+
+    // A timed wait that reaches its deadline returns ETIMEDOUT
+    sched.wake_sleepers(50);
+    crate::assert_eq!(state_of(&sched, timed), Some(ProcessState::Ready));
+    crate::assert_eq!(saved_return_value(&sched.processes[3]), Errno::ETIMEDOUT.as_syscall_return());
+
+    // Only the remaining waiter is counted; the queue is then empty
+    crate::assert_eq!(sched.futex_wake(KEY, 10), 1);
+    crate::assert_eq!(state_of(&sched, last), Some(ProcessState::Ready));
+    crate::assert_eq!(sched.futex_wake(KEY, 10), 0);
+
+    Ok(())
+}
+
+/// Test that threads share the address space but get their own TID and stack
+pub fn test_spawn_thread() -> TestResult {
+    let pid = crate::process::allocate_pid();
+    let mut process = Process::new(pid, 0x400000, 0x7000_0000);
+    crate::assert_false!(process.is_thread());
+
+    process.fs_base = 0x7fff_0000;
+    let context = ProcessContext::new_user(0x401234, 0x7000_0000);
+    let thread = process.spawn_thread(&context, 0x6000_0000)?;
+    crate::assert_true!(thread.is_thread());
+    crate::assert_eq!(thread.thread_group_id, pid);
+    crate::assert_true!(thread.id != pid);
+    crate::assert_true!(alloc::sync::Arc::ptr_eq(&thread.address_space, &process.address_space));
+    crate::assert_true!(thread.kernel_stack.top() != process.kernel_stack.top());
+    // TLS starts out the same as the creating thread's (CLONE_SETTLS overrides it)
+    crate::assert_eq!(thread.fs_base, 0x7fff_0000);
+
+    // Resumes where clone was called, on the new stack
+    let thread_context = unsafe { &*(thread.context_ptr as *const ProcessContext) };
+    crate::assert_eq!(thread_context.instruction_pointer(), 0x401234);
+    crate::assert_eq!(thread_context.stack_pointer(), 0x6000_0000);
+
+    Ok(())
+}
+
+/// Test that signal dispositions are shared by the thread group and execve
+/// leaves only the calling thread, which takes over the leader's PID
+pub fn test_thread_group_exec() -> TestResult {
+    let mut sched = Scheduler::new();
+    let pid = crate::process::allocate_pid();
+    let leader = Process::new(pid, 0x400000, 0x7000_0000);
+    let context = ProcessContext::new_user(0x401234, 0x7000_0000);
+    let first = leader.spawn_thread(&context, 0x6000_0000)?;
+    let second = leader.spawn_thread(&context, 0x5000_0000)?;
+    let (first_id, second_id) = (first.id, second.id);
+    let second_stack = second.kernel_stack.top();
+    let second_context = second.context_ptr;
+    sched.add_process(leader);
+    sched.add_process(first);
+    sched.add_process(second);
+
+    // A handler installed from one thread applies to every thread
+    let action = SigAction { handler: 0x401000, ..SigAction::default() };
+    crate::assert_true!(sched.set_signal_action(pid, signal::SIGUSR1, action).is_ok());
+    for process in sched.processes.iter() {
+        crate::assert_eq!(process.signals.action(signal::SIGUSR1).handler, 0x401000);
+    }
+
+    // execve from a non-leader thread kills the others and keeps the PID
+    let blocked = signal::sig_bit(signal::SIGUSR2);
+    if let Some(process) = sched.processes.iter_mut().find(|p| p.id == second_id) {
+        process.signals.set_blocked(blocked);
+    }
+    crate::assert_eq!(sched.exec_single_thread(second_id).ok(), Some(pid));
+    crate::assert_eq!(sched.processes.iter().filter(|p| p.thread_group_id == pid).count(), 1);
+    crate::assert_false!(sched.processes.iter().any(|p| p.id == first_id || p.id == second_id));
+    let process = sched.processes.iter().find(|p| p.id == pid)
+        .ok_or_else(|| TestError::AssertionFailed("leader was removed".to_string()))?;
+    // Still running on the calling thread's kernel stack, with its signal mask
+    crate::assert_eq!(process.kernel_stack.top(), second_stack);
+    crate::assert_eq!(process.context_ptr, second_context);
+    crate::assert_eq!(process.signals.blocked, blocked);
+
+    // From the leader itself there is nothing to take over
+    crate::assert_eq!(sched.exec_single_thread(pid).ok(), Some(pid));
+    crate::assert_eq!(sched.processes.len(), 1);
+
+    Ok(())
+}
@@ -1,235 +1,69 @@
 use x86_64::instructions::port::Port;
 use spin::Mutex;
-use alloc::vec::Vec;
-use lazy_static::lazy_static;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 const PIT_FREQUENCY: u32 = 1193182; // PITの基本周波数
-const TIMER_INTERVAL: u32 = 10; // 10Hz for faster timeout testing
-const DEFAULT_TIMEOUT_LIMIT: u64 = 30; // 3 seconds at 10Hz
+const TIMER_INTERVAL: u32 = 10; // 10Hz
 
-// プロセスごとのタイムアウト状態
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum TimeoutState {
-    Normal,
-    Warning,
-    TimedOut,
-}
+/// 1ティックあたりのミリ秒（CPU時間の課金単位）
+pub const MILLIS_PER_TICK: u64 = 1000 / TIMER_INTERVAL as u64;
+/// 1ティックあたりのナノ秒
+pub const NANOS_PER_TICK: u64 = 1_000_000_000 / TIMER_INTERVAL as u64;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+// clock_gettime のクロックID
+pub const CLOCK_REALTIME: u64 = 0;
+pub const CLOCK_MONOTONIC: u64 = 1;
 
-#[derive(Debug)]
-struct ProcessTimeout {
-    pid: u64,
-    start_time: u64,
-    limit: u64,
-    state: TimeoutState,
-    warning_sent: bool,
+/// ユーザー空間と共有する時刻の表現（struct timespec）
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
 }
 
-impl ProcessTimeout {
-    fn new(pid: u64, limit: u64) -> Self {
+impl Timespec {
+    /// ナノ秒から変換する
+    pub fn from_nanos(nanos: u64) -> Self {
         Self {
-            pid,
-            start_time: 0,
-            limit,
-            state: TimeoutState::Normal,
-            warning_sent: false,
+            tv_sec: (nanos / NANOS_PER_SEC) as i64,
+            tv_nsec: (nanos % NANOS_PER_SEC) as i64,
         }
     }
-    
-    fn reset(&mut self) {
-        self.start_time = 0;
-        self.state = TimeoutState::Normal;
-        self.warning_sent = false;
-    }
-    
-    fn start(&mut self, current_tick: u64) {
-        self.start_time = current_tick;
-        self.state = TimeoutState::Normal;
-        self.warning_sent = false;
-    }
-    
-    fn check_timeout(&mut self, current_tick: u64) -> TimeoutState {
-        if self.state == TimeoutState::TimedOut {
-            return TimeoutState::TimedOut;
-        }
-        
-        let elapsed = current_tick.saturating_sub(self.start_time);
-        let warning_threshold = self.limit / 2; // 50%で警告
-        
-        if elapsed >= self.limit {
-            self.state = TimeoutState::TimedOut;
-            TimeoutState::TimedOut
-        } else if elapsed >= warning_threshold && !self.warning_sent {
-            self.warning_sent = true;
-            TimeoutState::Warning
-        } else {
-            TimeoutState::Normal
+
+    /// ナノ秒に変換する（負の値や範囲外のtv_nsecは`None`）
+    pub fn to_nanos(&self) -> Option<u64> {
+        if self.tv_sec < 0 || !(0..NANOS_PER_SEC as i64).contains(&self.tv_nsec) {
+            return None;
         }
+        (self.tv_sec as u64).checked_mul(NANOS_PER_SEC)?.checked_add(self.tv_nsec as u64)
     }
 }
 
-// タイムアウト管理用のグローバル変数
-lazy_static! {
-    static ref TIMEOUT_MANAGER: Mutex<TimeoutManager> = Mutex::new(TimeoutManager::new());
-}
-static GLOBAL_TICK_COUNTER: Mutex<u64> = Mutex::new(0);
-
-// タイムアウト管理構造体
-struct TimeoutManager {
-    processes: Vec<ProcessTimeout>,
-    current_tick: u64,
-    user_mode_active: bool,
-    current_user_pid: u64,
+/// マイクロ秒精度の時刻の表現（struct timeval、getrusageなどで使う）
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
 }
 
-impl TimeoutManager {
-    fn new() -> Self {
+impl Timeval {
+    /// ミリ秒から変換する
+    pub fn from_millis(millis: u64) -> Self {
         Self {
-            processes: Vec::new(),
-            current_tick: 0,
-            user_mode_active: false,
-            current_user_pid: 0,
+            tv_sec: (millis / 1000) as i64,
+            tv_usec: ((millis % 1000) * 1000) as i64,
         }
     }
-    
-    fn register_process(&mut self, pid: u64, limit: Option<u64>) {
-        let timeout_limit = limit.unwrap_or(DEFAULT_TIMEOUT_LIMIT);
-        self.processes.push(ProcessTimeout::new(pid, timeout_limit));
-        println!("TIMEOUT: Process {} registered with limit {} ticks", pid, timeout_limit);
-    }
-    
-    fn unregister_process(&mut self, pid: u64) {
-        self.processes.retain(|p| p.pid != pid);
-        println!("TIMEOUT: Process {} unregistered", pid);
-    }
-    
-    fn start_user_mode(&mut self, pid: u64) {
-        self.user_mode_active = true;
-        self.current_user_pid = pid;
-        
-        // 対応するプロセスのタイムアウトを開始
-        if let Some(process) = self.processes.iter_mut().find(|p| p.pid == pid) {
-            process.start(self.current_tick);
-            println!("TIMEOUT: User mode started for PID {} at tick {}", pid, self.current_tick);
-        } else {
-            // プロセスが見つからない場合は登録して開始
-            self.register_process(pid, None);
-            if let Some(process) = self.processes.iter_mut().find(|p| p.pid == pid) {
-                process.start(self.current_tick);
-            }
-        }
-    }
-    
-    fn end_user_mode(&mut self) {
-        self.user_mode_active = false;
-        if let Some(process) = self.processes.iter_mut().find(|p| p.pid == self.current_user_pid) {
-            process.reset();
-            println!("TIMEOUT: User mode ended for PID {}", self.current_user_pid);
-        }
-        self.current_user_pid = 0;
-    }
-    
-    fn increment_tick(&mut self) {
-        self.current_tick = self.current_tick.wrapping_add(1);
-        
-        if self.user_mode_active {
-            self.check_timeouts();
-        }
-    }
-    
-    fn check_timeouts(&mut self) {
-        if let Some(process) = self.processes.iter_mut().find(|p| p.pid == self.current_user_pid) {
-            let timeout_state = process.check_timeout(self.current_tick);
-            let pid = process.pid; // 借用を避けるためにpidをコピー
-            
-            match timeout_state {
-                TimeoutState::Warning => {
-                    let remaining = process.limit.saturating_sub(
-                        self.current_tick.saturating_sub(process.start_time)
-                    );
-                    println!("TIMEOUT WARNING: PID {} has {} ticks remaining", 
-                            pid, remaining);
-                }
-                TimeoutState::TimedOut => {
-                    self.handle_timeout(pid);
-                }
-                TimeoutState::Normal => {
-                    // 正常状態
-                }
-            }
-        }
-    }
-    
-    fn handle_timeout(&mut self, pid: u64) {
-        println!("TIMEOUT: Process {} exceeded time limit!", pid);
-        
-        // プロセス情報を取得してから借用を解放
-        let (start_tick, limit) = if let Some(process) = self.processes.iter().find(|p| p.pid == pid) {
-            (process.start_time, process.limit)
-        } else {
-            (0, 0)
-        };
-        
-        println!("TIMEOUT: Current tick: {}, Start tick: {}, Limit: {}", 
-                self.current_tick, start_tick, limit);
-        
-        // プロセスを終了させる
-        self.kill_process(pid);
-        
-        // ユーザーモードを終了
-        self.end_user_mode();
-    }
-    
-    fn kill_process(&mut self, pid: u64) {
-        println!("TIMEOUT: Killing process {} due to timeout", pid);
-        
-        // プロセススケジューラに終了を通知
-        use crate::process::scheduler::SCHEDULER;
-        let mut sched = SCHEDULER.lock();
-        
-        // プロセスをZombie状態に設定
-        for process in &mut sched.processes {
-            if process.id == pid {
-                process.state = crate::process::ProcessState::Zombie;
-                process.exit_code = -1; // タイムアウト終了コード
-                println!("TIMEOUT: Process {} marked as zombie", pid);
-                break;
-            }
-        }
-        
-        // 親プロセスを起床させる
-        for process in &mut sched.processes {
-            if let crate::process::ProcessState::Waiting(
-                crate::process::WaitReason::Child(waiting_pid)
-            ) = process.state {
-                if waiting_pid == pid || waiting_pid == (-1i64 as u64) {
-                    process.state = crate::process::ProcessState::Ready;
-                    println!("TIMEOUT: Woke up parent {} from waiting for child {}", 
-                            process.id, pid);
-                }
-            }
-        }
-    }
-    
-    fn set_timeout_limit(&mut self, pid: u64, limit: u64) {
-        if let Some(process) = self.processes.iter_mut().find(|p| p.pid == pid) {
-            process.limit = limit;
-            println!("TIMEOUT: Set limit {} ticks for PID {}", limit, pid);
-        } else {
-            // プロセスが存在しない場合は新規登録
-            self.register_process(pid, Some(limit));
-        }
-    }
-    
-    fn get_status(&self, pid: u64) -> Option<(TimeoutState, u64, u64)> {
-        self.processes.iter()
-            .find(|p| p.pid == pid)
-            .map(|p| {
-                let elapsed = self.current_tick.saturating_sub(p.start_time);
-                (p.state, elapsed, p.limit)
-            })
-    }
 }
 
+static GLOBAL_TICK_COUNTER: Mutex<u64> = Mutex::new(0);
+// 直前のタイマー割り込みでのTSCと、そこから測った1ティックあたりのTSCのカウント数
+static LAST_TICK_TSC: AtomicU64 = AtomicU64::new(0);
+static TSC_PER_TICK: AtomicU64 = AtomicU64::new(0);
+
 // PITを初期化してタイマー割り込みを開始
 pub fn init() {
     let divisor = PIT_FREQUENCY / TIMER_INTERVAL;
@@ -248,65 +82,57 @@ pub fn init() {
         data_port.write(((divisor >> 8) & 0xFF) as u8);
     }
     
-    println!("Timer initialized: {}Hz (default timeout: {} ticks)", 
-             TIMER_INTERVAL, DEFAULT_TIMEOUT_LIMIT);
+    println!("Timer initialized: {}Hz", TIMER_INTERVAL);
 }
 
 // タイマーティックをインクリメント（タイマー割り込みから呼ばれる）
+// CPU時間の課金は process::handle_switch で行う
 pub fn increment_tick() {
-    let mut manager = TIMEOUT_MANAGER.lock();
-    manager.increment_tick();
-    
-    // グローバルカウンタも更新（後方互換性のため）
     let mut global_counter = GLOBAL_TICK_COUNTER.lock();
-    *global_counter = manager.current_tick;
-}
-
-// 後方互換性のための関数（廃止予定）
-#[deprecated(note = "Use increment_tick() instead")]
-pub fn increment_timeout() {
-    increment_tick();
-}
+    *global_counter = global_counter.wrapping_add(1);
 
-// ユーザーモードを開始
-pub fn start_user_mode(pid: u64) {
-    let mut manager = TIMEOUT_MANAGER.lock();
-    manager.start_user_mode(pid);
+    // ティックの間隔でTSCの周波数を測る（ティック未満の時間の計測に使う）
+    let now = read_tsc();
+    let last = LAST_TICK_TSC.swap(now, Ordering::Relaxed);
+    if last != 0 && now > last {
+        TSC_PER_TICK.store(now - last, Ordering::Relaxed);
+    }
 }
 
-// ユーザーモードを終了
-pub fn end_user_mode() {
-    let mut manager = TIMEOUT_MANAGER.lock();
-    manager.end_user_mode();
+/// タイムスタンプカウンタ（TSC）を読む
+pub fn read_tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
 }
 
-// プロセスをタイムアウト管理に登録
-pub fn register_process(pid: u64, timeout_limit: Option<u64>) {
-    let mut manager = TIMEOUT_MANAGER.lock();
-    manager.register_process(pid, timeout_limit);
+/// TSCのカウント数をナノ秒に変換する
+///
+/// 周波数はタイマー割り込みの間隔から測る。まだ測れていなければ0。
+pub fn tsc_to_nanos(cycles: u64) -> u64 {
+    match TSC_PER_TICK.load(Ordering::Relaxed) {
+        0 => 0,
+        per_tick => (cycles as u128 * NANOS_PER_TICK as u128 / per_tick as u128) as u64,
+    }
 }
 
-// プロセスをタイムアウト管理から削除
-pub fn unregister_process(pid: u64) {
-    let mut manager = TIMEOUT_MANAGER.lock();
-    manager.unregister_process(pid);
+// 後方互換性のための関数（廃止予定）
+#[deprecated(note = "Use increment_tick() instead")]
+pub fn increment_timeout() {
+    increment_tick();
 }
 
-// プロセスのタイムアウト制限を設定
-pub fn set_timeout_limit(pid: u64, limit: u64) {
-    let mut manager = TIMEOUT_MANAGER.lock();
-    manager.set_timeout_limit(pid, limit);
+// グローバルティックカウンタを取得
+pub fn get_global_tick() -> u64 {
+    *GLOBAL_TICK_COUNTER.lock()
 }
 
-// プロセスのタイムアウト状態を取得
-pub fn get_timeout_status(pid: u64) -> Option<(TimeoutState, u64, u64)> {
-    let manager = TIMEOUT_MANAGER.lock();
-    manager.get_status(pid)
+/// 起動してからの経過時間（ナノ秒、ティック単位の精度）
+pub fn monotonic_nanos() -> u64 {
+    get_global_tick() * NANOS_PER_TICK
 }
 
-// グローバルティックカウンタを取得
-pub fn get_global_tick() -> u64 {
-    *GLOBAL_TICK_COUNTER.lock()
+/// ナノ秒をティック数に変換する（切り上げ）
+pub fn nanos_to_ticks(nanos: u64) -> u64 {
+    nanos.div_ceil(NANOS_PER_TICK)
 }
 
 // 後方互換性のための関数（廃止予定）
@@ -315,9 +141,56 @@ pub fn get_timeout_counter() -> u64 {
     get_global_tick()
 }
 
-// 後方互換性のための関数（廃止予定）
-#[deprecated(note = "Use register_process/end_user_mode instead")]
-pub fn reset_timeout() {
-    let mut manager = TIMEOUT_MANAGER.lock();
-    manager.end_user_mode();
+/// 時刻系システムコールハンドラ
+pub mod syscalls {
+    use super::*;
+    use crate::error::{Errno, KernelResult};
+    use crate::memory::uaccess;
+    use crate::process::scheduler::SCHEDULER;
+    use crate::syscall::{self, SyscallArgs};
+    use crate::syscall::table::{ArgKind, SyscallEntry, SyscallResult, SyscallTable};
+
+    /// 時刻系システムコールを登録
+    pub fn register_syscalls(table: &mut SyscallTable) -> KernelResult<()> {
+        table.register(SyscallEntry::new(syscall::SYS_NANOSLEEP, "nanosleep", sys_nanosleep,
+            &[ArgKind::UserPtr, ArgKind::UserPtr]))?;
+        table.register(SyscallEntry::new(syscall::SYS_CLOCK_GETTIME, "clock_gettime", sys_clock_gettime,
+            &[ArgKind::Flags, ArgKind::UserPtr]))?;
+        Ok(())
+    }
+
+    // nanosleep: 指定時間スリープする
+    // 引数: RDI=req, RSI=rem
+    // スリープキューに入れてブロックし、期限のティックでタイマー割り込みから起こされる。
+    // 起きたときは戻り値0でユーザー空間に戻る。シグナルで中断されたときはEINTRで戻り、
+    // remが0でなければ残り時間を書き込む。
+    fn sys_nanosleep(args: &SyscallArgs) -> SyscallResult {
+        let request: Timespec = uaccess::get_user(args.arg1)?;
+        let rem = args.arg2;
+        let nanos = request.to_nanos().ok_or(Errno::EINVAL)?;
+        if nanos == 0 {
+            return Ok(0);
+        }
+        // 書き込みは中断されて再開するときなので、先に検証しておく
+        if rem != 0 {
+            uaccess::access_ok(rem, core::mem::size_of::<Timespec>(), true)?;
+        }
+
+        let deadline = get_global_tick().saturating_add(nanos_to_ticks(nanos));
+        let current_pid = syscall::get_current_process_id();
+        SCHEDULER.lock().sleep_until(current_pid, deadline, rem)?;
+        Ok(0)
+    }
+
+    // clock_gettime: 時刻を取得する
+    // 引数: RDI=clock_id, RSI=tp
+    // 実時間時計（RTC）はまだないので、CLOCK_MONOTONICのみサポートする
+    fn sys_clock_gettime(args: &SyscallArgs) -> SyscallResult {
+        let now = match args.arg1 {
+            CLOCK_MONOTONIC => Timespec::from_nanos(monotonic_nanos()),
+            _ => return Err(Errno::EINVAL),
+        };
+        uaccess::put_user(args.arg2, &now)?;
+        Ok(0)
+    }
 }